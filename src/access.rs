@@ -0,0 +1,317 @@
+use crate::config::{BindingType, Configuration};
+use crate::device::DeviceDescriptor;
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Where lg600r creates virtual devices, to pass keys and pointer events through
+pub const UINPUT: &str = "/dev/uinput";
+
+/// Where processes' open files are listed, to find whatever has grabbed a device
+const PROC: &str = "/proc";
+
+/// Where udev rules go; the `uaccess` tag only takes effect in rules sorted before 73-seat-late
+pub const RULES_PATH: &str = "/etc/udev/rules.d/70-lg600r.rules";
+
+/// Whose permission bits apply to a process opening a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Owner,
+    Group,
+    Other,
+}
+
+/// Explains why a device node couldn't be opened, and what would let it be
+pub fn explain(node: &Path, writing: bool, error: &io::Error) -> String {
+    let purpose = if writing { "writing" } else { "reading" };
+    let opened = format!(
+        "Couldn't open {} for {}: {}",
+        node.display(),
+        purpose,
+        error
+    );
+    match error.kind() {
+        io::ErrorKind::NotFound if node == Path::new(UINPUT) => format!(
+            "{}; the uinput module may need loading, with `modprobe uinput`",
+            opened
+        ),
+        io::ErrorKind::NotFound => format!("{}; it may have been unplugged", opened),
+        io::ErrorKind::PermissionDenied => match node.metadata() {
+            Ok(metadata) => {
+                let (owner, group) = (user_name(metadata.uid()), group_name(metadata.gid()));
+                let class = if metadata.uid() == unsafe { libc::geteuid() } {
+                    Class::Owner
+                } else if in_group(metadata.gid()) {
+                    Class::Group
+                } else {
+                    Class::Other
+                };
+                format!(
+                    "{}; {}",
+                    opened,
+                    denial(metadata.mode(), &owner, &group, class, writing)
+                )
+            }
+            Err(e) => format!("{}, and it can't be looked at either: {}", opened, e),
+        },
+        _ => opened,
+    }
+}
+
+/// Explains why a device node couldn't be grabbed, naming what else has it open if it's busy,
+/// as one of those will have grabbed it
+pub fn explain_grab(node: &Path, error: &io::Error) -> String {
+    let grabbing = format!("Couldn't grab {}: {}", node.display(), error);
+    if error.raw_os_error() != Some(libc::EBUSY) {
+        return grabbing;
+    }
+    let holders = holders(Path::new(PROC), node)
+        .iter()
+        .map(|(pid, name)| format!("{} (pid {})", name, pid))
+        .collect::<Vec<_>>();
+    if holders.is_empty() {
+        format!(
+            "{}; another process has grabbed it, though it can't be told which, as it may \
+             belong to another user",
+            grabbing
+        )
+    } else {
+        format!(
+            "{}; it's open in {}, which will have grabbed it",
+            grabbing,
+            holders.join(", ")
+        )
+    }
+}
+
+/// The processes other than this one with a node open, by pid and name, as far as can be seen
+fn holders(proc_root: &Path, node: &Path) -> Vec<(u32, String)> {
+    let node = node.canonicalize().unwrap_or_else(|_| node.to_path_buf());
+    let entries = match proc_root.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut holders = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            if pid == std::process::id() {
+                return None;
+            }
+            // Other users' descriptors can't be read, so their processes go unnoticed
+            let holds = entry
+                .path()
+                .join("fd")
+                .read_dir()
+                .ok()?
+                .filter_map(Result::ok)
+                .any(|fd| fd.path().read_link().ok().as_ref() == Some(&node));
+            if !holds {
+                return None;
+            }
+            let name = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            Some((pid, name.trim().to_string()))
+        })
+        .collect::<Vec<_>>();
+    holders.sort();
+    holders
+}
+
+/// Why a node with these permissions was off limits, given which of its classes applies
+fn denial(mode: u32, owner: &str, group: &str, class: Class, writing: bool) -> String {
+    let needed = if writing { 0o6 } else { 0o4 };
+    let (shift, who) = match class {
+        Class::Owner => (6, "its owner".to_string()),
+        Class::Group => (3, format!("the \"{}\" group", group)),
+        Class::Other => (0, format!("anyone outside the \"{}\" group", group)),
+    };
+    let described = format!(
+        "it's owned by {}:{} with mode {:04o}",
+        owner,
+        group,
+        mode & 0o7777
+    );
+    if (mode >> shift) & needed == needed {
+        // The mode allows it, so an ACL, a security module or a container must be in the way
+        return format!(
+            "{}, which allows {}, so something else denies it, like a security module or a container",
+            described, who
+        );
+    }
+    format!(
+        "{}, which doesn't allow {} to {} it; `lg600r setup udev` prints a rule granting access \
+         to whoever is logged in at the seat",
+        described,
+        who,
+        if writing { "write" } else { "read" }
+    )
+}
+
+fn user_name(uid: u32) -> String {
+    let entry = unsafe { libc::getpwuid(uid) };
+    if entry.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr((*entry).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn group_name(gid: u32) -> String {
+    let entry = unsafe { libc::getgrgid(gid) };
+    if entry.is_null() {
+        return gid.to_string();
+    }
+    unsafe { CStr::from_ptr((*entry).gr_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether this process has the group, as its own or a supplementary one
+fn in_group(gid: u32) -> bool {
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.contains(&gid)
+}
+
+/// Whether a configuration has lg600r create virtual devices, which needs access to uinput
+pub fn needs_uinput(config: &Configuration) -> bool {
+    let passes = |bindings: &[(u32, BindingType)]| {
+        bindings
+            .iter()
+            .any(|(_, binding)| *binding == BindingType::Passthrough)
+    };
+    config.keyboard.as_ref().map(|k| k.passthrough) == Some(true)
+        || config.pointer.as_ref().map(|p| p.grab) == Some(true)
+        || passes(&config.bindings)
+        || config
+            .device_keymaps
+            .iter()
+            .chain(&config.profiles)
+            .any(|(_, keymap)| passes(&keymap.bindings))
+}
+
+/// udev rules giving whoever is logged in at the seat access to these devices' event and
+/// hidraw nodes, and to uinput if it's needed, rather than to everyone
+pub fn udev_rules(devices: &[DeviceDescriptor], uinput: bool) -> String {
+    let mut rules = vec![
+        "# Generated by `lg600r setup udev`; install as ".to_string() + RULES_PATH + ",",
+        "# then run `udevadm control --reload && udevadm trigger`.".to_string(),
+        "# The uaccess tag takes effect with systemd-logind or elogind; without either, use"
+            .to_string(),
+        "# GROUP=\"input\", MODE=\"0660\" instead, and add yourself to the input group."
+            .to_string(),
+    ];
+    for device in devices {
+        let matched = match (device.vendor, device.product) {
+            (Some(vendor), Some(product)) => format!(
+                "ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\"",
+                vendor, product
+            ),
+            _ => {
+                // Matching on the vendor alone would open up the rest of its devices too, so
+                // without a product, it's matched by the USB serial udev gives its event nodes
+                let serial = device.prefix.trim_start_matches("usb-");
+                rules.push(String::new());
+                rules.push(format!("# {}", device.title));
+                rules.push(format!(
+                    "SUBSYSTEM==\"input\", KERNEL==\"event*\", ENV{{ID_SERIAL}}==\"{}*\", TAG+=\"uaccess\"",
+                    serial
+                ));
+                continue;
+            }
+        };
+        rules.push(String::new());
+        rules.push(format!("# {}", device.title));
+        rules.push(format!(
+            "SUBSYSTEM==\"input\", KERNEL==\"event*\", {}, TAG+=\"uaccess\"",
+            matched
+        ));
+        rules.push(format!(
+            "SUBSYSTEM==\"hidraw\", {}, TAG+=\"uaccess\"",
+            matched
+        ));
+    }
+    if uinput {
+        rules.push(String::new());
+        rules.push("# For passing keys and pointer events through virtual devices".to_string());
+        rules.push(
+            "KERNEL==\"uinput\", SUBSYSTEM==\"misc\", OPTIONS+=\"static_node=uinput\", TAG+=\"uaccess\""
+                .to_string(),
+        );
+    }
+    rules.join("\n") + "\n"
+}
+
+#[test]
+fn test_explain_denials() {
+    assert_eq!(
+        denial(0o20660, "root", "input", Class::Other, false),
+        "it's owned by root:input with mode 0660, which doesn't allow anyone outside the \
+         \"input\" group to read it; `lg600r setup udev` prints a rule granting access to \
+         whoever is logged in at the seat"
+    );
+    assert!(denial(0o640, "root", "input", Class::Group, true)
+        .contains("allow the \"input\" group to write"));
+    assert!(
+        denial(0o666, "root", "root", Class::Other, true).contains("so something else denies it")
+    );
+
+    let missing = io::Error::from(io::ErrorKind::NotFound);
+    assert!(explain(Path::new(UINPUT), true, &missing).contains("modprobe uinput"));
+}
+
+#[test]
+fn test_find_what_holds_a_node() {
+    use std::os::unix::fs::symlink;
+    let root = std::env::temp_dir().join(format!("lg600r-proc-{}", std::process::id()));
+    let node = root.join("event5");
+    let process = |pid: u32, name: &str, fds: &[&Path]| {
+        let dir = root.join(pid.to_string());
+        std::fs::create_dir_all(dir.join("fd")).unwrap();
+        std::fs::write(dir.join("comm"), format!("{}\n", name)).unwrap();
+        for (fd, target) in fds.iter().enumerate() {
+            symlink(target, dir.join("fd").join(fd.to_string())).unwrap();
+        }
+    };
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(&node, "").unwrap();
+    process(40, "remapper", &[Path::new("/dev/null"), &node]);
+    process(41, "bash", &[Path::new("/dev/null")]);
+    process(std::process::id(), "lg600r", &[&node]);
+    std::fs::create_dir_all(root.join("self")).unwrap();
+    let found = holders(&root, &node);
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(found, vec![(40, "remapper".to_string())]);
+
+    let busy = io::Error::from_raw_os_error(libc::EBUSY);
+    assert!(explain_grab(Path::new("/nonexistent/event5"), &busy).contains("can't be told which"));
+}
+
+#[test]
+fn test_generate_udev_rules() {
+    let devices = crate::device::builtin();
+    let rules = udev_rules(&devices[..1], false);
+    assert!(rules.contains(
+        "SUBSYSTEM==\"input\", KERNEL==\"event*\", ATTRS{idVendor}==\"046d\", \
+         ATTRS{idProduct}==\"c24a\", TAG+=\"uaccess\"\n"
+    ));
+    assert!(rules.contains("SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"046d\""));
+    assert!(!rules.contains("uinput\""));
+    assert!(rules
+        .lines()
+        .all(|line| line.starts_with('#') || !line.contains("MODE")));
+    let naga = udev_rules(&devices[3..], true);
+    assert!(naga.contains(
+        "SUBSYSTEM==\"input\", KERNEL==\"event*\", ENV{ID_SERIAL}==\"Razer_Razer_Naga*\", \
+         TAG+=\"uaccess\"\n"
+    ));
+    assert!(!naga.contains("idVendor"));
+    assert!(naga.contains("KERNEL==\"uinput\""));
+}
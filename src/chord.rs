@@ -0,0 +1,139 @@
+use crate::config::gkey::{format_gkey, parse_gkey};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Splits the device a key is written on, like `pad.G1`, from the G-key's own name
+pub fn split_device(key: &str) -> (Option<String>, &str) {
+    // `shift.G9` is a G-key of its own, rather than G9 on a device called "shift"
+    match key.find('.') {
+        Some(dot) if !key[..dot].eq_ignore_ascii_case("shift") => {
+            (Some(key[..dot].to_string()), &key[dot + 1..])
+        }
+        _ => (None, key),
+    }
+}
+
+/// G-keys held together, perhaps on different devices, written like `G9 + pad.G1`.
+///
+/// Each key is on the named device, or on the first device driven if it names none.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chord(pub Vec<(Option<String>, u32)>);
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = self
+            .0
+            .iter()
+            .map(|(device, gkey)| match device {
+                Some(device) => format!("{}.{}", device, format_gkey(*gkey)),
+                None => format_gkey(*gkey),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", keys.join(" + "))
+    }
+}
+
+impl ::std::str::FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Chord, String> {
+        let mut keys = Vec::new();
+        for key in s.split('+').map(str::trim) {
+            let (device, name) = split_device(key);
+            let gkey = parse_gkey(name).map_err(|e| format!("In chord \"{}\": {}", s, e))?;
+            keys.push((device, gkey));
+        }
+        keys.sort();
+        keys.dedup();
+        if keys.len() < 2 {
+            return Err(format!(
+                "Chord \"{}\" must be at least two different G-keys",
+                s
+            ));
+        }
+        Ok(Chord(keys))
+    }
+}
+
+/// Follows which G-keys are held on each device, by index, to tell when a chord is completed.
+///
+/// The keys pressed before the last still do whatever they're bound to; the last one
+/// completes the chord instead, and its release is swallowed along with its press.
+pub struct Tracker<T> {
+    chords: Vec<(BTreeSet<(usize, u32)>, T)>,
+    held: BTreeSet<(usize, u32)>,
+    /// Keys which completed a chord and haven't been released since
+    completing: BTreeSet<(usize, u32)>,
+}
+
+impl<T> Tracker<T> {
+    pub fn new(chords: Vec<(BTreeSet<(usize, u32)>, T)>) -> Tracker<T> {
+        Tracker {
+            chords,
+            held: BTreeSet::new(),
+            completing: BTreeSet::new(),
+        }
+    }
+
+    /// Replaces the chords, as on reloading, while remembering which keys are held
+    pub fn set_chords(&mut self, chords: Vec<(BTreeSet<(usize, u32)>, T)>) {
+        self.chords = chords;
+    }
+
+    /// Notes a press, returning what's bound to the chord it completes, if any
+    pub fn press(&mut self, key: (usize, u32)) -> Option<&T> {
+        self.held.insert(key);
+        let held = &self.held;
+        let completed = self
+            .chords
+            .iter()
+            .find(|(keys, _)| keys.contains(&key) && keys.is_subset(held))
+            .map(|(_, bound)| bound);
+        if completed.is_some() {
+            self.completing.insert(key);
+        }
+        completed
+    }
+
+    /// Notes a release, returning whether it's swallowed for having completed a chord
+    pub fn release(&mut self, key: (usize, u32)) -> bool {
+        self.held.remove(&key);
+        self.completing.remove(&key)
+    }
+}
+
+#[test]
+fn test_parse_chords() {
+    use std::str::FromStr;
+    let chord = Chord::from_str("pad.G1 + G9").unwrap();
+    assert_eq!(chord, Chord(vec![(None, 9), (Some("pad".to_string()), 1)]));
+    assert_eq!(chord.to_string(), "G9 + pad.G1");
+    assert_eq!(
+        Chord::from_str("shift.G9+pad.shift.left").map(|c| c.to_string()),
+        Ok("G^9 + pad.G^1".to_string())
+    );
+    assert!(Chord::from_str("G9").is_err());
+    assert!(Chord::from_str("G9 + 9").is_err());
+    assert!(Chord::from_str("G9 + pad.thumb").is_err());
+}
+
+#[test]
+fn test_track_chords() {
+    let keys = |keys: &[(usize, u32)]| keys.iter().cloned().collect::<BTreeSet<_>>();
+    let mut tracker = Tracker::new(vec![
+        (keys(&[(0, 9), (1, 1)]), "across"),
+        (keys(&[(0, 9), (0, 10)]), "together"),
+    ]);
+    assert_eq!(tracker.press((1, 1)), None);
+    assert_eq!(tracker.press((0, 9)), Some(&"across"));
+    assert!(!tracker.release((1, 1)));
+    assert!(tracker.release((0, 9)));
+
+    assert_eq!(tracker.press((0, 10)), None);
+    assert_eq!(tracker.press((1, 1)), None);
+    assert_eq!(tracker.press((0, 9)), Some(&"across"));
+    assert!(!tracker.release((0, 10)));
+    assert!(!tracker.release((1, 1)));
+    assert!(tracker.release((0, 9)));
+    assert_eq!(tracker.press((0, 9)), None);
+}
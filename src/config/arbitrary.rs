@@ -0,0 +1,383 @@
+//! Arbitrary configurations for property tests, and the parts of them that must survive being
+//! serialized and parsed again
+
+use super::*;
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+
+fn gkey() -> impl Strategy<Value = u32> {
+    prop_oneof![1u32..100, 101u32..200]
+}
+
+/// Any text, or text using environment variables, which are kept as written until used
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![".*", r"([a-z/ ]|\$\$|\$[A-Z_]+|\$\{[A-Z_]+\})*"]
+}
+
+pub fn binding() -> impl Strategy<Value = BindingType> {
+    prop_oneof![
+        text().prop_map(BindingType::Command),
+        (1u8..=255).prop_map(BindingType::EmulateMouse),
+        key_combo().prop_map(BindingType::EmulateKey),
+        ".*".prop_map(BindingType::KeySequence),
+        exec_spec().prop_map(BindingType::Exec),
+        text().prop_map(BindingType::I3),
+        volume_action().prop_map(BindingType::Volume),
+        media_action().prop_map(BindingType::Media),
+        (
+            prop_oneof![Just(ScrollAxis::Vertical), Just(ScrollAxis::Horizontal)],
+            any::<i32>(),
+            proptest::option::of(1u32..),
+        )
+            .prop_map(|(axis, amount, repeat_ms)| BindingType::Scroll {
+                axis,
+                amount,
+                repeat_ms,
+            }),
+        (any::<i32>(), any::<i32>(), any::<bool>(), any::<u32>()).prop_map(
+            |(x, y, relative, screen)| BindingType::MovePointer {
+                x,
+                y,
+                relative,
+                screen: if relative { 0 } else { screen },
+            }
+        ),
+        (1u8..=255, 1u32..).prop_map(|(button, count)| BindingType::Click { button, count }),
+        Just(BindingType::Passthrough),
+        proptest::option::of(".*").prop_map(BindingType::Profile),
+    ]
+}
+
+fn media_action() -> impl Strategy<Value = mpris::MediaAction> {
+    let command = prop_oneof![
+        Just(mpris::MediaCommand::PlayPause),
+        Just(mpris::MediaCommand::Play),
+        Just(mpris::MediaCommand::Pause),
+        Just(mpris::MediaCommand::Stop),
+        Just(mpris::MediaCommand::Next),
+        Just(mpris::MediaCommand::Previous),
+        any::<i64>().prop_map(mpris::MediaCommand::Seek),
+        Just(mpris::MediaCommand::Raise),
+    ];
+    (command, proptest::option::of(text()))
+        .prop_map(|(command, player)| mpris::MediaAction { command, player })
+}
+
+fn volume_action() -> impl Strategy<Value = volume::VolumeAction> {
+    let operation = prop_oneof![
+        (-100i32..=100).prop_map(volume::VolumeOperation::Step),
+        (0u32..=100).prop_map(volume::VolumeOperation::Set),
+        proptest::option::of(any::<bool>()).prop_map(volume::VolumeOperation::Mute),
+    ];
+    let device = prop_oneof![
+        text().prop_map(volume::VolumeDevice::Sink),
+        text().prop_map(volume::VolumeDevice::Source),
+    ];
+    (
+        operation,
+        device,
+        proptest::option::of(1u32..),
+        proptest::option::of(text()),
+    )
+        .prop_map(
+            |(operation, device, repeat_ms, fallback)| volume::VolumeAction {
+                operation,
+                device,
+                repeat_ms,
+                fallback,
+            },
+        )
+}
+
+fn key_combo() -> impl Strategy<Value = xdo::KeyCombo> {
+    let key = prop_oneof![
+        proptest::sample::select(xdo::Key::NAMES),
+        proptest::sample::select(xdo::keysyms::KEYSYMS).prop_map(|(name, _)| name),
+    ];
+    let modifier = proptest::sample::select(vec![
+        xdo::Key::Control,
+        xdo::Key::Alt,
+        xdo::Key::Shift,
+        xdo::Key::SuperL,
+        xdo::Key::HyperR,
+    ]);
+    (proptest::collection::vec(modifier, 0..3), key).prop_map(|(modifiers, name)| xdo::KeyCombo {
+        modifiers,
+        key: name.parse().unwrap(),
+    })
+}
+
+fn exec_spec() -> impl Strategy<Value = exec::ExecSpec> {
+    let target = prop_oneof![
+        proptest::collection::vec(text(), 1..4).prop_map(exec::ExecTarget::Argv),
+        (".*", text()).prop_map(|(shell, command)| exec::ExecTarget::Shell { shell, command }),
+        (".*", proptest::collection::vec(text(), 1..4))
+            .prop_map(|(shell, argv)| exec::ExecTarget::ShellArgv { shell, argv }),
+    ];
+    (
+        target,
+        proptest::collection::btree_map(".*", text(), 0..3),
+        proptest::option::of(prop_oneof!["/[a-z/]*", "~/[a-z/]*", text()]),
+        any::<bool>(),
+    )
+        .prop_map(|(target, env, cwd, detach)| exec::ExecSpec {
+            target,
+            env,
+            cwd,
+            detach,
+        })
+}
+
+fn notification_settings() -> impl Strategy<Value = notify::NotificationSettings> {
+    (any::<bool>(), any::<bool>(), any::<bool>(), any::<u32>()).prop_map(
+        |(enabled, errors, states, per_minute)| notify::NotificationSettings {
+            enabled,
+            errors,
+            states,
+            per_minute,
+        },
+    )
+}
+
+fn drawn_gesture() -> impl Strategy<Value = gesture::Gesture> {
+    use gesture::Direction::*;
+    proptest::collection::vec(
+        prop_oneof![Just(Up), Just(Down), Just(Left), Just(Right)],
+        1..6,
+    )
+    .prop_map(|mut strokes| {
+        strokes.dedup();
+        gesture::Gesture(strokes)
+    })
+}
+
+fn gesture_settings() -> impl Strategy<Value = GestureSettings> {
+    (
+        gkey(),
+        1u32..500,
+        proptest::collection::btree_map(drawn_gesture(), binding(), 0..8),
+    )
+        .prop_map(|(trigger, threshold, bindings)| GestureSettings {
+            trigger,
+            threshold,
+            bindings: bindings.into_iter().collect(),
+        })
+}
+
+fn device_descriptor() -> impl Strategy<Value = device::DeviceDescriptor> {
+    let button = 1u32..100;
+    (
+        // Never the name of a built-in, which would be described by what differs from it
+        ("x[a-z0-9]{0,7}", proptest::option::of(".*")),
+        proptest::option::of(any::<u16>()),
+        proptest::option::of(any::<u16>()),
+        (".*", ".*", proptest::option::of(".*")),
+        proptest::option::of(any::<u32>()),
+        proptest::collection::btree_map(button.clone(), ".*", 0..4),
+        proptest::collection::vec(proptest::collection::vec(button, 1..4), 0..4),
+    )
+        .prop_map(
+            |((name, title), vendor, product, interfaces, scancode_mask, buttons, layout)| {
+                let (prefix, keyboard, pointer) = interfaces;
+                device::DeviceDescriptor {
+                    title: title.unwrap_or_else(|| name.clone()),
+                    name,
+                    vendor,
+                    product,
+                    prefix,
+                    keyboard,
+                    pointer,
+                    scancode_mask,
+                    buttons,
+                    layout,
+                }
+            },
+        )
+}
+
+fn selector() -> impl Strategy<Value = device::Selector> {
+    (
+        proptest::option::of("x[a-z0-9]{0,7}"),
+        proptest::option::of(".*"),
+        proptest::option::of(".*"),
+    )
+        .prop_map(|(name, serial, phys)| device::Selector { name, serial, phys })
+}
+
+fn keymap() -> impl Strategy<Value = DeviceKeymap> {
+    (
+        proptest::collection::btree_map(gkey(), (binding(), any::<bool>()), 0..6),
+        proptest::collection::btree_map(gkey(), any::<u32>(), 0..6),
+    )
+        .prop_map(|(bindings, scancodes)| DeviceKeymap {
+            notify: bindings
+                .iter()
+                .filter(|(_, (_, notify))| *notify)
+                .map(|(gkey, _)| *gkey)
+                .collect(),
+            bindings: bindings
+                .into_iter()
+                .map(|(gkey, (binding, _))| (gkey, binding))
+                .collect(),
+            scancodes: scancodes.into_iter().collect(),
+        })
+}
+
+fn chord() -> impl Strategy<Value = chord::Chord> {
+    proptest::collection::vec((proptest::option::of("x[a-z0-9]{0,3}"), gkey()), 2..4)
+        .prop_filter_map("fewer than two different keys", |mut keys| {
+            keys.sort();
+            keys.dedup();
+            if keys.len() < 2 {
+                None
+            } else {
+                Some(chord::Chord(keys))
+            }
+        })
+}
+
+fn grab_mode() -> impl Strategy<Value = keyboard_watcher::GrabMode> {
+    use keyboard_watcher::GrabMode;
+    prop_oneof![
+        Just(GrabMode::Always),
+        Just(GrabMode::Never),
+        ".*".prop_map(GrabMode::Profile),
+    ]
+}
+
+pub fn configuration() -> impl Strategy<Value = Configuration> {
+    let settings = (
+        proptest::option::of(notification_settings()),
+        proptest::option::of(gesture_settings()),
+        proptest::option::of(any::<bool>()),
+        proptest::option::of((any::<bool>(), grab_mode())),
+    );
+    let devices = (
+        proptest::collection::vec(selector(), 0..3),
+        proptest::collection::vec(device_descriptor(), 0..3),
+        proptest::collection::btree_map("x[a-z0-9]{0,7}", keymap(), 0..3),
+    );
+    let chords = (
+        proptest::collection::btree_map(chord(), binding(), 0..4),
+        proptest::option::of(any::<bool>()),
+        proptest::collection::btree_map("x[a-z0-9]{0,7}", keymap(), 0..3),
+    );
+    (
+        proptest::collection::btree_map(gkey(), (binding(), any::<bool>()), 0..24),
+        proptest::collection::btree_map(gkey(), any::<u32>(), 0..24),
+        settings,
+        devices,
+        chords,
+    )
+        .prop_map(
+            |(bindings, scancodes, settings, (device, devices, keymaps), chords)| {
+                let (notifications, gestures, grab, keyboard) = settings;
+                let (chords, shared, profiles) = chords;
+                // Each device is described once, under its name
+                let devices = devices
+                    .into_iter()
+                    .map(|descriptor| (descriptor.name.clone(), descriptor))
+                    .collect::<BTreeMap<_, _>>();
+                Configuration {
+                    notify: bindings
+                        .iter()
+                        .filter(|(_, (_, notify))| *notify)
+                        .map(|(gkey, _)| *gkey)
+                        .collect(),
+                    bindings: bindings
+                        .into_iter()
+                        .map(|(gkey, (binding, _))| (gkey, binding))
+                        .collect(),
+                    scancodes: scancodes.into_iter().collect(),
+                    notifications,
+                    gestures,
+                    pointer: grab.map(|grab| pointer_watcher::PointerSettings { grab }),
+                    keyboard: keyboard.map(|(passthrough, grab)| {
+                        keyboard_watcher::KeyboardSettings { passthrough, grab }
+                    }),
+                    device,
+                    devices: devices.into_values().collect(),
+                    device_keymaps: keymaps.into_iter().collect(),
+                    chords: chords.into_iter().collect(),
+                    layers: shared.map(|shared| LayerSettings { shared }),
+                    profiles: profiles.into_iter().collect(),
+                }
+            },
+        )
+}
+
+/// The parts of a configuration that must survive a roundtrip, in a comparable form
+pub type Normalized<'a> = (
+    BTreeMap<u32, BindingType>,
+    BTreeMap<u32, u32>,
+    &'a std::collections::BTreeSet<u32>,
+    &'a Option<notify::NotificationSettings>,
+    Option<(u32, u32, BTreeMap<gesture::Gesture, BindingType>)>,
+    &'a Option<pointer_watcher::PointerSettings>,
+    &'a Option<keyboard_watcher::KeyboardSettings>,
+    &'a Vec<device::Selector>,
+    BTreeMap<&'a str, &'a device::DeviceDescriptor>,
+    // Devices' keymaps, then profiles', which may be empty
+    (
+        BTreeMap<&'a str, NormalizedKeymap<'a>>,
+        BTreeMap<&'a str, NormalizedKeymap<'a>>,
+    ),
+    BTreeMap<&'a chord::Chord, &'a BindingType>,
+    &'a Option<LayerSettings>,
+);
+pub type NormalizedKeymap<'a> = (
+    BTreeMap<u32, BindingType>,
+    BTreeMap<u32, u32>,
+    &'a std::collections::BTreeSet<u32>,
+);
+
+fn normalized_keymap(keymap: &DeviceKeymap) -> NormalizedKeymap<'_> {
+    (
+        keymap.bindings.iter().cloned().collect(),
+        keymap.scancodes.iter().cloned().collect(),
+        &keymap.notify,
+    )
+}
+
+pub fn normalized(config: &Configuration) -> Normalized<'_> {
+    (
+        config.bindings.iter().cloned().collect(),
+        config.scancodes.iter().cloned().collect(),
+        &config.notify,
+        &config.notifications,
+        config.gestures.as_ref().map(|gestures| {
+            (
+                gestures.trigger,
+                gestures.threshold,
+                gestures.bindings.iter().cloned().collect(),
+            )
+        }),
+        &config.pointer,
+        &config.keyboard,
+        &config.device,
+        config
+            .devices
+            .iter()
+            .map(|descriptor| (descriptor.name.as_str(), descriptor))
+            .collect(),
+        (
+            config
+                .device_keymaps
+                .iter()
+                .filter(|(_, keymap)| !keymap.is_empty())
+                .map(|(name, keymap)| (name.as_str(), normalized_keymap(keymap)))
+                .collect(),
+            config
+                .profiles
+                .iter()
+                .map(|(name, keymap)| (name.as_str(), normalized_keymap(keymap)))
+                .collect(),
+        ),
+        config
+            .chords
+            .iter()
+            .map(|(chord, binding)| (chord, binding))
+            .collect(),
+        &config.layers,
+    )
+}
@@ -0,0 +1,983 @@
+use super::{
+    binding_entries, chord, device, gkey, keyboard_watcher, parse_binding, parse_device,
+    parse_device_selection, parse_gesture_binding, parse_keymap, parse_scancode, parse_trigger,
+    BindingType, Configuration, IntermedConfig, CONFIG_KEYS,
+};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based line and column within the config file
+    pub location: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    fn error(message: String, location: Option<(usize, usize)>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            location,
+        }
+    }
+
+    fn warning(message: String, location: Option<(usize, usize)>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            location,
+        }
+    }
+
+    pub fn render(&self, path: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.location {
+            Some((line, column)) => format!(
+                "{}: {}\n  --> {}:{}:{}",
+                severity, self.message, path, line, column
+            ),
+            None => format!("{}: {}\n  --> {}", severity, self.message, path),
+        }
+    }
+}
+
+/// Suggests the candidate closest to a misspelt `name`, if any is plausibly what was meant
+pub fn did_you_mean<'a, I: Iterator<Item = &'a str>>(name: &str, candidates: I) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    let threshold = ::std::cmp::max(1, name.chars().count() / 3);
+    candidates
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Finds where `key` is assigned within `[table]`, as a 1-based line and column
+fn locate(source: &str, table: &str, key: &str) -> Option<(usize, usize)> {
+    let candidates = [
+        key.to_string(),
+        format!("\"{}\"", key),
+        format!("'{}'", key),
+    ];
+    // Keys before the first header belong to the top-level table, named ""
+    let mut in_table = table.is_empty();
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            let header = trimmed.trim_start_matches('[').split(']').next();
+            in_table = header.map(str::trim) == Some(table);
+            continue;
+        }
+        if !in_table {
+            continue;
+        }
+        for candidate in &candidates {
+            if trimmed.starts_with(candidate.as_str())
+                && trimmed[candidate.len()..].trim_start().starts_with('=')
+            {
+                return Some((index + 1, line.len() - trimmed.len() + 1));
+            }
+        }
+    }
+    // `shift.G9` may equally have been written as `G9` under a `[table.shift]` header
+    if key.starts_with("shift.") {
+        return locate(source, &format!("{}.shift", table), &key["shift.".len()..]);
+    }
+    None
+}
+
+/// Finds where `[table]` begins, as a 1-based line and column
+fn locate_table(source: &str, table: &str) -> Option<(usize, usize)> {
+    source.lines().enumerate().find_map(|(index, line)| {
+        let trimmed = line.trim_start();
+        let header = trimmed.strip_prefix('[')?.split(']').next()?;
+        if header.trim() == table {
+            Some((index + 1, line.len() - trimmed.len() + 1))
+        } else {
+            None
+        }
+    })
+}
+
+/// Finds where a top-level key is assigned, or where the first table under it begins, as a
+/// 1-based line and column
+fn locate_top_level(source: &str, key: &str) -> Option<(usize, usize)> {
+    locate(source, "", key).or_else(|| {
+        source.lines().enumerate().find_map(|(index, line)| {
+            let trimmed = line.trim_start();
+            let header = trimmed.strip_prefix('[')?.trim_start_matches('[');
+            let first = header.split(&['.', ']'][..]).next()?;
+            if first.trim() == key {
+                Some((index + 1, line.len() - trimmed.len() + 1))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// Reports the keys at the top level of a config file that lg600r doesn't know of
+fn unknown_keys(source: &str, table: &toml::value::Table) -> Vec<Diagnostic> {
+    table
+        .iter()
+        .filter(|(key, _)| !CONFIG_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| {
+            let kind = if value.is_table() { "table" } else { "key" };
+            let suggestion = did_you_mean(key, CONFIG_KEYS.iter().cloned())
+                .map(|s| format!("; did you mean \"{}\"?", s))
+                .unwrap_or_default();
+            Diagnostic::error(
+                format!("Unknown {} \"{}\"{}", kind, key, suggestion),
+                locate_top_level(source, key),
+            )
+        })
+        .collect()
+}
+
+fn syntax_error(e: toml::de::Error) -> Diagnostic {
+    let location = e.line_col().map(|(line, column)| (line + 1, column + 1));
+    Diagnostic::error(e.to_string(), location)
+}
+
+/// Resolves each entry's G-key, reporting unknown and duplicated G-keys
+fn resolve_entries<T>(
+    source: &str,
+    table: &str,
+    entries: Vec<(String, T)>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<(String, u32, T)> {
+    let mut seen = BTreeMap::<u32, String>::new();
+    let mut resolved = Vec::new();
+    for (name, value) in entries {
+        let location = locate(source, table, &name);
+        match gkey::parse_gkey(&name) {
+            Err(e) => diagnostics.push(Diagnostic::error(e, location)),
+            Ok(gkey) => {
+                if let Some(previous) = seen.insert(gkey, name.clone()) {
+                    diagnostics.push(Diagnostic::error(
+                        format!(
+                            "\"{}\" and \"{}\" both refer to {}",
+                            previous,
+                            name,
+                            gkey::format_gkey(gkey)
+                        ),
+                        location,
+                    ));
+                } else {
+                    resolved.push((name, gkey, value));
+                }
+            }
+        }
+    }
+    resolved
+}
+
+/// Reports a failure to load a config file's includes
+pub fn include_error(source: &str, message: String) -> Diagnostic {
+    Diagnostic::error(message, locate(source, "", "include"))
+}
+
+/// Validates a config file's contents beyond what's needed to load it
+pub fn check_config(source: &str) -> Vec<Diagnostic> {
+    check_config_with(source, &Configuration::default())
+}
+
+/// Validates a config file's contents, given the configuration it inherits from its includes
+pub fn check_config_with(source: &str, inherited: &Configuration) -> Vec<Diagnostic> {
+    // Unknown keys are reported on their own, as nothing more can be read while they're there
+    let unknown = match toml::from_str(source) {
+        Ok(table) => unknown_keys(source, &table),
+        Err(e) => return vec![syntax_error(e)],
+    };
+    if !unknown.is_empty() {
+        return unknown;
+    }
+    let icfg: IntermedConfig = match toml::from_str(source) {
+        Ok(icfg) => icfg,
+        Err(e) => return vec![syntax_error(e)],
+    };
+    let mut diagnostics = Vec::new();
+
+    let local_scancodes = resolve_entries(
+        source,
+        "scancodes",
+        icfg.scancode_entries(),
+        &mut diagnostics,
+    );
+    let mut scancodes = BTreeMap::<u32, u32>::new();
+    let mut gkeys_by_scancode = BTreeMap::<u32, (u32, String)>::new();
+    for &(gkey, scancode) in &inherited.scancodes {
+        if local_scancodes.iter().all(|(_, local, _)| *local != gkey) {
+            let name = format!("{} (included)", gkey::format_gkey(gkey));
+            scancodes.insert(gkey, scancode);
+            gkeys_by_scancode.insert(scancode, (gkey, name));
+        }
+    }
+    for (name, gkey, value) in local_scancodes {
+        let location = locate(source, "scancodes", &name);
+        match (parse_scancode(value), value) {
+            (None, serde_value::Value::String(name)) if name.contains('_') => {
+                let names = crate::keycodes::KEYCODES.iter().map(|(name, _)| *name);
+                let suggestion = did_you_mean(name, names)
+                    .map(|s| format!("; did you mean \"{}\"?", s))
+                    .unwrap_or_default();
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "Unknown key \"{}\" for {}{}",
+                        name,
+                        gkey::format_gkey(gkey),
+                        suggestion
+                    ),
+                    location,
+                ))
+            }
+            (None, _) => diagnostics.push(Diagnostic::error(
+                format!(
+                    "Invalid scancode {:?} for {}",
+                    value,
+                    gkey::format_gkey(gkey)
+                ),
+                location,
+            )),
+            (Some(scancode), _) => {
+                if let Some((_, previous)) =
+                    gkeys_by_scancode.insert(scancode, (gkey, name.clone()))
+                {
+                    diagnostics.push(Diagnostic::error(
+                        format!(
+                            "\"{}\" and \"{}\" are both mapped to scancode {}",
+                            previous,
+                            name,
+                            device::format_scancode(scancode)
+                        ),
+                        location,
+                    ));
+                }
+                scancodes.insert(gkey, scancode);
+            }
+        }
+    }
+
+    let mut bindings = Vec::new();
+    for (name, gkey, value) in
+        resolve_entries(source, "bindings", icfg.binding_entries(), &mut diagnostics)
+    {
+        let location = locate(source, "bindings", &name);
+        match parse_binding(gkey, value) {
+            Err(e) => diagnostics.push(Diagnostic::error(
+                format!("Invalid binding for {}: {}", gkey::format_gkey(gkey), e),
+                location,
+            )),
+            Ok(_) => bindings.push((gkey, location)),
+        }
+    }
+
+    // A grabbed pointer's buttons are bound without going through scancodes
+    let grabbed = icfg
+        .pointer
+        .as_ref()
+        .or(inherited.pointer.as_ref())
+        .map(|pointer| pointer.grab)
+        == Some(true);
+    let needs_scancode = |gkey: u32| !(grabbed && gkey::is_pointer_gkey(gkey));
+
+    for &(shifted, layer) in &[(false, "base"), (true, "G-shift")] {
+        let in_layer = |gkey: &u32| (*gkey >= gkey::GSHIFT_OFFSET) == shifted;
+        let layer_bindings = bindings
+            .iter()
+            .filter(|(gkey, _)| in_layer(gkey) && needs_scancode(*gkey))
+            .collect::<Vec<_>>();
+        if layer_bindings.is_empty() {
+            continue;
+        }
+        if !scancodes.keys().any(in_layer) {
+            diagnostics.push(Diagnostic::warning(
+                format!(
+                    "The {} layer has {} binding(s) but no scancodes, \
+                     so none of them can be triggered",
+                    layer,
+                    layer_bindings.len()
+                ),
+                layer_bindings[0].1,
+            ));
+            continue;
+        }
+        for (gkey, location) in layer_bindings {
+            if !scancodes.contains_key(gkey) {
+                diagnostics.push(Diagnostic::warning(unscanned(*gkey), *location));
+            }
+        }
+    }
+
+    if let Some(igestures) = &icfg.gestures {
+        check_gestures(source, igestures, &scancodes, grabbed, &mut diagnostics);
+    }
+    check_devices(
+        source,
+        &icfg,
+        inherited,
+        &scancodes,
+        grabbed,
+        &mut diagnostics,
+    );
+    check_profiles(
+        source,
+        &icfg,
+        inherited,
+        &scancodes,
+        grabbed,
+        &mut diagnostics,
+    );
+
+    diagnostics
+}
+
+/// What's wrong with a bound G-key that has no scancode
+fn unscanned(gkey: u32) -> String {
+    format!(
+        "{} has no scancode, so it will only trigger on scancode {}",
+        gkey::format_gkey(gkey),
+        gkey
+    )
+}
+
+/// Warns about the G-keys a profile's or device's table binds that neither it nor `scanned`
+/// gives a scancode, unless they're buttons of a grabbed pointer
+fn check_unscanned<F: Fn(u32) -> bool>(
+    source: &str,
+    (kind, name): (&str, &str),
+    bindings: &BTreeMap<String, serde_value::Value>,
+    scancodes: &BTreeMap<String, serde_value::Value>,
+    grabbed: bool,
+    scanned: F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let keymap = match parse_keymap(bindings, scancodes) {
+        Ok(keymap) => keymap,
+        // Reported already
+        Err(_) => return,
+    };
+    let table = format!("{}s.{}", kind, name);
+    for (key, _) in binding_entries(bindings) {
+        let gkey = match gkey::parse_gkey(&key) {
+            Ok(gkey) => gkey,
+            Err(_) => continue,
+        };
+        let own = keymap.scancodes.iter().any(|(scanned, _)| *scanned == gkey);
+        if own || scanned(gkey) || (grabbed && gkey::is_pointer_gkey(gkey)) {
+            continue;
+        }
+        diagnostics.push(Diagnostic::warning(
+            format!("In {} \"{}\": {}", kind, name, unscanned(gkey)),
+            locate(source, &format!("{}.bindings", table), &key)
+                .or_else(|| locate_table(source, &table))
+                .or_else(|| locate(source, &format!("{}s", kind), name)),
+        ));
+    }
+}
+
+fn check_profiles(
+    source: &str,
+    icfg: &IntermedConfig,
+    inherited: &Configuration,
+    scancodes: &BTreeMap<u32, u32>,
+    grabbed: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let locate_profile = |name: &str| {
+        let table = format!("profiles.{}", name);
+        locate_table(source, &table)
+            .or_else(|| locate(source, "profiles", name))
+            .or_else(|| locate_table(source, &format!("{}.bindings", table)))
+            .or_else(|| locate_table(source, &format!("{}.scancodes", table)))
+    };
+    for (name, iprofile) in &icfg.profiles {
+        if let Err(e) = parse_keymap(&iprofile.bindings, &iprofile.scancodes) {
+            diagnostics.push(Diagnostic::error(
+                format!("In profile \"{}\": {}", name, e),
+                locate_profile(name),
+            ));
+        }
+        // A profile's bindings also take the first device's scancodes
+        check_unscanned(
+            source,
+            ("profile", name),
+            &iprofile.bindings,
+            &iprofile.scancodes,
+            grabbed,
+            |gkey| scancodes.contains_key(&gkey),
+            diagnostics,
+        );
+    }
+    let names = inherited
+        .profiles
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .chain(icfg.profiles.keys().map(String::as_str))
+        .collect::<Vec<_>>();
+    let unknown = |name: &str| {
+        let suggestion = did_you_mean(name, names.iter().cloned())
+            .map(|s| format!("; did you mean \"{}\"?", s))
+            .unwrap_or_default();
+        format!("Unknown profile \"{}\"{}", name, suggestion)
+    };
+
+    let tables = std::iter::once(("bindings".to_string(), &icfg.bindings)).chain(
+        icfg.profiles
+            .iter()
+            .map(|(name, iprofile)| (format!("profiles.{}.bindings", name), &iprofile.bindings)),
+    );
+    for (table, bindings) in tables {
+        for (key, value) in binding_entries(bindings) {
+            let switched = match gkey::parse_gkey(&key).and_then(|gkey| parse_binding(gkey, value))
+            {
+                Ok((_, BindingType::Profile(Some(name)))) => name,
+                _ => continue,
+            };
+            if !names.contains(&switched.as_str()) {
+                diagnostics.push(Diagnostic::error(
+                    unknown(&switched),
+                    locate(source, &table, &key),
+                ));
+            }
+        }
+    }
+
+    let keyboard = match &icfg.keyboard {
+        Some(keyboard) => keyboard,
+        None => return,
+    };
+    let location = locate(source, "keyboard", "grab");
+    match &keyboard.grab {
+        keyboard_watcher::GrabMode::Profile(name) if !names.contains(&name.as_str()) => {
+            diagnostics.push(Diagnostic::error(unknown(name), location))
+        }
+        keyboard_watcher::GrabMode::Never if keyboard.passthrough => {
+            diagnostics.push(Diagnostic::warning(
+                "Keys are only passed through while the keyboard interface is grabbed, so \
+                 \"passthrough\" does nothing when it's never grabbed"
+                    .to_string(),
+                location,
+            ))
+        }
+        _ => (),
+    }
+}
+
+fn check_devices(
+    source: &str,
+    icfg: &IntermedConfig,
+    inherited: &Configuration,
+    scancodes: &BTreeMap<u32, u32>,
+    grabbed: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // A device's table may be given whole, inline, or only as its bindings or scancodes
+    let locate_device = |name: &str| {
+        let table = format!("devices.{}", name);
+        locate_table(source, &table)
+            .or_else(|| locate(source, "devices", name))
+            .or_else(|| locate_table(source, &format!("{}.bindings", table)))
+            .or_else(|| locate_table(source, &format!("{}.scancodes", table)))
+    };
+    let mut devices = inherited.devices.clone();
+    // Those already reported, which needn't also be warned about as undescribed
+    let mut misdescribed = Vec::new();
+    for (name, idevice) in &icfg.devices {
+        let location = locate_device(name);
+        match parse_device(name, idevice) {
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(e, location));
+                misdescribed.push(name.as_str());
+            }
+            Ok(None) => (),
+            Ok(Some(descriptor)) => {
+                devices.retain(|existing| existing.name != descriptor.name);
+                devices.push(descriptor);
+            }
+        }
+        if let Err(e) = parse_keymap(&idevice.bindings, &idevice.scancodes) {
+            diagnostics.push(Diagnostic::error(
+                format!("In device \"{}\": {}", name, e),
+                location,
+            ));
+        }
+    }
+    let known = device::known(&devices);
+    let is_known = |name: &str| known.iter().any(|device| device.name == name);
+    let undescribed = icfg
+        .devices
+        .keys()
+        .filter(|name| !is_known(name) && !misdescribed.contains(&name.as_str()));
+    for name in undescribed {
+        diagnostics.push(Diagnostic::warning(
+            format!(
+                "Device \"{}\" isn't described anywhere, so its bindings can't be used",
+                name
+            ),
+            locate_device(name),
+        ));
+    }
+
+    let selected = match icfg.device.as_ref().map(parse_device_selection) {
+        Some(Ok(selected)) => selected,
+        Some(Err(e)) => {
+            diagnostics.push(Diagnostic::error(e, locate(source, "", "device")));
+            vec![]
+        }
+        None => inherited.device.clone(),
+    };
+    for selector in &selected {
+        if let Err(e) = device::candidates(selector, &known) {
+            diagnostics.push(Diagnostic::error(e, locate(source, "", "device")));
+        }
+    }
+    // Only the first device driven also takes the shared scancodes, which any might be unless
+    // another is selected first
+    let first = selected
+        .first()
+        .and_then(|selector| selector.name.as_deref());
+    for (name, idevice) in icfg.devices.iter().filter(|(name, _)| is_known(name)) {
+        let shared = first.is_none() || first == Some(name.as_str());
+        check_unscanned(
+            source,
+            ("device", name),
+            &idevice.bindings,
+            &idevice.scancodes,
+            grabbed && shared,
+            |gkey| shared && scancodes.contains_key(&gkey),
+            diagnostics,
+        );
+    }
+
+    for (name, token) in &icfg.chords {
+        use std::str::FromStr;
+        let location = locate(source, "chords", name);
+        let chord = match chord::Chord::from_str(name) {
+            Ok(chord) => chord,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(e, location));
+                continue;
+            }
+        };
+        let (_, last) = chord.0[chord.0.len() - 1];
+        if let Err(e) = parse_binding(last, token) {
+            diagnostics.push(Diagnostic::error(
+                format!("Invalid binding for chord \"{}\": {}", chord, e),
+                location,
+            ));
+        }
+        for unknown in chord.0.iter().filter_map(|(device, _)| device.as_ref()) {
+            if !is_known(unknown) {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "Chord \"{}\" is on an unknown device \"{}\"",
+                        chord, unknown
+                    ),
+                    location,
+                ));
+            }
+        }
+    }
+}
+
+fn check_gestures(
+    source: &str,
+    igestures: &super::IntermedGestures,
+    scancodes: &BTreeMap<u32, u32>,
+    grabbed: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if igestures.threshold == Some(0) {
+        diagnostics.push(Diagnostic::error(
+            "Gesture \"threshold\" must be at least 1".to_string(),
+            locate(source, "gestures", "threshold"),
+        ));
+    }
+    let trigger = match parse_trigger(&igestures.trigger) {
+        Ok(trigger) => trigger,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(e, locate(source, "gestures", "trigger")));
+            return;
+        }
+    };
+    if !(scancodes.contains_key(&trigger) || grabbed && gkey::is_pointer_gkey(trigger)) {
+        diagnostics.push(Diagnostic::warning(
+            format!(
+                "The gesture trigger {} has no scancode, so it will only trigger on scancode {}",
+                gkey::format_gkey(trigger),
+                trigger
+            ),
+            locate(source, "gestures", "trigger"),
+        ));
+    }
+    let mut seen = BTreeMap::new();
+    for (name, token) in &igestures.bindings {
+        let location = locate(source, "gestures.bindings", name);
+        match parse_gesture_binding(trigger, name, token) {
+            Err(e) => diagnostics.push(Diagnostic::error(e, location)),
+            Ok((drawn, _)) => {
+                if let Some(previous) = seen.insert(drawn, name) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("\"{}\" and \"{}\" are the same gesture", previous, name),
+                        location,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Each diagnostic's message and location, as tests compare them
+#[cfg(test)]
+fn located(diagnostics: &[Diagnostic]) -> Vec<(&str, Option<(usize, usize)>)> {
+    diagnostics
+        .iter()
+        .map(|d| (d.message.as_str(), d.location))
+        .collect()
+}
+
+#[test]
+fn test_check_invalid_bindings() {
+    let input = r#"
+[bindings]
+G9 = "fine"
+G10 = { type = "keyboard", key = "PageDwn" }
+G11 = { type = "mouse", button = 0 }
+
+[scancodes]
+G9 = 30
+G10 = 31
+G11 = 32
+"#;
+    let rendered = check_config(input)
+        .iter()
+        .map(|d| d.render("config.toml"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            "error: Invalid binding for G10: Unknown key \"PageDwn\"; \
+             did you mean \"PageDown\"?\n  --> config.toml:4:1",
+            "error: Invalid binding for G11: Invalid mouse button 0; \
+             buttons are numbered from 1 to 255\n  --> config.toml:5:1",
+        ]
+    );
+}
+
+#[test]
+fn test_check_duplicate_scancodes() {
+    let input = "[scancodes]\nG9 = 30\nG10 = 31\nG11 = 30\n";
+    assert_eq!(
+        located(&check_config(input)),
+        vec![(
+            "\"G11\" and \"G9\" are both mapped to scancode 30",
+            Some((2, 1))
+        )]
+    );
+
+    let inherited = Configuration {
+        scancodes: vec![(110, 31)],
+        ..Default::default()
+    };
+    assert_eq!(
+        located(&check_config_with(input, &inherited)),
+        vec![
+            (
+                "\"G^10 (included)\" and \"G10\" are both mapped to scancode 31",
+                Some((3, 1))
+            ),
+            (
+                "\"G11\" and \"G9\" are both mapped to scancode 30",
+                Some((2, 1))
+            ),
+        ]
+    );
+
+    let keys =
+        check_config("[scancodes]\nG9 = \"KEY_F1\"\nG10 = \"KEY_F1\"\nG11 = \"KEY_PLAYPAWS\"\n");
+    assert_eq!(
+        keys.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(),
+        vec![
+            "Unknown key \"KEY_PLAYPAWS\" for G11; did you mean \"KEY_PLAYPAUSE\"?",
+            "\"G10\" and \"G9\" are both mapped to scancode KEY_F1",
+        ]
+    );
+}
+
+#[test]
+fn test_check_unreachable_bindings() {
+    let input = r#"
+[bindings]
+G9 = "fine"
+G12 = "no scancode"
+"G^9" = "no shifted scancodes"
+
+[scancodes]
+G9 = 30
+"#;
+    let rendered = check_config(input)
+        .iter()
+        .map(|d| d.render("config.toml"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            "warning: G12 has no scancode, so it will only trigger on scancode 12\
+             \n  --> config.toml:4:1",
+            "warning: The G-shift layer has 1 binding(s) but no scancodes, \
+             so none of them can be triggered\n  --> config.toml:5:1",
+        ]
+    );
+
+    // Scancodes from included files reach them too
+    let inherited = Configuration {
+        scancodes: vec![(12, 33), (109, 4)],
+        ..Default::default()
+    };
+    assert_eq!(check_config_with(input, &inherited), vec![]);
+
+    let pointer =
+        "[pointer]\ngrab = true\n[bindings]\nshift.wheel-up = \"louder\"\nmiddle = \"paste\"\n";
+    assert_eq!(check_config(pointer), vec![]);
+    assert_eq!(check_config(&pointer.replace("true", "false")).len(), 2);
+
+    let elsewhere = check_config(
+        r#"
+device = ["g600", "g700"]
+
+[scancodes]
+G9 = 30
+
+[profiles.games]
+bindings = { G9 = "scanned", G10 = "not" }
+
+[devices.g600.bindings]
+G9 = "scanned"
+[devices.g700]
+bindings = { G9 = "only the first device's", G10 = "scanned" }
+scancodes = { G10 = 31 }
+"#,
+    );
+    assert_eq!(
+        located(&elsewhere),
+        vec![
+            (
+                "In device \"g700\": G9 has no scancode, so it will only trigger on scancode 9",
+                Some((12, 1))
+            ),
+            (
+                "In profile \"games\": G10 has no scancode, so it will only trigger on scancode 10",
+                Some((7, 1))
+            ),
+        ]
+    );
+
+    let never = check_config("[keyboard]\npassthrough = true\ngrab = \"never\"\n");
+    assert_eq!(never.len(), 1);
+    assert_eq!(never[0].severity, Severity::Warning);
+}
+
+#[test]
+fn test_check_gestures() {
+    let gestures = check_config(
+        r#"
+[scancodes]
+G7 = 36
+
+[gestures]
+trigger = "G7"
+[gestures.bindings]
+L-U = "fine"
+"L-L" = "repeats"
+left-up = "same as L-U"
+"#,
+    );
+    assert_eq!(
+        located(&gestures),
+        vec![
+            (
+                "Gesture \"L-L\" repeats a stroke; one long stroke is drawn the same as two",
+                Some((9, 1))
+            ),
+            (
+                "\"L-U\" and \"left-up\" are the same gesture",
+                Some((10, 1))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_check_devices() {
+    let devices = check_config(
+        r#"
+device = "pad"
+
+[devices.macropad]
+prefix = "usb-Macropad"
+buttons = { G9 = "A", "G^10" = "B" }
+"#,
+    );
+    assert_eq!(
+        located(&devices),
+        vec![
+            (
+                "In device \"macropad\": Device buttons are unshifted G-keys, unlike \"G^10\"",
+                Some((4, 1))
+            ),
+            ("Unknown device \"pad\"", Some((2, 1))),
+        ]
+    );
+    assert_eq!(check_config("device = \"naga\"\n"), vec![]);
+    assert_eq!(
+        check_config("device = [\"naga\", { name = \"g600\", serial = \"AB12\" }]\n"),
+        vec![]
+    );
+    let selection = check_config("\ndevice = { name = \"g600\", port = \"1-2\" }\n");
+    assert_eq!(
+        located(&selection),
+        vec![(
+            "Invalid device selection: Unknown field port. Expected one of name, serial, phys",
+            Some((2, 1))
+        )]
+    );
+}
+
+#[test]
+fn test_check_chords() {
+    let multiple = check_config(
+        r#"
+device = ["g600", "pad"]
+
+[chords]
+"G9 + pad.G1" = "fine"
+"G9 + pda.G1" = "misspelt"
+
+[devices.pad]
+prefix = "usb-Macropad"
+bindings = { G1 = "pad only" }
+scancodes = { G1 = 30 }
+
+[devices.undescribed.bindings]
+G1 = "unusable"
+"#,
+    );
+    assert_eq!(
+        located(&multiple),
+        vec![
+            (
+                "Device \"undescribed\" isn't described anywhere, so its bindings can't be used",
+                Some((13, 1))
+            ),
+            (
+                "Chord \"G9 + pda.G1\" is on an unknown device \"pda\"",
+                Some((6, 1))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_check_profiles() {
+    let profiles = check_config(
+        r#"
+[keyboard]
+grab = { profile = "game" }
+
+[scancodes]
+G9 = 30
+G10 = 31
+
+[bindings]
+G9 = { type = "profile", name = "games" }
+
+[profiles.games.bindings]
+G9 = { type = "profile" }
+G10 = { type = "profile", name = "work" }
+"#,
+    );
+    assert_eq!(
+        located(&profiles),
+        vec![
+            ("Unknown profile \"work\"", Some((14, 1))),
+            (
+                "Unknown profile \"game\"; did you mean \"games\"?",
+                Some((3, 1))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_check_unknown_keys() {
+    let unknown = check_config(
+        r#"
+include = []
+inculde = ["base.toml"]
+
+[gesture]
+trigger = "G7"
+
+[profile.games.bindings]
+G9 = "typo"
+
+[keybaord]
+grab = "never"
+"#,
+    );
+    assert_eq!(
+        located(&unknown),
+        vec![
+            (
+                "Unknown table \"gesture\"; did you mean \"gestures\"?",
+                Some((5, 1))
+            ),
+            (
+                "Unknown key \"inculde\"; did you mean \"include\"?",
+                Some((3, 1))
+            ),
+            (
+                "Unknown table \"keybaord\"; did you mean \"keyboard\"?",
+                Some((11, 1))
+            ),
+            (
+                "Unknown table \"profile\"; did you mean \"profiles\"?",
+                Some((8, 1))
+            ),
+        ]
+    );
+    assert_eq!(
+        check_config("[mystery]\n")[0].message,
+        "Unknown table \"mystery\""
+    );
+
+    let unparseable = check_config("[bindings]\nG9 = \n");
+    assert_eq!(unparseable.len(), 1);
+    assert_eq!(unparseable[0].location, Some((2, 6)));
+}
@@ -0,0 +1,98 @@
+/// Offset added to a G-key's number when it is pressed with G-shift held.
+pub const GSHIFT_OFFSET: u32 = 100;
+
+/// The ring-finger button, whose G-key tells which layer the pointer's buttons are in
+pub const GSHIFT: u32 = 6;
+/// The wheel's tilt, which the pointer interface reports as horizontal scrolling
+pub const WHEEL_LEFT: u32 = 4;
+pub const WHEEL_RIGHT: u32 = 5;
+/// The scroll wheel has no G-number of its own, so its notches are numbered past the G-keys
+pub const WHEEL_UP: u32 = 21;
+pub const WHEEL_DOWN: u32 = 22;
+
+/// G-keys which the pointer interface sends, rather than the keyboard interface
+const POINTER_GKEYS: &[u32] = &[1, 2, 3, WHEEL_LEFT, WHEEL_RIGHT, WHEEL_UP, WHEEL_DOWN];
+
+/// Buttons with names of their own, rather than only a G-number
+const NAMED_GKEYS: &[(&str, u32)] = &[
+    ("left", 1),
+    ("right", 2),
+    ("middle", 3),
+    ("wheel-left", WHEEL_LEFT),
+    ("wheel-right", WHEEL_RIGHT),
+    ("gshift", GSHIFT),
+    ("wheel-up", WHEEL_UP),
+    ("wheel-down", WHEEL_DOWN),
+];
+
+pub fn format_gkey(gkey: u32) -> String {
+    match gkey {
+        k if k >= GSHIFT_OFFSET => format!("G^{}", &(k - GSHIFT_OFFSET)),
+        k => format!("G{}", &k),
+    }
+}
+
+/// Whether a G-key, in either layer, comes from the pointer interface when it's grabbed
+pub fn is_pointer_gkey(gkey: u32) -> bool {
+    POINTER_GKEYS.contains(&(gkey % GSHIFT_OFFSET))
+}
+
+/// Parses a G-key as written in a config key.
+///
+/// Accepts the raw numbering (`9`, `109`), G-numbers (`G9`), button names (`left`, `gshift`),
+/// and either form of G-shifted key (`G^9`, `shift.G9`, `shift.left`).
+pub fn parse_gkey(name: &str) -> Result<u32, String> {
+    let trimmed = name.trim();
+    if !trimmed.is_empty() && trimmed.chars().all(|c| c.is_ascii_digit()) {
+        return trimmed
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid G-key \"{}\": {}", name, e));
+    }
+
+    let lowered = trimmed.to_ascii_lowercase();
+    let unknown = || format!("Unknown G-key \"{}\"", name);
+    let parse_number = |digits: &str| match digits.parse::<u32>() {
+        Ok(n) if n > 0 && n < GSHIFT_OFFSET => Ok(n),
+        _ => Err(unknown()),
+    };
+
+    let (shifted, number) = if lowered.starts_with("g^") {
+        (true, parse_number(&lowered["g^".len()..])?)
+    } else {
+        let (shifted, unshifted) = if lowered.starts_with("shift.") {
+            (true, &lowered["shift.".len()..])
+        } else {
+            (false, lowered.as_str())
+        };
+        let number = match NAMED_GKEYS.iter().find(|(n, _)| *n == unshifted) {
+            Some(&(_, n)) => n,
+            None if unshifted.starts_with('g') => parse_number(&unshifted[1..])?,
+            None => return Err(unknown()),
+        };
+        (shifted, number)
+    };
+
+    Ok(if shifted {
+        number + GSHIFT_OFFSET
+    } else {
+        number
+    })
+}
+
+#[test]
+fn test_parse_gkey() {
+    assert_eq!(parse_gkey("009"), Ok(9));
+    assert_eq!(parse_gkey("109"), Ok(109));
+    assert_eq!(parse_gkey("G9"), Ok(9));
+    assert_eq!(parse_gkey("G^9"), Ok(109));
+    assert_eq!(parse_gkey("shift.G9"), Ok(109));
+    assert_eq!(parse_gkey("middle"), Ok(3));
+    assert_eq!(parse_gkey("shift.wheel-left"), Ok(104));
+    assert_eq!(parse_gkey("GShift"), Ok(6));
+    assert_eq!(parse_gkey("shift.wheel-down"), Ok(122));
+    assert!(is_pointer_gkey(122) && is_pointer_gkey(3) && !is_pointer_gkey(9));
+    assert!(parse_gkey("G^G9").is_err());
+    assert!(parse_gkey("shift.G^9").is_err());
+    assert!(parse_gkey("G100").is_err());
+    assert!(parse_gkey("thumb").is_err());
+}
@@ -2,14 +2,32 @@ extern crate dirs;
 extern crate toml;
 extern crate xdg;
 
+use super::chord;
+use super::device;
+use super::exec;
+use super::gesture;
+use super::keyboard_watcher;
+use super::mpris;
+use super::notify;
+use super::pointer_watcher;
+use super::volume;
 use super::xdo;
 
+#[cfg(test)]
+mod arbitrary;
+pub mod check;
+pub mod gkey;
+
 const CONFIG_NAME: &str = "config.toml";
 
 pub fn find_dotfile() -> Option<::std::path::PathBuf> {
     // Priorities (highest first):
+    //$LG600R_CONFIG, used even if it doesn't exist so that mistakes in it aren't silently ignored
     //XDG style, e.g.: ~/.config/lg600r/config.toml
     //~/.dotfile, e.g.: ~/.lg600r/config.toml
+    if let Some(path) = ::std::env::var_os("LG600R_CONFIG").filter(|p| !p.is_empty()) {
+        return Some(::std::path::PathBuf::from(path));
+    }
     xdg::BaseDirectories::with_prefix("lg600r")
         .ok()
         .and_then(|basedirs| {
@@ -33,128 +51,1540 @@ pub fn find_dotfile() -> Option<::std::path::PathBuf> {
         })
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct Configuration {
     pub bindings: Vec<(u32, BindingType)>,
     pub scancodes: Vec<(u32, u32)>,
+    /// G-keys whose bindings show a notification whenever they're used
+    pub notify: std::collections::BTreeSet<u32>,
+    pub notifications: Option<notify::NotificationSettings>,
+    pub gestures: Option<GestureSettings>,
+    pub pointer: Option<pointer_watcher::PointerSettings>,
+    pub keyboard: Option<keyboard_watcher::KeyboardSettings>,
+    /// Which devices to drive at once; if none are selected, the first one found is driven
+    pub device: Vec<device::Selector>,
+    /// Devices described in the config, in addition to or in place of the built-in ones
+    pub devices: Vec<device::DeviceDescriptor>,
+    /// Bindings and scancodes of particular devices, by device name
+    pub device_keymaps: Vec<(String, DeviceKeymap)>,
+    /// Bindings for G-keys held together, perhaps across devices
+    pub chords: Vec<(chord::Chord, BindingType)>,
+    pub layers: Option<LayerSettings>,
+    /// Bindings and scancodes switched to by name, which take precedence over the first
+    /// device's own while switched to
+    pub profiles: Vec<(String, DeviceKeymap)>,
+}
+
+/// A device's own bindings and scancodes, which take precedence over the shared ones
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceKeymap {
+    pub bindings: Vec<(u32, BindingType)>,
+    pub scancodes: Vec<(u32, u32)>,
+    pub notify: std::collections::BTreeSet<u32>,
+}
+
+impl DeviceKeymap {
+    fn is_empty(&self) -> bool {
+        self.bindings.is_empty() && self.scancodes.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayerSettings {
+    /// Whether holding G-shift puts the keys of every device driven in the G-shift layer,
+    /// and not only those of the device it's on
+    pub shared: bool,
+}
+
+/// Bindings for pointer gestures drawn while holding a trigger G-key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GestureSettings {
+    /// Held to draw a gesture; tapped without moving, it does whatever it's bound to
+    pub trigger: u32,
+    /// How far, in mouse counts, the pointer must travel for a stroke to register
+    pub threshold: u32,
+    pub bindings: Vec<(gesture::Gesture, BindingType)>,
+}
+
+impl GestureSettings {
+    pub fn binding(&self, drawn: &gesture::Gesture) -> Option<&BindingType> {
+        self.bindings
+            .iter()
+            .find(|(gesture, _)| gesture == drawn)
+            .map(|(_, binding)| binding)
+    }
+}
+
+fn overlay<T: PartialEq>(base: &mut Vec<(T, BindingType)>, entries: Vec<(T, BindingType)>) {
+    for (key, value) in entries {
+        match base.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(entry) => entry.1 = value,
+            None => base.push((key, value)),
+        }
+    }
+}
+
+/// Overlays bindings and scancodes; whether a binding notifies goes along with the binding
+/// that replaces it
+fn overlay_keymap(
+    bindings: &mut Vec<(u32, BindingType)>,
+    scancodes: &mut Vec<(u32, u32)>,
+    notify: &mut std::collections::BTreeSet<u32>,
+    other: DeviceKeymap,
+) {
+    for (gkey, _) in &other.bindings {
+        notify.remove(gkey);
+    }
+    notify.extend(other.notify);
+    overlay(bindings, other.bindings);
+    for (gkey, scancode) in other.scancodes {
+        match scancodes.iter_mut().find(|(existing, _)| *existing == gkey) {
+            Some(entry) => entry.1 = scancode,
+            None => scancodes.push((gkey, scancode)),
+        }
+    }
+}
+
+/// Overlays keymaps by name, where those of the same name are overlaid in turn
+fn overlay_keymaps(base: &mut Vec<(String, DeviceKeymap)>, other: Vec<(String, DeviceKeymap)>) {
+    for (name, keymap) in other {
+        match base.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => overlay_keymap(
+                &mut existing.bindings,
+                &mut existing.scancodes,
+                &mut existing.notify,
+                keymap,
+            ),
+            None => base.push((name, keymap)),
+        }
+    }
+}
+
+impl Configuration {
+    /// Overlays `other` onto this configuration; its entries replace any for the same G-key.
+    pub fn merge(&mut self, other: Configuration) {
+        overlay_keymap(
+            &mut self.bindings,
+            &mut self.scancodes,
+            &mut self.notify,
+            DeviceKeymap {
+                bindings: other.bindings,
+                scancodes: other.scancodes,
+                notify: other.notify,
+            },
+        );
+        if other.notifications.is_some() {
+            self.notifications = other.notifications;
+        }
+        if other.gestures.is_some() {
+            self.gestures = other.gestures;
+        }
+        if other.pointer.is_some() {
+            self.pointer = other.pointer;
+        }
+        if other.keyboard.is_some() {
+            self.keyboard = other.keyboard;
+        }
+        if !other.device.is_empty() {
+            self.device = other.device;
+        }
+        for descriptor in other.devices {
+            self.devices
+                .retain(|existing| existing.name != descriptor.name);
+            self.devices.push(descriptor);
+        }
+        overlay_keymaps(&mut self.device_keymaps, other.device_keymaps);
+        overlay(&mut self.chords, other.chords);
+        if other.layers.is_some() {
+            self.layers = other.layers;
+        }
+        overlay_keymaps(&mut self.profiles, other.profiles);
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.profiles.iter().any(|(n, _)| n == name)
+    }
+
+    /// The configuration of one of the devices driven: the first device driven has the shared
+    /// bindings and scancodes, along with the gesture and pointer settings, and each device's
+    /// own bindings and scancodes take precedence
+    pub fn for_device(&self, name: &str, first: bool) -> Configuration {
+        let mut config = self.clone();
+        if !first {
+            config.bindings.clear();
+            config.scancodes.clear();
+            config.notify.clear();
+            config.gestures = None;
+            config.pointer = None;
+        }
+        if let Some((_, keymap)) = self.device_keymaps.iter().find(|(n, _)| n == name) {
+            overlay_keymap(
+                &mut config.bindings,
+                &mut config.scancodes,
+                &mut config.notify,
+                keymap.clone(),
+            );
+        }
+        config
+    }
+
+    /// The configuration with a profile's bindings and scancodes taking precedence, if one's
+    /// switched to
+    pub fn with_profile(mut self, profile: Option<&str>) -> Configuration {
+        let keymap = self
+            .profiles
+            .iter()
+            .find(|(name, _)| Some(name.as_str()) == profile)
+            .map(|(_, keymap)| keymap.clone());
+        if let Some(keymap) = keymap {
+            overlay_keymap(
+                &mut self.bindings,
+                &mut self.scancodes,
+                &mut self.notify,
+                keymap,
+            );
+        }
+        self
+    }
+}
+
+/// Config key for a G-key, zero-padded so that keys sort in G-key order
+fn canonical_gkey(gkey: u32) -> String {
+    format!("{:03}", gkey)
+}
+
+/// Serializes a binding in the form `parse_binding` reads it back from, and whether it notifies
+struct SerializedBinding<'a>(&'a BindingType, bool);
+
+impl<'a> serde::Serialize for SerializedBinding<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let SerializedBinding(inner, notify) = self;
+        let mut map = match inner {
+            BindingType::Command(cmd) if !notify => return serializer.serialize_str(cmd),
+            _ => serializer.serialize_map(None)?,
+        };
+        match inner {
+            BindingType::Command(cmd) => {
+                map.serialize_entry("type", "command")?;
+                map.serialize_entry("command", cmd)?;
+            }
+            BindingType::EmulateKey(key) => {
+                map.serialize_entry("type", "keyboard")?;
+                map.serialize_entry("key", &key.to_string())?;
+            }
+            BindingType::EmulateMouse(button) => {
+                map.serialize_entry("type", "mouse")?;
+                map.serialize_entry("button", button)?;
+            }
+            BindingType::KeySequence(text) => {
+                map.serialize_entry("type", "sequence")?;
+                map.serialize_entry("text", text)?;
+            }
+            BindingType::I3(command) => {
+                map.serialize_entry("type", "i3")?;
+                map.serialize_entry("command", command)?;
+            }
+            BindingType::Passthrough => map.serialize_entry("type", "passthrough")?,
+            BindingType::Profile(name) => {
+                map.serialize_entry("type", "profile")?;
+                if let Some(name) = name {
+                    map.serialize_entry("name", name)?;
+                }
+            }
+            BindingType::Scroll {
+                axis,
+                amount,
+                repeat_ms,
+            } => {
+                map.serialize_entry("type", "scroll")?;
+                map.serialize_entry("amount", amount)?;
+                if *axis == ScrollAxis::Horizontal {
+                    map.serialize_entry("axis", "horizontal")?;
+                }
+                if let Some(repeat) = repeat_ms {
+                    map.serialize_entry("repeat", repeat)?;
+                }
+            }
+            BindingType::MovePointer {
+                x,
+                y,
+                relative,
+                screen,
+            } => {
+                map.serialize_entry("type", "move")?;
+                map.serialize_entry("x", x)?;
+                map.serialize_entry("y", y)?;
+                if *relative {
+                    map.serialize_entry("relative", &true)?;
+                } else if *screen != 0 {
+                    map.serialize_entry("screen", screen)?;
+                }
+            }
+            BindingType::Click { button, count } => {
+                map.serialize_entry("type", "click")?;
+                map.serialize_entry("button", button)?;
+                if *count != 1 {
+                    map.serialize_entry("count", count)?;
+                }
+            }
+            BindingType::Media(action) => {
+                map.serialize_entry("type", "media")?;
+                map.serialize_entry("action", action.command.name())?;
+                if let mpris::MediaCommand::Seek(offset) = action.command {
+                    map.serialize_entry("offset", &offset)?;
+                }
+                if let Some(player) = &action.player {
+                    map.serialize_entry("player", player)?;
+                }
+            }
+            BindingType::Volume(action) => {
+                map.serialize_entry("type", "volume")?;
+                match action.operation {
+                    volume::VolumeOperation::Step(percent) => {
+                        map.serialize_entry("step", &percent)?
+                    }
+                    volume::VolumeOperation::Set(percent) => {
+                        map.serialize_entry("set", &percent)?
+                    }
+                    volume::VolumeOperation::Mute(None) => map.serialize_entry("mute", "toggle")?,
+                    volume::VolumeOperation::Mute(Some(mute)) => {
+                        map.serialize_entry("mute", &mute)?
+                    }
+                }
+                match &action.device {
+                    device if *device == volume::VolumeDevice::default() => (),
+                    volume::VolumeDevice::Sink(name) => map.serialize_entry("sink", name)?,
+                    volume::VolumeDevice::Source(name) => map.serialize_entry("source", name)?,
+                }
+                if let Some(repeat) = action.repeat_ms {
+                    map.serialize_entry("repeat", &repeat)?;
+                }
+                if let Some(fallback) = &action.fallback {
+                    map.serialize_entry("fallback", fallback)?;
+                }
+            }
+            BindingType::Exec(spec) => {
+                map.serialize_entry("type", "exec")?;
+                match &spec.target {
+                    exec::ExecTarget::Argv(argv) => {
+                        map.serialize_entry("argv", argv)?;
+                    }
+                    exec::ExecTarget::Shell { shell, command } => {
+                        map.serialize_entry("shell", shell)?;
+                        map.serialize_entry("command", command)?;
+                    }
+                    exec::ExecTarget::ShellArgv { shell, argv } => {
+                        map.serialize_entry("shell", shell)?;
+                        map.serialize_entry("argv", argv)?;
+                    }
+                }
+                if !spec.env.is_empty() {
+                    map.serialize_entry("env", &spec.env)?;
+                }
+                if let Some(cwd) = &spec.cwd {
+                    map.serialize_entry("cwd", cwd)?;
+                }
+                if spec.detach {
+                    map.serialize_entry("detach", &true)?;
+                }
+            }
+        }
+        if *notify {
+            map.serialize_entry("notify", &true)?;
+        }
+        map.end()
+    }
+}
+
+fn serialized_bindings<'a>(
+    bindings: &'a [(u32, BindingType)],
+    notify: &std::collections::BTreeSet<u32>,
+) -> std::collections::BTreeMap<String, SerializedBinding<'a>> {
+    bindings
+        .iter()
+        .map(|(gkey, binding)| {
+            let notify = notify.contains(gkey);
+            (canonical_gkey(*gkey), SerializedBinding(binding, notify))
+        })
+        .collect()
+}
+
+/// Serializes a scancode as `parse_scancode` reads it back, naming the key it stands for if any
+struct SerializedScancode(u32);
+
+impl serde::Serialize for SerializedScancode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match device::scancode_keycode(self.0).and_then(device::keycode_name) {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_u32(self.0),
+        }
+    }
+}
+
+fn serialized_scancodes(
+    scancodes: &[(u32, u32)],
+) -> std::collections::BTreeMap<String, SerializedScancode> {
+    scancodes
+        .iter()
+        .map(|(gkey, scancode)| (canonical_gkey(*gkey), SerializedScancode(*scancode)))
+        .collect()
+}
+
+/// Serializes what's under `[devices.<name>]`: a device's description, and its own bindings
+#[derive(Default)]
+struct SerializedDevice<'a>(
+    Option<&'a device::DeviceDescriptor>,
+    Option<&'a DeviceKeymap>,
+);
+
+impl<'a> serde::Serialize for SerializedDevice<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(descriptor) = self.0 {
+            descriptor.serialize_entries(&mut map)?;
+        }
+        if let Some(keymap) = self.1 {
+            if !keymap.bindings.is_empty() {
+                let bindings = serialized_bindings(&keymap.bindings, &keymap.notify);
+                map.serialize_entry("bindings", &bindings)?;
+            }
+            if !keymap.scancodes.is_empty() {
+                map.serialize_entry("scancodes", &serialized_scancodes(&keymap.scancodes))?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl serde::Serialize for Configuration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let bindings = serialized_bindings(&self.bindings, &self.notify);
+        let scancodes = serialized_scancodes(&self.scancodes);
+        let mut config = serializer.serialize_struct("Configuration", 2)?;
+        // Selected by name alone, devices are written by name; TOML lists can't mix in tables
+        let names = self
+            .device
+            .iter()
+            .map(device::Selector::name_only)
+            .collect::<Option<Vec<_>>>();
+        match (self.device.as_slice(), names) {
+            ([], _) => (),
+            (_, Some(names)) if names.len() == 1 => config.serialize_field("device", names[0])?,
+            (_, Some(names)) => config.serialize_field("device", &names)?,
+            ([device], None) => config.serialize_field("device", device)?,
+            (devices, None) => config.serialize_field("device", devices)?,
+        }
+        config.serialize_field("bindings", &bindings)?;
+        config.serialize_field("scancodes", &scancodes)?;
+        if let Some(notifications) = &self.notifications {
+            config.serialize_field("notifications", notifications)?;
+        }
+        if let Some(gestures) = &self.gestures {
+            config.serialize_field("gestures", gestures)?;
+        }
+        if let Some(pointer) = &self.pointer {
+            config.serialize_field("pointer", pointer)?;
+        }
+        if let Some(keyboard) = &self.keyboard {
+            config.serialize_field("keyboard", keyboard)?;
+        }
+        if !self.chords.is_empty() {
+            let chords = self
+                .chords
+                .iter()
+                .map(|(chord, binding)| (chord.to_string(), SerializedBinding(binding, false)))
+                .collect::<std::collections::BTreeMap<_, _>>();
+            config.serialize_field("chords", &chords)?;
+        }
+        if let Some(layers) = &self.layers {
+            config.serialize_field("layers", layers)?;
+        }
+        let mut devices = std::collections::BTreeMap::<&str, SerializedDevice>::new();
+        for descriptor in &self.devices {
+            devices.entry(&descriptor.name).or_default().0 = Some(descriptor);
+        }
+        for (name, keymap) in self.device_keymaps.iter().filter(|(_, k)| !k.is_empty()) {
+            devices.entry(name).or_default().1 = Some(keymap);
+        }
+        if !devices.is_empty() {
+            config.serialize_field("devices", &devices)?;
+        }
+        if !self.profiles.is_empty() {
+            let profiles = self
+                .profiles
+                .iter()
+                .map(|(name, keymap)| (name, SerializedDevice(None, Some(keymap))))
+                .collect::<std::collections::BTreeMap<_, _>>();
+            config.serialize_field("profiles", &profiles)?;
+        }
+        config.end()
+    }
+}
+
+impl serde::Serialize for GestureSettings {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let bindings = self
+            .bindings
+            .iter()
+            .map(|(gesture, binding)| (gesture.to_string(), SerializedBinding(binding, false)))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("trigger", &gkey::format_gkey(self.trigger))?;
+        if self.threshold != gesture::DEFAULT_THRESHOLD {
+            map.serialize_entry("threshold", &self.threshold)?;
+        }
+        map.serialize_entry("bindings", &bindings)?;
+        map.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Toml,
+    Json,
+}
+
+impl Configuration {
+    /// Canonical TOML, which reads back to an identical configuration
+    pub fn to_toml_string(&self) -> Result<String, Box<dyn (::std::error::Error)>> {
+        // Going via `toml::Value` puts plain values ahead of tables, as TOML requires
+        Ok(toml::to_string(&toml::Value::try_from(self)?)?)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, Box<dyn (::std::error::Error)>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Substitutes `$VAR` and `${VAR}` with environment variables, and `$$` with `$`.
+///
+/// Unset variables, and anything else following a `$`, are left as written,
+/// so that commands run through a shell can still make use of them.
+pub fn expand_env(s: &str) -> String {
+    expand_env_with(s, |name| ::std::env::var(name).ok())
+}
+
+fn expand_env_with<F: Fn(&str) -> Option<String>>(s: &str, lookup: F) -> String {
+    let is_name_char = |c: char| c == '_' || c.is_ascii_alphanumeric();
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(index) = rest.find('$') {
+        out.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        let (name, consumed) = if after.starts_with('$') {
+            out.push('$');
+            rest = &after[1..];
+            continue;
+        } else if after.starts_with('{') {
+            match after.find('}') {
+                Some(end) if after[1..end].chars().all(is_name_char) => (&after[1..end], end + 1),
+                _ => ("", 0),
+            }
+        } else {
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        let starts_like_name = name
+            .chars()
+            .next()
+            .map(|c| !c.is_ascii_digit())
+            .unwrap_or(false);
+        match lookup(name) {
+            Some(value) if starts_like_name => out.push_str(&value),
+            _ => {
+                out.push('$');
+                out.push_str(&after[..consumed]);
+            }
+        }
+        rest = &after[consumed..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[test]
+fn test_expand_env() {
+    let lookup = |name: &str| match name {
+        "HOME" => Some("/home/zoey".to_string()),
+        "EMPTY" => Some(String::new()),
+        _ => None,
+    };
+    assert_eq!(
+        expand_env_with("ls $HOME/${HOME}x $EMPTY.", lookup),
+        "ls /home/zoey//home/zoeyx ."
+    );
+    assert_eq!(
+        expand_env_with("$UNSET $$HOME ${HOME:-x} $(date) $1 $", lookup),
+        "$UNSET $HOME ${HOME:-x} $(date) $1 $"
+    );
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BindingType {
     Command(String),
-    EmulateKey(xdo::Key),
+    EmulateKey(xdo::KeyCombo),
     EmulateMouse(u8),
     KeySequence(String),
+    Exec(exec::ExecSpec),
+    /// A command sent over i3 or sway's IPC socket
+    I3(String),
+    Volume(volume::VolumeAction),
+    /// Controls an MPRIS2 media player over D-Bus
+    Media(mpris::MediaAction),
+    /// Scrolls by `amount` clicks, down or right if positive, repeating every `repeat_ms` while held
+    Scroll {
+        axis: ScrollAxis,
+        amount: i32,
+        repeat_ms: Option<u32>,
+    },
+    /// Moves the pointer by `x` and `y`, or if not `relative`, to that position on `screen`
+    MovePointer {
+        x: i32,
+        y: i32,
+        relative: bool,
+        screen: u32,
+    },
+    Click {
+        button: u8,
+        count: u32,
+    },
+    /// Re-emits the key's own keypress, as if lg600r weren't there
+    Passthrough,
+    /// Switches to the profile of this name, or without one, back to no profile
+    Profile(Option<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScrollAxis {
+    Vertical,
+    Horizontal,
+}
+
+/// What a binding does, as told to whoever's using it, like `Volume +5%` or `Key ctrl+c`
+impl ::std::fmt::Display for BindingType {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            BindingType::Command(command) => write!(f, "{}", command),
+            BindingType::EmulateKey(key) => write!(f, "Key {}", key),
+            BindingType::EmulateMouse(button) => write!(f, "Mouse button {}", button),
+            BindingType::KeySequence(text) => write!(f, "Types \"{}\"", text),
+            BindingType::Exec(spec) => {
+                match &spec.target {
+                    exec::ExecTarget::Argv(argv) | exec::ExecTarget::ShellArgv { argv, .. } => {
+                        write!(f, "{}", argv.join(" "))?
+                    }
+                    exec::ExecTarget::Shell { command, .. } => write!(f, "{}", command)?,
+                }
+                if spec.detach {
+                    write!(f, " (detached)")?;
+                }
+                Ok(())
+            }
+            BindingType::I3(command) => write!(f, "i3: {}", command),
+            BindingType::Volume(action) => {
+                match action.operation {
+                    volume::VolumeOperation::Step(percent) => write!(f, "Volume {:+}%", percent)?,
+                    volume::VolumeOperation::Set(percent) => write!(f, "Volume {}%", percent)?,
+                    volume::VolumeOperation::Mute(None) => write!(f, "Toggle mute")?,
+                    volume::VolumeOperation::Mute(Some(true)) => write!(f, "Mute")?,
+                    volume::VolumeOperation::Mute(Some(false)) => write!(f, "Unmute")?,
+                }
+                match &action.device {
+                    device if *device == volume::VolumeDevice::default() => Ok(()),
+                    volume::VolumeDevice::Sink(name) => write!(f, " of {}", name),
+                    volume::VolumeDevice::Source(name) => write!(f, " of {}", name),
+                }
+            }
+            BindingType::Media(action) => {
+                write!(f, "Media {}", action.command.name())?;
+                if let mpris::MediaCommand::Seek(offset) = action.command {
+                    write!(f, " {:+}ms", offset)?;
+                }
+                match &action.player {
+                    Some(player) => write!(f, " on {}", player),
+                    None => Ok(()),
+                }
+            }
+            BindingType::Scroll { axis, amount, .. } => {
+                let direction = match (axis, *amount < 0) {
+                    (ScrollAxis::Vertical, false) => "down",
+                    (ScrollAxis::Vertical, true) => "up",
+                    (ScrollAxis::Horizontal, false) => "right",
+                    (ScrollAxis::Horizontal, true) => "left",
+                };
+                write!(f, "Scroll {} {}", direction, amount.abs())
+            }
+            BindingType::MovePointer {
+                x,
+                y,
+                relative: true,
+                ..
+            } => write!(f, "Move pointer by {}, {}", x, y),
+            BindingType::MovePointer { x, y, screen, .. } => {
+                write!(f, "Move pointer to {}, {}", x, y)?;
+                match screen {
+                    0 => Ok(()),
+                    screen => write!(f, " on screen {}", screen),
+                }
+            }
+            BindingType::Click { button, count } => match count {
+                1 => write!(f, "Click button {}", button),
+                count => write!(f, "Click button {} {} times", button, count),
+            },
+            BindingType::Passthrough => write!(f, "Pass through"),
+            BindingType::Profile(Some(name)) => write!(f, "Profile \"{}\"", name),
+            BindingType::Profile(None) => write!(f, "Back from profile"),
+        }
+    }
 }
 
 fn sval_as_uint(val: &serde_value::Value) -> Option<u64> {
+    use std::convert::TryFrom;
+    match val {
+        serde_value::Value::String(s) => s.parse::<u64>().ok(),
+        serde_value::Value::I8(i) => u64::try_from(*i).ok(),
+        serde_value::Value::I16(i) => u64::try_from(*i).ok(),
+        serde_value::Value::I32(i) => u64::try_from(*i).ok(),
+        serde_value::Value::I64(i) => u64::try_from(*i).ok(),
+        serde_value::Value::U8(i) => Some(u64::from(*i)),
+        serde_value::Value::U16(i) => Some(u64::from(*i)),
+        serde_value::Value::U32(i) => Some(u64::from(*i)),
+        serde_value::Value::U64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn sval_as_int(val: &serde_value::Value) -> Option<i64> {
+    use std::convert::TryFrom;
     match val {
-        serde_value::Value::String(s) => Some(s.parse::<u64>().unwrap()),
-        serde_value::Value::I8(i) => Some((*i) as u64),
-        serde_value::Value::I16(i) => Some((*i) as u64),
-        serde_value::Value::I32(i) => Some((*i) as u64),
-        serde_value::Value::I64(i) => Some((*i) as u64),
-        v => None,
+        serde_value::Value::String(s) => s.parse::<i64>().ok(),
+        serde_value::Value::I8(i) => Some(i64::from(*i)),
+        serde_value::Value::I16(i) => Some(i64::from(*i)),
+        serde_value::Value::I32(i) => Some(i64::from(*i)),
+        serde_value::Value::I64(i) => Some(*i),
+        serde_value::Value::U64(i) => i64::try_from(*i).ok(),
+        other => sval_as_uint(other).map(|i| i as i64),
     }
 }
 
-fn parse_binding(gkey: &String, token: &serde_value::Value) -> (u32, BindingType) {
-    let gkey = gkey.as_str().parse::<u32>().unwrap();
+fn table_field<'a>(
+    table: &'a std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+    field: &str,
+) -> Option<&'a serde_value::Value> {
+    table.get(&serde_value::Value::String(field.to_string()))
+}
+
+fn parse_key(name: &str) -> Result<xdo::KeyCombo, String> {
+    use std::str::FromStr;
+    xdo::KeyCombo::from_str(name).map_err(|name| {
+        match check::did_you_mean(&name, xdo::Key::all_names()) {
+            Some(suggestion) => {
+                format!("Unknown key \"{}\"; did you mean \"{}\"?", name, suggestion)
+            }
+            None => format!("Unknown key \"{}\"", name),
+        }
+    })
+}
+
+fn parse_exec(
+    table: &std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+) -> Result<exec::ExecSpec, String> {
+    use serde_value::Value;
+    let string = |field: &str| match table_field(table, field) {
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("Exec \"{}\" was a non-string value", field)),
+        None => Ok(None),
+    };
+
+    let target = match (table_field(table, "argv"), string("command")?) {
+        (Some(Value::Seq(items)), None) => {
+            let argv = items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(s.clone()),
+                    _ => Err("Exec \"argv\" must only contain strings".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if argv.is_empty() {
+                return Err("Exec \"argv\" must at least name a program".to_string());
+            }
+            match string("shell")? {
+                Some(shell) => exec::ExecTarget::ShellArgv { shell, argv },
+                None => exec::ExecTarget::Argv(argv),
+            }
+        }
+        (Some(_), None) => return Err("Exec \"argv\" must be an array of strings".to_string()),
+        (None, Some(command)) => exec::ExecTarget::Shell {
+            shell: string("shell")?.unwrap_or_else(|| "sh".to_string()),
+            command,
+        },
+        (Some(_), Some(_)) => {
+            return Err(
+                "Exec bindings take either an \"argv\" or a \"command\", not both".to_string(),
+            )
+        }
+        (None, None) => return Err("Exec binding needs an \"argv\" or a \"command\"".to_string()),
+    };
+
+    let env = match table_field(table, "env") {
+        Some(Value::Map(vars)) => vars
+            .iter()
+            .map(|(name, value)| match (name, value) {
+                (Value::String(name), Value::String(value)) => Ok((name.clone(), value.clone())),
+                _ => Err(format!("Exec \"env\" entry {:?} must be a string", name)),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err("Exec \"env\" must be a table of strings".to_string()),
+        None => Default::default(),
+    };
+
+    let detach = match table_field(table, "detach") {
+        Some(Value::Bool(detach)) => *detach,
+        Some(_) => return Err("Exec \"detach\" must be true or false".to_string()),
+        None => false,
+    };
+
+    Ok(exec::ExecSpec {
+        target,
+        env,
+        cwd: string("cwd")?,
+        detach,
+    })
+}
+
+fn parse_volume(
+    table: &std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+) -> Result<volume::VolumeAction, String> {
+    use serde_value::Value;
+    let percent = |field: &str, min: i64| -> Result<Option<i64>, String> {
+        match table_field(table, field) {
+            None => Ok(None),
+            Some(value) => match sval_as_int(value) {
+                Some(p) if p >= min && p <= 100 => Ok(Some(p)),
+                _ => Err(format!(
+                    "Volume \"{}\" must be a percentage from {} to 100",
+                    field, min
+                )),
+            },
+        }
+    };
+    let string = |field: &str| match table_field(table, field) {
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("Volume \"{}\" was a non-string value", field)),
+        None => Ok(None),
+    };
+
+    let mute = match table_field(table, "mute") {
+        None => None,
+        Some(Value::Bool(mute)) => Some(Some(*mute)),
+        Some(Value::String(toggle)) if toggle == "toggle" => Some(None),
+        Some(_) => return Err("Volume \"mute\" must be true, false or \"toggle\"".to_string()),
+    };
+    let operation = match (percent("step", -100)?, percent("set", 0)?, mute) {
+        (Some(step), None, None) => volume::VolumeOperation::Step(step as i32),
+        (None, Some(set), None) => volume::VolumeOperation::Set(set as u32),
+        (None, None, Some(mute)) => volume::VolumeOperation::Mute(mute),
+        (None, None, None) => {
+            return Err("Volume binding needs a \"step\", \"set\" or \"mute\"".to_string())
+        }
+        _ => {
+            return Err(
+                "Volume bindings take only one of \"step\", \"set\" and \"mute\"".to_string(),
+            )
+        }
+    };
+
+    let device = match (string("sink")?, string("source")?) {
+        (None, None) => volume::VolumeDevice::default(),
+        (Some(sink), None) => volume::VolumeDevice::Sink(sink),
+        (None, Some(source)) => volume::VolumeDevice::Source(source),
+        (Some(_), Some(_)) => {
+            return Err("Volume bindings take either a \"sink\" or a \"source\"".to_string())
+        }
+    };
+
+    let repeat_ms = match table_field(table, "repeat") {
+        None => None,
+        Some(value) => match sval_as_uint(value) {
+            Some(ms) if ms >= 1 && ms <= u64::from(u32::max_value()) => Some(ms as u32),
+            _ => {
+                return Err(
+                    "Volume \"repeat\" must be a positive number of milliseconds".to_string(),
+                )
+            }
+        },
+    };
+
+    Ok(volume::VolumeAction {
+        operation,
+        device,
+        repeat_ms,
+        fallback: string("fallback")?,
+    })
+}
+
+fn parse_media(
+    table: &std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+) -> Result<mpris::MediaAction, String> {
+    use serde_value::Value;
+    let command = match table_field(table, "action") {
+        Some(Value::String(name)) => match name.as_str() {
+            "play-pause" => mpris::MediaCommand::PlayPause,
+            "play" => mpris::MediaCommand::Play,
+            "pause" => mpris::MediaCommand::Pause,
+            "stop" => mpris::MediaCommand::Stop,
+            "next" => mpris::MediaCommand::Next,
+            "previous" => mpris::MediaCommand::Previous,
+            "raise" => mpris::MediaCommand::Raise,
+            "seek" => match table_field(table, "offset").map(sval_as_int) {
+                Some(Some(offset)) => mpris::MediaCommand::Seek(offset),
+                Some(None) => return Err("Media \"offset\" must be a whole number".to_string()),
+                None => {
+                    return Err(
+                        "Seeking needs an \"offset\" in milliseconds, negative to rewind"
+                            .to_string(),
+                    )
+                }
+            },
+            other => {
+                return Err(
+                    match check::did_you_mean(other, mpris::MediaCommand::NAMES.iter().cloned()) {
+                        Some(suggestion) => format!(
+                            "Unknown media action \"{}\"; did you mean \"{}\"?",
+                            other, suggestion
+                        ),
+                        None => format!("Unknown media action \"{}\"", other),
+                    },
+                )
+            }
+        },
+        Some(_) => return Err("Media action was a non-string value".to_string()),
+        None => return Err("Media binding is missing its \"action\"".to_string()),
+    };
+    let player = match table_field(table, "player") {
+        Some(Value::String(player)) => Some(player.clone()),
+        Some(_) => return Err("Media player was a non-string value".to_string()),
+        None => None,
+    };
+    Ok(mpris::MediaAction { command, player })
+}
+
+/// Reads an integer field within `range`, or `default` if it's absent
+fn int_field<T: std::convert::TryFrom<i64>>(
+    table: &std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+    kind: &str,
+    field: &str,
+    range: ::std::ops::RangeInclusive<i64>,
+    default: Option<T>,
+) -> Result<T, String> {
+    use std::convert::TryFrom;
+    match table_field(table, field) {
+        None => default.ok_or_else(|| format!("{} binding is missing its \"{}\"", kind, field)),
+        Some(value) => sval_as_int(value)
+            .filter(|n| range.contains(n))
+            .and_then(|n| T::try_from(n).ok())
+            .ok_or_else(|| {
+                format!(
+                    "{} \"{}\" must be a whole number from {} to {}",
+                    kind,
+                    field,
+                    range.start(),
+                    range.end()
+                )
+            }),
+    }
+}
+
+fn parse_pointer_binding(
+    kind: &str,
+    table: &std::collections::BTreeMap<serde_value::Value, serde_value::Value>,
+) -> Result<BindingType, String> {
+    use serde_value::Value;
+    let int = i64::from(i32::min_value())..=i64::from(i32::max_value());
+    let positive = 1..=i64::from(u32::max_value());
+    let flag = |field: &str| match table_field(table, field) {
+        Some(Value::Bool(flag)) => Ok(Some(*flag)),
+        Some(_) => Err(format!("{} \"{}\" must be true or false", kind, field)),
+        None => Ok(None),
+    };
+    Ok(match kind {
+        "Scroll" => BindingType::Scroll {
+            axis: match table_field(table, "axis") {
+                None => ScrollAxis::Vertical,
+                Some(Value::String(axis)) if axis == "vertical" => ScrollAxis::Vertical,
+                Some(Value::String(axis)) if axis == "horizontal" => ScrollAxis::Horizontal,
+                Some(_) => {
+                    return Err("Scroll \"axis\" must be \"vertical\" or \"horizontal\"".to_string())
+                }
+            },
+            amount: int_field(table, kind, "amount", int, None)?,
+            repeat_ms: match table_field(table, "repeat") {
+                None => None,
+                Some(_) => Some(int_field(table, kind, "repeat", positive, None)?),
+            },
+        },
+        "Move" => {
+            let relative = flag("relative")?.unwrap_or(false);
+            if relative && table_field(table, "screen").is_some() {
+                return Err("Relative moves don't take a \"screen\"".to_string());
+            }
+            BindingType::MovePointer {
+                x: int_field(table, kind, "x", int.clone(), None)?,
+                y: int_field(table, kind, "y", int, None)?,
+                relative,
+                screen: int_field(
+                    table,
+                    kind,
+                    "screen",
+                    0..=i64::from(u32::max_value()),
+                    Some(0),
+                )?,
+            }
+        }
+        _ => BindingType::Click {
+            button: int_field(
+                table,
+                kind,
+                "button",
+                1..=i64::from(u8::max_value()),
+                Some(1),
+            )?,
+            count: int_field(table, kind, "count", positive, Some(1))?,
+        },
+    })
+}
+
+fn parse_binding(gkey: u32, token: &serde_value::Value) -> Result<(u32, BindingType), String> {
     use serde_value::Value;
-    use std::convert::{TryFrom, TryInto};
     let binding = match token {
         Value::String(s) => BindingType::Command(s.clone()),
-        Value::Map(table) => match table.get(&Value::String("type".to_string())).unwrap() {
-            Value::String(s) => match s.as_ref() {
+        Value::Map(table) => match table_field(table, "type") {
+            _ if binding_notifies(token).is_err() => {
+                return Err("Binding \"notify\" must be true or false".to_string())
+            }
+            Some(Value::String(s)) => match s.as_ref() {
+                "command" => match table_field(table, "command") {
+                    Some(Value::String(command)) => BindingType::Command(command.clone()),
+                    Some(_) => return Err("Command was a non-string value".to_string()),
+                    None => return Err("Command binding is missing its \"command\"".to_string()),
+                },
                 "mouse" => {
-                    let val_at_button = table.get(&Value::String("button".to_string())).unwrap();
-                    let btn: u8 = sval_as_uint(val_at_button)
-                        .map(|x| x as u8)
-                        .expect("Invalid button value");
-                    BindingType::EmulateMouse(btn)
-                }
-                "keyboard" => {
-                    if let Value::String(source_str) =
-                        table.get(&Value::String("key".to_string())).unwrap()
-                    {
-                        use std::str::FromStr;
-                        let key: Result<xdo::Key, _> = FromStr::from_str(&source_str);
-                        let key = match key {
-                            Ok(key) => key,
-                            Err(e) => {
-                                panic!("Failed to parse {} as a key; err: {}", source_str, &e)
-                            }
-                        };
-                        BindingType::EmulateKey(key)
-                    } else {
-                        panic!("Key was a non-string value")
+                    let btn = table_field(table, "button")
+                        .ok_or_else(|| "Mouse binding is missing a \"button\"".to_string())?;
+                    match sval_as_uint(btn) {
+                        Some(b) if b >= 1 && b <= u64::from(u8::max_value()) => {
+                            BindingType::EmulateMouse(b as u8)
+                        }
+                        parsed => {
+                            return Err(format!(
+                                "Invalid mouse button {}; buttons are numbered from 1 to {}",
+                                parsed
+                                    .map(|b| b.to_string())
+                                    .unwrap_or_else(|| format!("{:?}", btn)),
+                                u8::max_value()
+                            ))
+                        }
                     }
                 }
-                _ => unreachable!(),
+                "keyboard" => match table_field(table, "key") {
+                    Some(Value::String(source_str)) => {
+                        BindingType::EmulateKey(parse_key(source_str)?)
+                    }
+                    Some(_) => return Err("Key was a non-string value".to_string()),
+                    None => return Err("Keyboard binding is missing a \"key\"".to_string()),
+                },
+                "exec" => BindingType::Exec(parse_exec(table)?),
+                "volume" => BindingType::Volume(parse_volume(table)?),
+                "media" => BindingType::Media(parse_media(table)?),
+                "scroll" => parse_pointer_binding("Scroll", table)?,
+                "move" => parse_pointer_binding("Move", table)?,
+                "click" => parse_pointer_binding("Click", table)?,
+                "passthrough" => BindingType::Passthrough,
+                "profile" => match table_field(table, "name") {
+                    Some(Value::String(name)) => BindingType::Profile(Some(name.clone())),
+                    Some(_) => return Err("Profile name was a non-string value".to_string()),
+                    None => BindingType::Profile(None),
+                },
+                "i3" => match table_field(table, "command") {
+                    Some(Value::String(command)) => BindingType::I3(command.clone()),
+                    Some(_) => return Err("i3 command was a non-string value".to_string()),
+                    None => return Err("i3 binding is missing its \"command\"".to_string()),
+                },
+                "sequence" => match table_field(table, "text") {
+                    Some(Value::String(text)) => BindingType::KeySequence(text.clone()),
+                    Some(_) => return Err("Sequence text was a non-string value".to_string()),
+                    None => return Err("Sequence binding is missing its \"text\"".to_string()),
+                },
+                other => return Err(format!("Unknown binding type \"{}\"", other)),
             },
-            _ => unreachable!(),
+            Some(_) => return Err("Binding type was a non-string value".to_string()),
+            None => return Err("Binding table is missing a \"type\"".to_string()),
         },
-        _ => unreachable!(),
+        _ => {
+            return Err("Bindings must be a command string or a table with a \"type\"".to_string())
+        }
     };
 
-    (gkey, binding)
+    Ok((gkey, binding))
+}
+
+/// Whether a binding asks for a notification whenever it's used
+fn binding_notifies(token: &serde_value::Value) -> Result<bool, ()> {
+    use serde_value::Value;
+    match token {
+        Value::Map(table) => match table_field(table, "notify") {
+            Some(Value::Bool(notify)) => Ok(*notify),
+            Some(_) => Err(()),
+            None => Ok(false),
+        },
+        _ => Ok(false),
+    }
+}
+
+/// Parses a scancode, given as a number or as the name of the evdev key it stands for
+fn parse_scancode(value: &serde_value::Value) -> Option<u32> {
+    use std::convert::TryFrom;
+    match value {
+        serde_value::Value::String(name) if name.contains('_') => {
+            device::keycode(name).map(device::keycode_scancode)
+        }
+        value => sval_as_uint(value).and_then(|x| u32::try_from(x).ok()),
+    }
+}
+
+/// Undoes TOML's interpretation of a bare `shift.G9 = ...` key as a `shift` table containing `G9`
+fn flatten_shift_tables<
+    'a,
+    F: Fn(&std::collections::BTreeMap<serde_value::Value, serde_value::Value>) -> bool,
+>(
+    table: &'a std::collections::BTreeMap<String, serde_value::Value>,
+    is_shift_table: F,
+) -> Vec<(String, &'a serde_value::Value)> {
+    use serde_value::Value;
+    let mut entries = Vec::new();
+    for (key, value) in table {
+        match value {
+            Value::Map(inner) if key == "shift" && is_shift_table(inner) => {
+                for (inner_key, inner_value) in inner {
+                    match inner_key {
+                        Value::String(k) => entries.push((format!("shift.{}", k), inner_value)),
+                        _ => entries.push((format!("shift.{:?}", inner_key), inner_value)),
+                    }
+                }
+            }
+            _ => entries.push((key.clone(), value)),
+        }
+    }
+    entries
+}
+
+/// Resolves symbolic G-key names, rejecting keys which name the same G-key twice
+fn resolve_gkeys<T>(entries: Vec<(String, T)>) -> Result<Vec<(u32, T)>, String> {
+    let mut seen = std::collections::BTreeMap::<u32, String>::new();
+    let mut resolved = Vec::with_capacity(entries.len());
+    for (name, value) in entries {
+        let gkey = gkey::parse_gkey(&name)?;
+        if let Some(previous) = seen.insert(gkey, name.clone()) {
+            return Err(format!(
+                "\"{}\" and \"{}\" both refer to {}",
+                previous,
+                name,
+                gkey::format_gkey(gkey)
+            ));
+        }
+        resolved.push((gkey, value));
+    }
+    Ok(resolved)
+}
+
+/// Parses the G-key that gestures are drawn while holding, given by name or number
+fn parse_trigger(value: &serde_value::Value) -> Result<u32, String> {
+    use std::convert::TryFrom;
+    match value {
+        serde_value::Value::String(name) => gkey::parse_gkey(name),
+        other => sval_as_uint(other)
+            .and_then(|gkey| u32::try_from(gkey).ok())
+            .ok_or_else(|| "Gesture \"trigger\" must be a G-key".to_string()),
+    }
+}
+
+fn parse_gesture_binding(
+    trigger: u32,
+    name: &str,
+    token: &serde_value::Value,
+) -> Result<(gesture::Gesture, BindingType), String> {
+    use std::str::FromStr;
+    let drawn = gesture::Gesture::from_str(name)?;
+    let (_, binding) = parse_binding(trigger, token)
+        .map_err(|e| format!("Invalid binding for gesture \"{}\": {}", drawn, e))?;
+    Ok((drawn, binding))
+}
+
+fn parse_gestures(igestures: &IntermedGestures) -> Result<GestureSettings, String> {
+    let trigger = parse_trigger(&igestures.trigger)?;
+    let threshold = match igestures.threshold {
+        Some(0) => return Err("Gesture \"threshold\" must be at least 1".to_string()),
+        Some(threshold) => threshold,
+        None => gesture::DEFAULT_THRESHOLD,
+    };
+    let mut bindings: Vec<(gesture::Gesture, BindingType)> = Vec::new();
+    for (name, token) in &igestures.bindings {
+        let (drawn, binding) = parse_gesture_binding(trigger, name, token)?;
+        if bindings.iter().any(|(existing, _)| *existing == drawn) {
+            return Err(format!("Gesture \"{}\" is bound more than once", drawn));
+        }
+        bindings.push((drawn, binding));
+    }
+    Ok(GestureSettings {
+        trigger,
+        threshold,
+        bindings,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntermedGestures {
+    trigger: serde_value::Value,
+    #[serde(default)]
+    threshold: Option<u32>,
+    #[serde(default)]
+    bindings: std::collections::BTreeMap<String, serde_value::Value>,
+}
+
+/// Parses an unshifted G-key naming one of a device's buttons
+fn parse_button(name: &str) -> Result<u32, String> {
+    match gkey::parse_gkey(name)? {
+        gkey if gkey >= gkey::GSHIFT_OFFSET => Err(format!(
+            "Device buttons are unshifted G-keys, unlike \"{}\"",
+            name
+        )),
+        gkey => Ok(gkey),
+    }
+}
+
+/// Parses a device's description; a table which only holds bindings describes nothing
+fn parse_device(
+    name: &str,
+    idevice: &IntermedDevice,
+) -> Result<Option<device::DeviceDescriptor>, String> {
+    let in_device = |e: String| format!("In device \"{}\": {}", name, e);
+    let described = idevice.title.is_some()
+        || idevice.vendor.is_some()
+        || idevice.product.is_some()
+        || idevice.prefix.is_some()
+        || idevice.keyboard.is_some()
+        || idevice.pointer.is_some()
+        || idevice.scancode_mask.is_some()
+        || !idevice.buttons.is_empty()
+        || !idevice.layout.is_empty();
+    if !described {
+        return Ok(None);
+    }
+    // Describing a built-in device only changes what's given
+    let base = device::builtin()
+        .into_iter()
+        .find(|builtin| builtin.name == name);
+    let buttons = idevice
+        .buttons
+        .iter()
+        .map(|(button, label)| Ok((parse_button(button)?, label.clone())))
+        .collect::<Result<std::collections::BTreeMap<_, _>, String>>()
+        .map_err(in_device)?;
+    let layout = idevice
+        .layout
+        .iter()
+        .map(|row| row.iter().map(|button| parse_button(button)).collect())
+        .collect::<Result<Vec<Vec<_>>, String>>()
+        .map_err(in_device)?;
+    let base = match (base, &idevice.prefix, idevice.vendor) {
+        (Some(base), _, _) => base,
+        (None, None, None) => {
+            return Err(in_device(
+                "A \"vendor\" or a \"prefix\" is needed to recognise it".to_string(),
+            ))
+        }
+        (None, prefix, _) => device::DeviceDescriptor {
+            name: name.to_string(),
+            title: name.to_string(),
+            vendor: None,
+            product: None,
+            prefix: prefix.clone().unwrap_or_default(),
+            keyboard: device::DEFAULT_KEYBOARD_INTERFACE.to_string(),
+            pointer: None,
+            scancode_mask: None,
+            buttons: std::collections::BTreeMap::new(),
+            layout: Vec::new(),
+        },
+    };
+    Ok(Some(device::DeviceDescriptor {
+        title: idevice.title.clone().unwrap_or(base.title),
+        vendor: idevice.vendor.or(base.vendor),
+        product: idevice.product.or(base.product),
+        prefix: idevice.prefix.clone().unwrap_or(base.prefix),
+        keyboard: idevice.keyboard.clone().unwrap_or(base.keyboard),
+        pointer: idevice.pointer.clone().or(base.pointer),
+        scancode_mask: idevice.scancode_mask.or(base.scancode_mask),
+        buttons: if buttons.is_empty() {
+            base.buttons
+        } else {
+            buttons
+        },
+        layout: if layout.is_empty() {
+            base.layout
+        } else {
+            layout
+        },
+        ..base
+    }))
+}
+
+/// Parses bindings and scancodes, as found at the top level or in a device's table
+fn parse_keymap(
+    bindings_table: &std::collections::BTreeMap<String, serde_value::Value>,
+    scancodes_table: &std::collections::BTreeMap<String, serde_value::Value>,
+) -> Result<DeviceKeymap, String> {
+    let entries = resolve_gkeys(binding_entries(bindings_table))?;
+    let bindings: Vec<(u32, BindingType)> = entries
+        .iter()
+        .map(|(gkey, val)| parse_binding(*gkey, val))
+        .collect::<Result<_, _>>()?;
+    let notify = entries
+        .iter()
+        .filter(|(_, val)| binding_notifies(val) == Ok(true))
+        .map(|(gkey, _)| *gkey)
+        .collect();
+
+    let scancodes: Vec<(u32, u32)> = resolve_gkeys(scancode_entries(scancodes_table))?
+        .into_iter()
+        .map(|(gkey, value)| {
+            parse_scancode(value)
+                .map(|scancode| (gkey, scancode))
+                .ok_or_else(|| format!("Invalid scancode for {}", gkey::format_gkey(gkey)))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(DeviceKeymap {
+        bindings,
+        scancodes,
+        notify,
+    })
+}
+
+fn parse_chords(
+    ichords: &std::collections::BTreeMap<String, serde_value::Value>,
+) -> Result<Vec<(chord::Chord, BindingType)>, String> {
+    use std::str::FromStr;
+    let mut chords: Vec<(chord::Chord, BindingType)> = Vec::new();
+    for (name, token) in ichords {
+        let chord = chord::Chord::from_str(name)?;
+        if chords.iter().any(|(existing, _)| *existing == chord) {
+            return Err(format!("Chord \"{}\" is bound more than once", chord));
+        }
+        let (_, last) = chord.0[chord.0.len() - 1];
+        let (_, binding) = parse_binding(last, token)
+            .map_err(|e| format!("Invalid binding for chord \"{}\": {}", chord, e))?;
+        chords.push((chord, binding));
+    }
+    Ok(chords)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntermedDevice {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    vendor: Option<u16>,
+    #[serde(default)]
+    product: Option<u16>,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    keyboard: Option<String>,
+    #[serde(default)]
+    pointer: Option<String>,
+    #[serde(default)]
+    scancode_mask: Option<u32>,
+    #[serde(default)]
+    buttons: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    layout: Vec<Vec<String>>,
+    #[serde(default)]
+    bindings: std::collections::BTreeMap<String, serde_value::Value>,
+    #[serde(default)]
+    scancodes: std::collections::BTreeMap<String, serde_value::Value>,
+}
+
+/// Parses what `device` selects: a device, by its name or by a table picking it out, or a list
+fn parse_device_selection(value: &serde_value::Value) -> Result<Vec<device::Selector>, String> {
+    let selector = |value: &serde_value::Value| {
+        match value {
+        serde_value::Value::String(name) => Ok(device::Selector::named(name)),
+        serde_value::Value::Map(_) => value
+            .clone()
+            .deserialize_into()
+            .map_err(|e| format!("Invalid device selection: {}", e)),
+        _ => Err("A device is selected by its name, or a table like { name = \"g600\", serial = \"...\" }".to_string()),
+    }
+    };
+    match value {
+        serde_value::Value::Seq(values) => values.iter().map(selector).collect(),
+        value => selector(value).map(|selector| vec![selector]),
+    }
+}
+
+/// The keys a config file may have at its top level, which `IntermedConfig` reads
+const CONFIG_KEYS: &[&str] = &[
+    "include",
+    "bindings",
+    "scancodes",
+    "notifications",
+    "gestures",
+    "pointer",
+    "keyboard",
+    "device",
+    "devices",
+    "chords",
+    "layers",
+    "profiles",
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntermedConfig {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    bindings: std::collections::BTreeMap<String, serde_value::Value>,
+    #[serde(default)]
+    scancodes: std::collections::BTreeMap<String, serde_value::Value>,
+    #[serde(default)]
+    notifications: Option<notify::NotificationSettings>,
+    #[serde(default)]
+    gestures: Option<IntermedGestures>,
+    #[serde(default)]
+    pointer: Option<pointer_watcher::PointerSettings>,
+    #[serde(default)]
+    keyboard: Option<keyboard_watcher::KeyboardSettings>,
+    #[serde(default)]
+    device: Option<serde_value::Value>,
+    #[serde(default)]
+    devices: std::collections::BTreeMap<String, IntermedDevice>,
+    #[serde(default)]
+    chords: std::collections::BTreeMap<String, serde_value::Value>,
+    #[serde(default)]
+    layers: Option<LayerSettings>,
+    #[serde(default)]
+    profiles: std::collections::BTreeMap<String, IntermedProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntermedProfile {
+    #[serde(default)]
+    bindings: std::collections::BTreeMap<String, serde_value::Value>,
+    #[serde(default)]
+    scancodes: std::collections::BTreeMap<String, serde_value::Value>,
+}
+
+fn binding_entries(
+    table: &std::collections::BTreeMap<String, serde_value::Value>,
+) -> Vec<(String, &serde_value::Value)> {
+    // A `shift` table is only a binding if it says which type of binding it is
+    flatten_shift_tables(table, |table| table_field(table, "type").is_none())
+}
+
+fn scancode_entries(
+    table: &std::collections::BTreeMap<String, serde_value::Value>,
+) -> Vec<(String, &serde_value::Value)> {
+    flatten_shift_tables(table, |_| true)
+}
+
+impl IntermedConfig {
+    fn binding_entries(&self) -> Vec<(String, &serde_value::Value)> {
+        binding_entries(&self.bindings)
+    }
+
+    fn scancode_entries(&self) -> Vec<(String, &serde_value::Value)> {
+        scancode_entries(&self.scancodes)
+    }
 }
 
 fn parse_config_from_toml_string(
     tomlstr: &String,
 ) -> Result<Configuration, Box<dyn (::std::error::Error)>> {
-    #[derive(Debug)]
-    struct BindingWrapper(BindingType);
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
-    impl serde::Serialize for BindingWrapper {
-        fn serialize<S>(
-            &self,
-            serializer: S,
-        ) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
-        where
-            S: Serializer,
-        {
-            let BindingWrapper(inner) = self;
-            match inner {
-                BindingType::Command(cmd) => {
-                    //                    Serialize::serialize()
-                    unimplemented!()
-                }
-                _ => unimplemented!(),
-            }
-        }
-    }
-    #[derive(Deserialize)]
-    struct IntermedConfig {
-        bindings: std::collections::BTreeMap<String, serde_value::Value>,
-        scancodes: std::collections::btree_map::BTreeMap<serde_value::Value, serde_value::Value>,
-    }
     let icfg: IntermedConfig = toml::from_str(tomlstr)?;
-    println!("config.bindings: {:#?}", &icfg.bindings);
-    let bindings: Vec<(u32, BindingType)> = icfg
-        .bindings
-        .iter()
-        .map(|(key, val)| parse_binding(key, val))
-        .collect();
+    configuration_from_intermed(&icfg)
+}
+
+fn configuration_from_intermed(
+    icfg: &IntermedConfig,
+) -> Result<Configuration, Box<dyn (::std::error::Error)>> {
+    let DeviceKeymap {
+        bindings,
+        scancodes,
+        notify,
+    } = parse_keymap(&icfg.bindings, &icfg.scancodes)?;
+    let mut devices = Vec::new();
+    let mut device_keymaps = Vec::new();
+    for (name, idevice) in &icfg.devices {
+        devices.extend(parse_device(name, idevice)?);
+        let keymap = parse_keymap(&idevice.bindings, &idevice.scancodes)
+            .map_err(|e| format!("In device \"{}\": {}", name, e))?;
+        if !keymap.is_empty() {
+            device_keymaps.push((name.clone(), keymap));
+        }
+    }
 
-    let scancodes: Vec<(u32, u32)> = icfg
-        .scancodes
+    let profiles = icfg
+        .profiles
         .iter()
-        .map(|(key, value)| {
-            (
-                sval_as_uint(key)
-                    .map(|x| x as u32)
-                    .expect("Invalid type in scancode key"),
-                sval_as_uint(value)
-                    .map(|x| x as u32)
-                    .expect("Invalid type in scancode value"),
-            )
+        .map(|(name, iprofile)| {
+            parse_keymap(&iprofile.bindings, &iprofile.scancodes)
+                .map(|keymap| (name.clone(), keymap))
+                .map_err(|e| format!("In profile \"{}\": {}", name, e))
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
     Ok(Configuration {
         bindings,
         scancodes,
+        notify,
+        notifications: icfg.notifications.clone(),
+        gestures: icfg.gestures.as_ref().map(parse_gestures).transpose()?,
+        pointer: icfg.pointer.clone(),
+        keyboard: icfg.keyboard.clone(),
+        device: icfg
+            .device
+            .as_ref()
+            .map(parse_device_selection)
+            .transpose()?
+            .unwrap_or_default(),
+        devices,
+        device_keymaps,
+        chords: parse_chords(&icfg.chords)?,
+        layers: icfg.layers.clone(),
+        profiles,
     })
 }
 
@@ -210,7 +1640,7 @@ fn test_parse_config() -> () {
     assert_eq!(res.bindings[1], (113u32, BindingType::EmulateMouse(9)));
     assert_eq!(
         res.bindings[2],
-        (115u32, BindingType::EmulateKey(crate::xdo::Key::F1))
+        (115u32, BindingType::EmulateKey(crate::xdo::Key::F1.into()))
     );
     assert_eq!(
         res.bindings[3],
@@ -218,18 +1648,557 @@ fn test_parse_config() -> () {
     );
 }
 
+#[test]
+fn test_parse_symbolic_gkeys() {
+    let input = r#"
+        [bindings]
+        G9 = "nine"
+        "G^10" = "shifted ten"
+        shift.G11 = "shifted eleven"
+        middle = { type = "mouse", button = 2 }
+
+        [scancodes]
+        G9 = 30
+        110 = 17
+        shift.G11 = 12
+        shift.gshift = 99
+    "#;
+    let res = parse_config_from_toml_string(&String::from(input)).expect("Must pass");
+    assert_eq!(
+        res.bindings,
+        vec![
+            (9u32, BindingType::Command("nine".to_string())),
+            (110u32, BindingType::Command("shifted ten".to_string())),
+            (3u32, BindingType::EmulateMouse(2)),
+            (111u32, BindingType::Command("shifted eleven".to_string())),
+        ]
+    );
+    assert_eq!(
+        res.scancodes,
+        vec![(110, 17), (9, 30), (111, 12), (106, 99)]
+    );
+
+    let duplicated = r#"
+        [bindings]
+        G9 = "nine"
+        009 = "also nine"
+
+        [scancodes]
+    "#;
+    let err = parse_config_from_toml_string(&String::from(duplicated)).unwrap_err();
+    assert!(err.to_string().contains("both refer to G9"));
+}
+
+#[test]
+fn test_parse_exec() {
+    let input = r#"
+        [bindings]
+        G8 = { type = "exec", argv = ["echo", "a b; rm x"], cwd = "~/tmp" }
+        G9 = { type = "exec", shell = "bash", command = "echo $$HOME", env = { A = "b" } }
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    let mut env = std::collections::BTreeMap::new();
+    env.insert("A".to_string(), "b".to_string());
+    assert_eq!(
+        config.bindings,
+        vec![
+            (
+                8,
+                BindingType::Exec(exec::ExecSpec {
+                    target: exec::ExecTarget::Argv(vec![
+                        "echo".to_string(),
+                        "a b; rm x".to_string()
+                    ]),
+                    env: Default::default(),
+                    cwd: Some("~/tmp".to_string()),
+                    detach: false,
+                })
+            ),
+            (
+                9,
+                BindingType::Exec(exec::ExecSpec {
+                    target: exec::ExecTarget::Shell {
+                        shell: "bash".to_string(),
+                        command: "echo $$HOME".to_string(),
+                    },
+                    env,
+                    cwd: None,
+                    detach: false,
+                })
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_exec_argv_with_shell_runs() {
+    let input = r#"
+        [bindings]
+        G9 = { type = "exec", argv = ["test", "a b; false", "=", "a b; false"], shell = "sh" }
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    match &config.bindings[..] {
+        [(9, BindingType::Exec(spec))] => {
+            assert_eq!(
+                spec.target,
+                exec::ExecTarget::ShellArgv {
+                    shell: "sh".to_string(),
+                    argv: vec![
+                        "test".to_string(),
+                        "a b; false".to_string(),
+                        "=".to_string(),
+                        "a b; false".to_string()
+                    ],
+                }
+            );
+            assert_eq!(exec::run(spec), Ok(()));
+        }
+        bindings => panic!("Unexpected bindings {:?}", bindings),
+    }
+}
+
+#[test]
+fn test_display_bindings() {
+    let input = r#"
+        [bindings]
+        G1 = "ls -l"
+        G2 = { type = "keyboard", key = "ctrl+c" }
+        G3 = { type = "volume", step = -5, source = "mic" }
+        G4 = { type = "media", action = "seek", offset = 5000, player = "vlc" }
+        G5 = { type = "scroll", amount = -3 }
+        G6 = { type = "click", button = 1, count = 2 }
+        G7 = { type = "exec", argv = ["notify-send", "hi"], detach = true }
+        G8 = { type = "profile", name = "games" }
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    assert_eq!(
+        config
+            .bindings
+            .iter()
+            .map(|(_, binding)| binding.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "ls -l",
+            "Key ctrl+c",
+            "Volume -5% of mic",
+            "Media seek +5000ms on vlc",
+            "Scroll up 3",
+            "Click button 1 2 times",
+            "notify-send hi (detached)",
+            "Profile \"games\"",
+        ]
+    );
+}
+
 fn load_dotfile_contents(dotfilepath: &::std::path::Path) -> ::std::io::Result<String> {
-    assert!(dotfilepath.exists());
     use std::io::prelude::*;
-    let mut f = ::std::fs::OpenOptions::new().read(true).open(dotfilepath)?;
+    let mut f = ::std::fs::OpenOptions::new()
+        .read(true)
+        .open(dotfilepath)
+        .map_err(|e| {
+            ::std::io::Error::new(
+                e.kind(),
+                format!("Couldn't read \"{}\": {}", dotfilepath.to_string_lossy(), e),
+            )
+        })?;
     let mut contents = String::new();
     f.read_to_string(&mut contents)?;
     Ok(contents)
 }
 
-pub fn load_configuration_from_dotfile(
+/// Expands a leading `~/` in a path to the home directory
+pub fn expand_home(path: &str) -> ::std::path::PathBuf {
+    match (path.starts_with("~/"), dirs::home_dir()) {
+        (true, Some(home)) => home.join(&path[2..]),
+        _ => ::std::path::PathBuf::from(path),
+    }
+}
+
+/// Expands environment variables and a leading `~/` in a path
+fn expand_path(path: &str) -> ::std::path::PathBuf {
+    expand_home(&expand_env(path))
+}
+
+/// Resolves an include relative to the directory of the file including it
+fn resolve_include(including: &::std::path::Path, include: &str) -> ::std::path::PathBuf {
+    let path = expand_path(include);
+    match including.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
+/// Loads a config file after the files it includes.
+///
+/// Includes are merged depth-first in the order they're listed, so later includes override
+/// earlier ones, and the including file overrides everything it includes.
+fn load_with_includes(
     dotfilepath: &::std::path::Path,
+    stack: &mut Vec<::std::path::PathBuf>,
 ) -> Result<Configuration, Box<dyn (::std::error::Error)>> {
+    let canonical = dotfilepath
+        .canonicalize()
+        .unwrap_or_else(|_| dotfilepath.to_path_buf());
+    if stack.contains(&canonical) {
+        let cycle = stack
+            .iter()
+            .chain(::std::iter::once(&canonical))
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        return Err(format!("Config files include each other: {}", cycle.join(" -> ")).into());
+    }
+
     let contents = load_dotfile_contents(dotfilepath)?;
-    parse_config_from_toml_string(&contents)
+    let icfg: IntermedConfig = toml::from_str(&contents)
+        .map_err(|e| format!("In \"{}\": {}", dotfilepath.to_string_lossy(), e))?;
+
+    stack.push(canonical);
+    let mut merged = load_includes(dotfilepath, &icfg, stack)?;
+    stack.pop();
+
+    merged.merge(
+        configuration_from_intermed(&icfg)
+            .map_err(|e| format!("In \"{}\": {}", dotfilepath.to_string_lossy(), e))?,
+    );
+    Ok(merged)
+}
+
+/// Merges the configuration provided by the files a config file includes
+fn load_includes(
+    dotfilepath: &::std::path::Path,
+    icfg: &IntermedConfig,
+    stack: &mut Vec<::std::path::PathBuf>,
+) -> Result<Configuration, Box<dyn (::std::error::Error)>> {
+    let mut merged = Configuration::default();
+    for include in &icfg.include {
+        merged.merge(load_with_includes(
+            &resolve_include(dotfilepath, include),
+            stack,
+        )?);
+    }
+    Ok(merged)
+}
+
+/// Validates a config file, returning its diagnostics
+pub fn check_dotfile(dotfilepath: &::std::path::Path) -> ::std::io::Result<Vec<check::Diagnostic>> {
+    let contents = load_dotfile_contents(dotfilepath)?;
+    let icfg: IntermedConfig = match toml::from_str(&contents) {
+        Ok(icfg) => icfg,
+        // Leave reporting the syntax error to the checks themselves
+        Err(_) => return Ok(check::check_config(&contents)),
+    };
+    let mut stack = vec![dotfilepath
+        .canonicalize()
+        .unwrap_or_else(|_| dotfilepath.to_path_buf())];
+    Ok(match load_includes(dotfilepath, &icfg, &mut stack) {
+        Ok(inherited) => check::check_config_with(&contents, &inherited),
+        Err(e) => {
+            let mut diagnostics = check::check_config(&contents);
+            diagnostics.push(check::include_error(&contents, e.to_string()));
+            diagnostics
+        }
+    })
+}
+
+/// Renders a config file in canonical form; if `resolved`, with its includes merged in.
+///
+/// Either way, environment variables are written as they are, since they're only expanded
+/// once a binding is used.
+pub fn dump_dotfile(
+    dotfilepath: &::std::path::Path,
+    resolved: bool,
+    format: DumpFormat,
+) -> Result<String, Box<dyn (::std::error::Error)>> {
+    if resolved {
+        let config = load_configuration_from_dotfile(dotfilepath)?;
+        return match format {
+            DumpFormat::Toml => config.to_toml_string(),
+            DumpFormat::Json => config.to_json_string(),
+        };
+    }
+
+    let contents = load_dotfile_contents(dotfilepath)?;
+    let icfg: IntermedConfig = toml::from_str(&contents)?;
+    let config = configuration_from_intermed(&icfg)?;
+    match format {
+        DumpFormat::Toml => {
+            let mut value = toml::Value::try_from(&config)?;
+            if let (Some(table), false) = (value.as_table_mut(), icfg.include.is_empty()) {
+                table.insert("include".to_string(), toml::Value::try_from(&icfg.include)?);
+            }
+            Ok(toml::to_string(&value)?)
+        }
+        DumpFormat::Json => {
+            let mut value = serde_json::to_value(&config)?;
+            if let (Some(object), false) = (value.as_object_mut(), icfg.include.is_empty()) {
+                object.insert("include".to_string(), serde_json::to_value(&icfg.include)?);
+            }
+            Ok(serde_json::to_string_pretty(&value)?)
+        }
+    }
+}
+
+pub fn load_configuration_from_dotfile(
+    dotfilepath: &::std::path::Path,
+) -> Result<Configuration, Box<dyn (::std::error::Error)>> {
+    load_with_includes(dotfilepath, &mut Vec::new())
+}
+
+#[test]
+fn test_parse_gestures() {
+    let input = r#"
+        [gestures]
+        trigger = "G^7"
+        [gestures.bindings]
+        L = "back"
+        "l-u" = { type = "i3", command = "focus up" }
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    let gestures = config.gestures.expect("Must have gestures");
+    assert_eq!(gestures.trigger, 107);
+    assert_eq!(gestures.threshold, gesture::DEFAULT_THRESHOLD);
+    assert_eq!(
+        gestures.binding(&gesture::Gesture(vec![
+            gesture::Direction::Left,
+            gesture::Direction::Up
+        ])),
+        Some(&BindingType::I3("focus up".to_string()))
+    );
+    assert_eq!(gestures.binding(&gesture::Gesture(vec![])), None);
+
+    let duplicated = "[gestures]\ntrigger = 7\nbindings = { U = \"a\", up = \"b\" }\n";
+    assert_eq!(
+        parse_config_from_toml_string(&duplicated.to_string())
+            .unwrap_err()
+            .to_string(),
+        "Gesture \"U\" is bound more than once"
+    );
+}
+
+#[test]
+fn test_parse_multiple_devices() {
+    use std::str::FromStr;
+    let input = r#"
+        device = ["g600", "pad"]
+        [bindings]
+        G9 = "nine"
+        G10 = "ten"
+        [devices.g600.bindings]
+        G10 = { type = "command", command = "own ten", notify = true }
+        [devices.pad]
+        prefix = "usb-Pad"
+        [devices.pad.scancodes]
+        G1 = 30
+        [devices.pad.bindings]
+        G1 = "one"
+        [chords]
+        "pad.G1 + G9" = "together"
+        [layers]
+        shared = true
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    assert_eq!(
+        config.device,
+        vec![
+            device::Selector::named("g600"),
+            device::Selector::named("pad")
+        ]
+    );
+    assert_eq!(config.devices.len(), 1);
+    assert_eq!(
+        config.devices[0].keyboard,
+        device::DEFAULT_KEYBOARD_INTERFACE
+    );
+    assert_eq!(
+        config.chords,
+        vec![(
+            chord::Chord::from_str("G9 + pad.G1").unwrap(),
+            BindingType::Command("together".to_string())
+        )]
+    );
+    assert_eq!(config.layers, Some(LayerSettings { shared: true }));
+
+    let g600 = config.for_device("g600", true);
+    assert_eq!(
+        g600.bindings,
+        vec![
+            (10, BindingType::Command("own ten".to_string())),
+            (9, BindingType::Command("nine".to_string())),
+        ]
+    );
+    assert!(g600.notify.contains(&10));
+    let pad = config.for_device("pad", false);
+    assert_eq!(
+        pad.bindings,
+        vec![(1, BindingType::Command("one".to_string()))]
+    );
+    assert_eq!(pad.scancodes, vec![(1, 30)]);
+}
+
+#[test]
+fn test_parse_keycode_scancodes() {
+    let input = "[scancodes]\nG9 = \"KEY_F13\"\nG10 = \"btn_side\"\nG11 = 30\n";
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    assert_eq!(
+        config.scancodes,
+        vec![
+            (10, device::keycode_scancode(0x113)),
+            (11, 30),
+            (9, device::keycode_scancode(183)),
+        ]
+    );
+    assert!(config
+        .to_toml_string()
+        .unwrap()
+        .contains("010 = \"BTN_SIDE\""));
+    assert!(
+        parse_config_from_toml_string(&"[scancodes]\nG9 = \"KEY_F99\"".to_string())
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid scancode for G9")
+    );
+}
+
+#[test]
+fn test_parse_profiles() {
+    let input = r#"
+        [bindings]
+        G9 = "nine"
+        G10 = { type = "profile", name = "games" }
+        [scancodes]
+        G9 = 30
+        [profiles.games.bindings]
+        G9 = { type = "keyboard", key = "space", notify = true }
+        G10 = { type = "profile" }
+        [profiles.games.scancodes]
+        G11 = 32
+    "#;
+    let config = parse_config_from_toml_string(&input.to_string()).expect("Must parse");
+    assert!(config.has_profile("games") && !config.has_profile("work"));
+    assert_eq!(
+        config.bindings[0],
+        (10, BindingType::Profile(Some("games".to_string())))
+    );
+
+    let base = config.clone().with_profile(None);
+    assert_eq!(base.bindings, config.bindings);
+    let games = config.with_profile(Some("games"));
+    assert_eq!(
+        games.bindings,
+        vec![
+            (10, BindingType::Profile(None)),
+            (9, BindingType::EmulateKey(parse_key("space").unwrap())),
+        ]
+    );
+    assert_eq!(games.scancodes, vec![(9, 30), (11, 32)]);
+    assert!(games.notify.contains(&9));
+
+    let unnamed = parse_config_from_toml_string(
+        &"[profiles.x.bindings]\nG9 = { type = \"profile\", name = 3 }".to_string(),
+    );
+    assert!(unnamed
+        .unwrap_err()
+        .to_string()
+        .contains("In profile \"x\": Profile name was a non-string value"));
+}
+
+#[test]
+fn test_load_with_includes() {
+    let dir = ::std::env::temp_dir().join(format!("lg600r-includes-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(dir.join("shared")).unwrap();
+    let write = |name: &str, contents: &str| ::std::fs::write(dir.join(name), contents).unwrap();
+    write(
+        "shared/base.toml",
+        "[bindings]\nG9 = \"base nine\"\nG10 = \"base ten\"\n[scancodes]\nG9 = 30\nG10 = 31\n",
+    );
+    write(
+        "shared/team.toml",
+        "include = [\"base.toml\"]\n[bindings]\nG10 = \"team ten\"\nG11 = \"team eleven\"\n",
+    );
+    write(
+        "config.toml",
+        "include = [\"shared/team.toml\"]\n[bindings]\nG11 = \"mine\"\n[scancodes]\nG11 = 32\n",
+    );
+    write("loop.toml", "include = [\"loop.toml\"]\n");
+
+    let config = load_configuration_from_dotfile(&dir.join("config.toml")).expect("Must load");
+    let looped = load_configuration_from_dotfile(&dir.join("loop.toml"));
+    ::std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        config.bindings,
+        vec![
+            (10, BindingType::Command("team ten".to_string())),
+            (9, BindingType::Command("base nine".to_string())),
+            (11, BindingType::Command("mine".to_string())),
+        ]
+    );
+    assert_eq!(config.scancodes, vec![(10, 31), (9, 30), (11, 32)]);
+    assert!(looped
+        .unwrap_err()
+        .to_string()
+        .contains("include each other"));
+}
+
+#[test]
+fn test_dump_keeps_variables() {
+    let path = ::std::env::temp_dir().join(format!("lg600r-dump-{}.toml", ::std::process::id()));
+    let source = r#"
+        [bindings]
+        G1 = "echo $HOME"
+        G2 = { type = "i3", command = "workspace ${WORKSPACE}" }
+        G3 = { type = "volume", step = 5, sink = "$SINK", fallback = "amixer -c $$CARD" }
+        G4 = { type = "media", action = "play", player = "${PLAYER}" }
+        G5 = { type = "exec", argv = ["$EDITOR", "${FILE}"], env = { A = "$B" }, cwd = "$DIR" }
+        G6 = { type = "exec", shell = "sh", command = "echo $$$HOME" }
+    "#;
+    ::std::fs::write(&path, source).unwrap();
+    let written = parse_config_from_toml_string(&source.to_string()).unwrap();
+    let dumps = [
+        dump_dotfile(&path, false, DumpFormat::Toml).unwrap(),
+        dump_dotfile(&path, true, DumpFormat::Toml).unwrap(),
+    ];
+    let json = dump_dotfile(&path, false, DumpFormat::Json).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+
+    for dump in &dumps {
+        assert!(!dump.contains(&::std::env::var("HOME").unwrap()));
+        let dumped = parse_config_from_toml_string(dump).unwrap();
+        assert_eq!(dumped.bindings, written.bindings);
+    }
+    for variable in &[
+        "$SINK",
+        "$$CARD",
+        "${PLAYER}",
+        "$EDITOR",
+        "$B",
+        "$DIR",
+        "$$$HOME",
+    ] {
+        assert!(dumps[0].contains(variable), "{} is in the dump", variable);
+    }
+    let icfg: IntermedConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        configuration_from_intermed(&icfg).unwrap().bindings,
+        written.bindings
+    );
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_toml_roundtrip(config in arbitrary::configuration()) {
+        let serialized = config.to_toml_string().unwrap();
+        let parsed = parse_config_from_toml_string(&serialized).unwrap();
+        proptest::prop_assert_eq!(arbitrary::normalized(&parsed), arbitrary::normalized(&config));
+        proptest::prop_assert_eq!(parsed.to_toml_string().unwrap(), serialized);
+    }
+
+    #[test]
+    fn test_json_roundtrip(config in arbitrary::configuration()) {
+        let serialized = config.to_json_string().unwrap();
+        let icfg: IntermedConfig = serde_json::from_str(&serialized).unwrap();
+        let parsed = configuration_from_intermed(&icfg).unwrap();
+        proptest::prop_assert_eq!(arbitrary::normalized(&parsed), arbitrary::normalized(&config));
+    }
 }
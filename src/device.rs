@@ -0,0 +1,812 @@
+use crate::config::check::did_you_mean;
+use crate::config::gkey::{format_gkey, GSHIFT_OFFSET};
+use crate::keycodes::KEYCODES;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the kernel describes devices, input devices among them under `class/input`
+pub const SYSFS: &str = "/sys";
+/// Where event nodes are, by the names sysfs lists them under
+const DEV_INPUT: &str = "/dev/input";
+
+/// The HID usage page of keyboard keys, which is what most buttons send
+const KEYBOARD_USAGE_PAGE: u32 = 0x07;
+/// The usage page of the scancodes standing for evdev key codes, for buttons told apart by the
+/// key they send rather than by their scan; it's vendor-defined, and no device driven uses it
+const KEYCODE_USAGE_PAGE: u32 = 0xffff;
+/// The usual ending for a device's keyboard interface
+pub const DEFAULT_KEYBOARD_INTERFACE: &str = "-event-kbd";
+
+/// Describes a mouse or macropad: how to find its interfaces, and what its buttons are called
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    /// What the device is selected by, like `g600`
+    pub name: String,
+    /// What the device is called in messages, like `Logitech G600`
+    pub title: String,
+    /// USB vendor ID, where known
+    pub vendor: Option<u16>,
+    /// USB product ID; if unknown, any of the vendor's products named like `prefix` may match
+    pub product: Option<u16>,
+    /// How udev names the device's entries in /dev/input/by-id, which identifies it unless its
+    /// product is known
+    pub prefix: String,
+    /// How the by-id entry for the interface that buttons send keypresses through ends,
+    /// which gives its USB interface number and whether it's a keyboard or a mouse
+    pub keyboard: String,
+    /// How the by-id entry for the pointer interface ends, if the device has one worth watching
+    pub pointer: Option<String>,
+    /// The bits of each `MSC_SCAN` value that make up a button's scancode, in place of those
+    /// `scancode` picks out
+    pub scancode_mask: Option<u32>,
+    /// Names shown instead of G-numbers, by unshifted G-key
+    pub buttons: BTreeMap<u32, String>,
+    /// Rows of unshifted G-keys, as the buttons are physically arranged
+    pub layout: Vec<Vec<u32>>,
+}
+
+impl DeviceDescriptor {
+    /// What a G-key is called on this device, like `G^9`, or `shift.thumb` for a named button
+    pub fn label(&self, gkey: u32) -> String {
+        match self.buttons.get(&(gkey % GSHIFT_OFFSET)) {
+            Some(name) if gkey >= GSHIFT_OFFSET => format!("shift.{}", name),
+            Some(name) => name.clone(),
+            None => format_gkey(gkey),
+        }
+    }
+
+    /// Whether an interface is this device's, and the one whose by-id entry ends in `suffix`
+    fn is_interface(&self, interface: &Interface, suffix: &str) -> bool {
+        let identified = match (self.vendor, self.product) {
+            (Some(vendor), Some(product)) => {
+                (interface.vendor, interface.product) == (vendor, product)
+            }
+            // A vendor's other devices may well have the same interfaces
+            (Some(vendor), None) => {
+                interface.vendor == vendor && interface.by_id.starts_with(&self.prefix)
+            }
+            (None, _) => interface.by_id.starts_with(&self.prefix),
+        };
+        let class = if suffix.ends_with("-event-kbd") {
+            interface.keyboard
+        } else if suffix.ends_with("-event-mouse") {
+            interface.mouse
+        } else {
+            true
+        };
+        identified && class && interface.number == interface_number(suffix)
+    }
+
+    /// The keyboard interfaces of this device, wherever it's plugged in, which `selector` picks
+    fn keyboards<'a>(
+        &'a self,
+        interfaces: &'a [Interface],
+        selector: &'a Selector,
+    ) -> impl Iterator<Item = &'a Interface> + 'a {
+        interfaces
+            .iter()
+            .filter(move |interface| self.is_interface(interface, &self.keyboard))
+            .filter(move |interface| selector.picks(interface))
+    }
+
+    /// The pointer interface on the same USB device as `keyboard`, if there's one to watch
+    fn pointer_beside(&self, interfaces: &[Interface], keyboard: &Interface) -> Option<PathBuf> {
+        let suffix = self.pointer.as_ref()?;
+        interfaces
+            .iter()
+            .find(|interface| interface.usb == keyboard.usb && self.is_interface(interface, suffix))
+            .map(|interface| interface.node.clone())
+    }
+}
+
+/// The USB interface number in a by-id entry's ending, like `-if01-event-kbd`; udev leaves it
+/// out for the first interface
+fn interface_number(suffix: &str) -> u8 {
+    suffix
+        .find("-if")
+        .and_then(|at| suffix.get(at + 3..at + 5))
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        .unwrap_or(0)
+}
+
+/// An input device's event interface, as sysfs describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// The event node to read, like `/dev/input/event5`
+    pub node: PathBuf,
+    /// The sysfs directory of the USB device the interface is part of
+    usb: PathBuf,
+    vendor: u16,
+    product: u16,
+    /// The USB device's serial number, if it has one
+    serial: String,
+    /// Where it's plugged in, like `usb-0000:00:14.0-2/input1`
+    phys: String,
+    /// Its USB interface number
+    number: u8,
+    keyboard: bool,
+    mouse: bool,
+    /// What udev would name the device in /dev/input/by-id, less the interface's ending
+    by_id: String,
+}
+
+/// Lists the event interfaces of the USB input devices plugged in, by sysfs under `sysfs`
+pub fn scan(sysfs: &Path) -> io::Result<Vec<Interface>> {
+    let mut interfaces = Vec::new();
+    for entry in fs::read_dir(sysfs.join("class/input"))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let number = match name.strip_prefix("event").map(str::parse::<u32>) {
+            Some(Ok(number)) => number,
+            _ => continue,
+        };
+        // The entry links to the event node's directory, within those of its input device
+        // and of the USB interface that input device is on
+        let event = fs::canonicalize(entry.path())?;
+        if let Some(interface) = describe(&event, &name) {
+            interfaces.push((number, interface));
+        }
+    }
+    interfaces.sort_by_key(|(number, _)| *number);
+    Ok(interfaces
+        .into_iter()
+        .map(|(_, interface)| interface)
+        .collect())
+}
+
+fn describe(event: &Path, name: &str) -> Option<Interface> {
+    let input = event.parent()?;
+    let usb_interface = input
+        .ancestors()
+        .find(|dir| dir.join("bInterfaceNumber").is_file())?;
+    let usb = usb_interface.parent()?;
+    let hex =
+        |dir: &Path, attribute| u16::from_str_radix(&read_attribute(dir, attribute)?, 16).ok();
+    let capabilities =
+        |kind| read_attribute(input, &format!("capabilities/{}", kind)).unwrap_or_default();
+    let (vendor, product) = (hex(input, "id/vendor")?, hex(input, "id/product")?);
+    let serial = read_attribute(usb, "serial").unwrap_or_default();
+    let (keys, axes) = (capabilities("key"), capabilities("rel"));
+    Some(Interface {
+        node: Path::new(DEV_INPUT).join(name),
+        vendor,
+        product,
+        phys: read_attribute(input, "phys").unwrap_or_default(),
+        number: u8::from_str_radix(&read_attribute(usb_interface, "bInterfaceNumber")?, 16).ok()?,
+        // As udev judges keyboards and mice: every key from Esc to D, or X and Y axes and a button
+        keyboard: (1..32).all(|key| has_bit(&keys, key)),
+        mouse: has_bit(&axes, 0) && has_bit(&axes, 1) && has_bit(&keys, BTN_MOUSE),
+        by_id: by_id_name(usb, vendor, product, &serial),
+        serial,
+        usb: usb.to_path_buf(),
+    })
+}
+
+/// The first button code, which mice have
+const BTN_MOUSE: usize = 0x110;
+
+fn read_attribute(dir: &Path, attribute: &str) -> Option<String> {
+    fs::read_to_string(dir.join(attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Whether a bit is set in a sysfs capability bitmap, which is written as hexadecimal words,
+/// each a C `long`, most significant first
+fn has_bit(bitmap: &str, bit: usize) -> bool {
+    let width = 8 * std::mem::size_of::<libc::c_long>();
+    match bitmap
+        .split_whitespace()
+        .rev()
+        .nth(bit / width)
+        .and_then(|word| u64::from_str_radix(word, 16).ok())
+    {
+        Some(word) => (word >> (bit % width)) & 1 == 1,
+        None => false,
+    }
+}
+
+/// The name udev gives a USB device in /dev/input/by-id, less any interface's ending, like
+/// `usb-Logitech_Gaming_Mouse_G600_8D7B94A7F0E23C16`
+fn by_id_name(usb: &Path, vendor: u16, product: u16, serial: &str) -> String {
+    // udev replaces whatever it won't put in a file name with underscores
+    let safe = |value: &str| {
+        value
+            .chars()
+            .map(|c| match c {
+                'a'..='z'
+                | 'A'..='Z'
+                | '0'..='9'
+                | '#'
+                | '+'
+                | '-'
+                | '.'
+                | ':'
+                | '='
+                | '@'
+                | '_' => c,
+                _ => '_',
+            })
+            .collect::<String>()
+    };
+    let manufacturer =
+        read_attribute(usb, "manufacturer").unwrap_or_else(|| format!("{:04x}", vendor));
+    let model = read_attribute(usb, "product").unwrap_or_else(|| format!("{:04x}", product));
+    let mut name = format!("usb-{}_{}", safe(&manufacturer), safe(&model));
+    if !serial.is_empty() {
+        name = format!("{}_{}", name, safe(serial));
+    }
+    name
+}
+
+/// Picks out which device to drive: one by name, or any known device, and perhaps which
+/// of several alike by serial number or port
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Selector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The USB serial number, as in `/sys/bus/usb/devices/*/serial`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// How the kernel's physical path for the device starts, like `usb-0000:00:14.0-2`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phys: Option<String>,
+}
+
+impl Selector {
+    pub fn named(name: &str) -> Selector {
+        Selector {
+            name: Some(name.to_string()),
+            ..Selector::default()
+        }
+    }
+
+    /// The device's name, if that's all it's selected by
+    pub fn name_only(&self) -> Option<&str> {
+        match (&self.name, &self.serial, &self.phys) {
+            (Some(name), None, None) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn picks(&self, interface: &Interface) -> bool {
+        let serial = self
+            .serial
+            .as_ref()
+            .map(|serial| *serial == interface.serial);
+        let phys = self
+            .phys
+            .as_ref()
+            .map(|phys| interface.phys.starts_with(phys.as_str()));
+        serial != Some(false) && phys != Some(false)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("any device"))?;
+        if let Some(serial) = &self.serial {
+            write!(f, " with serial {}", serial)?;
+        }
+        if let Some(phys) = &self.phys {
+            write!(f, " at {}", phys)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unshifted G-keys, in rows of this many, when a device's layout isn't known
+const UNKNOWN_LAYOUT_WIDTH: usize = 5;
+
+/// Rows of G-keys for a heatmap: the device's layout, or else the G-keys used, in order
+pub fn heatmap_rows(device: &DeviceDescriptor, used: &[u32]) -> Vec<Vec<u32>> {
+    if !device.layout.is_empty() {
+        return device.layout.clone();
+    }
+    let mut unshifted = used
+        .iter()
+        .map(|gkey| gkey % GSHIFT_OFFSET)
+        .collect::<Vec<_>>();
+    unshifted.sort();
+    unshifted.dedup();
+    unshifted
+        .chunks(UNKNOWN_LAYOUT_WIDTH)
+        .map(<[u32]>::to_vec)
+        .collect()
+}
+
+/// The scancode of a scan, which reports a HID usage as its page in the upper 16 bits and its ID
+/// in the lower: a keyboard key's is its ID alone, as its page goes without saying, while a key
+/// from another page, like the consumer page media keys are on, keeps its page so as not to be
+/// taken for a keyboard key. A mask, if given, picks out the scancode's bits instead
+pub fn scancode(usage: u32, mask: Option<u32>) -> u32 {
+    match mask {
+        Some(mask) => usage & mask,
+        None if usage >> 16 == KEYBOARD_USAGE_PAGE => usage & 0xffff,
+        None => usage,
+    }
+}
+
+/// The scancode standing for an evdev key code
+pub fn keycode_scancode(code: u32) -> u32 {
+    KEYCODE_USAGE_PAGE << 16 | code
+}
+
+/// The evdev key code a scancode stands for, if it stands for one
+pub fn scancode_keycode(scancode: u32) -> Option<u32> {
+    if scancode >> 16 == KEYCODE_USAGE_PAGE {
+        Some(scancode & 0xffff)
+    } else {
+        None
+    }
+}
+
+/// The evdev code of a key or button by its name, like `KEY_F13` or `BTN_SIDE`
+pub fn keycode(name: &str) -> Option<u32> {
+    KEYCODES
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, code)| u32::from(*code))
+}
+
+/// The name of a key or button by its evdev code
+pub fn keycode_name(code: u32) -> Option<&'static str> {
+    KEYCODES
+        .iter()
+        .find(|(_, c)| u32::from(*c) == code)
+        .map(|(name, _)| *name)
+}
+
+/// A scancode as it's written in a config: the name of the key it stands for if it stands for
+/// one, or else its number
+pub fn format_scancode(scancode: u32) -> String {
+    match scancode_keycode(scancode).and_then(keycode_name) {
+        Some(name) => name.to_string(),
+        None => scancode.to_string(),
+    }
+}
+
+/// The devices lg600r knows of without being told, the G600 first
+pub fn builtin() -> Vec<DeviceDescriptor> {
+    let named = |names: &[(u32, &str)]| {
+        names
+            .iter()
+            .map(|&(gkey, name)| (gkey, name.to_string()))
+            .collect()
+    };
+    let logitech =
+        |name: &str, title: &str, product, prefix: &str, layout: &[&[u32]]| DeviceDescriptor {
+            name: name.to_string(),
+            title: title.to_string(),
+            vendor: Some(0x046d),
+            product: Some(product),
+            prefix: prefix.to_string(),
+            keyboard: "-if01-event-kbd".to_string(),
+            pointer: Some("-event-mouse".to_string()),
+            scancode_mask: None,
+            buttons: BTreeMap::new(),
+            layout: layout.iter().map(|row| row.to_vec()).collect(),
+        };
+    vec![
+        // The click buttons, wheel tilt and G-shift, the two top buttons, then the side grid
+        logitech(
+            "g600",
+            "Logitech G600",
+            0xc24a,
+            "usb-Logitech_Gaming_Mouse_G600_",
+            &[
+                &[1, 3, 2],
+                &[4, 5, 6],
+                &[7, 8],
+                &[9, 10, 11],
+                &[12, 13, 14],
+                &[15, 16, 17],
+                &[18, 19, 20],
+            ],
+        ),
+        // The click buttons and wheel tilt, then the top and thumb buttons
+        logitech(
+            "g700",
+            "Logitech G700",
+            0xc06b,
+            "usb-Logitech_G700",
+            &[&[1, 3, 2], &[4, 5], &[6, 7, 8, 9], &[10, 11, 12, 13]],
+        ),
+        logitech(
+            "g602",
+            "Logitech G602",
+            0xc537,
+            "usb-Logitech_Gaming_Mouse_G602",
+            &[&[1, 3, 2], &[4, 5], &[6, 7, 8], &[9, 10, 11]],
+        ),
+        // Its thumb grid sends the number row, so its twelve keys take the G600's side grid
+        DeviceDescriptor {
+            name: "naga".to_string(),
+            title: "Razer Naga".to_string(),
+            vendor: Some(0x1532),
+            product: None,
+            prefix: "usb-Razer_Razer_Naga".to_string(),
+            keyboard: "-if02-event-kbd".to_string(),
+            pointer: Some("-event-mouse".to_string()),
+            scancode_mask: None,
+            buttons: named(&[
+                (9, "1"),
+                (10, "2"),
+                (11, "3"),
+                (12, "4"),
+                (13, "5"),
+                (14, "6"),
+                (15, "7"),
+                (16, "8"),
+                (17, "9"),
+                (18, "10"),
+                (19, "11"),
+                (20, "12"),
+            ]),
+            layout: vec![
+                vec![1, 3, 2],
+                vec![9, 10, 11],
+                vec![12, 13, 14],
+                vec![15, 16, 17],
+                vec![18, 19, 20],
+            ],
+        },
+    ]
+}
+
+/// Every device lg600r knows of: those described in the config, then the built-ins they
+/// don't take the place of
+pub fn known(custom: &[DeviceDescriptor]) -> Vec<DeviceDescriptor> {
+    let mut known = custom.to_vec();
+    for device in builtin() {
+        if known.iter().all(|other| other.name != device.name) {
+            known.push(device);
+        }
+    }
+    known
+}
+
+/// The devices a selection may be: the one it names, or if it names none, any known device
+pub fn candidates<'a>(
+    selector: &Selector,
+    known: &'a [DeviceDescriptor],
+) -> Result<Vec<&'a DeviceDescriptor>, String> {
+    let selected = match &selector.name {
+        Some(name) => name,
+        None => return Ok(known.iter().collect()),
+    };
+    match known.iter().find(|device| device.name == *selected) {
+        Some(device) => Ok(vec![device]),
+        None => {
+            let names = known.iter().map(|device| device.name.as_str());
+            Err(match did_you_mean(selected, names) {
+                Some(name) => format!(
+                    "Unknown device \"{}\"; did you mean \"{}\"?",
+                    selected, name
+                ),
+                None => format!("Unknown device \"{}\"", selected),
+            })
+        }
+    }
+}
+
+/// A device plugged in, with the interfaces to watch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub device: DeviceDescriptor,
+    pub keyboard: PathBuf,
+    pub pointer: Option<PathBuf>,
+}
+
+/// Finds the devices to drive among the `interfaces` plugged in: for each of `selected`, the
+/// first of its candidates found that isn't driven already, or if nothing's selected, the first
+/// known device found. What's selected but not found is returned too, to be warned about.
+pub fn locate(
+    selected: &[Selector],
+    known: &[DeviceDescriptor],
+    interfaces: &[Interface],
+) -> Result<(Vec<Found>, Vec<Selector>), String> {
+    let anything = [Selector::default()];
+    let selected = if selected.is_empty() {
+        &anything[..]
+    } else {
+        selected
+    };
+    let (mut found, mut missing) = (Vec::<Found>::new(), Vec::new());
+    for selector in selected {
+        let mut located = None;
+        for device in candidates(selector, known)? {
+            located = device
+                .keyboards(interfaces, selector)
+                .find(|keyboard| found.iter().all(|other| other.keyboard != keyboard.node))
+                .map(|keyboard| Found {
+                    device: device.clone(),
+                    keyboard: keyboard.node.clone(),
+                    pointer: device.pointer_beside(interfaces, keyboard),
+                });
+            if located.is_some() {
+                break;
+            }
+        }
+        match located {
+            Some(located) => found.push(located),
+            None => missing.push(selector.clone()),
+        }
+    }
+    Ok((found, missing))
+}
+
+impl DeviceDescriptor {
+    /// Serializes the description into a map, which may hold more besides
+    pub fn serialize_entries<M: serde::ser::SerializeMap>(
+        &self,
+        map: &mut M,
+    ) -> Result<(), M::Error> {
+        let buttons = self
+            .buttons
+            .iter()
+            .map(|(gkey, name)| (format_gkey(*gkey), name))
+            .collect::<BTreeMap<_, _>>();
+        let layout = self
+            .layout
+            .iter()
+            .map(|row| row.iter().map(|gkey| format_gkey(*gkey)).collect())
+            .collect::<Vec<Vec<_>>>();
+        if self.title != self.name {
+            map.serialize_entry("title", &self.title)?;
+        }
+        if let Some(vendor) = self.vendor {
+            map.serialize_entry("vendor", &vendor)?;
+        }
+        if let Some(product) = self.product {
+            map.serialize_entry("product", &product)?;
+        }
+        map.serialize_entry("prefix", &self.prefix)?;
+        map.serialize_entry("keyboard", &self.keyboard)?;
+        if let Some(pointer) = &self.pointer {
+            map.serialize_entry("pointer", pointer)?;
+        }
+        if let Some(mask) = self.scancode_mask {
+            map.serialize_entry("scancode_mask", &mask)?;
+        }
+        if !buttons.is_empty() {
+            map.serialize_entry("buttons", &buttons)?;
+        }
+        if !layout.is_empty() {
+            map.serialize_entry("layout", &layout)?;
+        }
+        Ok(())
+    }
+}
+
+impl serde::Serialize for DeviceDescriptor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        self.serialize_entries(&mut map)?;
+        map.end()
+    }
+}
+
+#[test]
+fn test_device_descriptors() {
+    let devices = builtin();
+    let g600 = &devices[0];
+    assert_eq!(interface_number(&g600.keyboard), 1);
+    assert_eq!(interface_number(g600.pointer.as_ref().unwrap()), 0);
+    assert_eq!(
+        (g600.label(9), g600.label(109)),
+        ("G9".into(), "G^9".into())
+    );
+    let naga = candidates(&Selector::named("naga"), &devices).unwrap()[0];
+    assert_eq!(
+        (naga.label(18), naga.label(118)),
+        ("10".into(), "shift.10".into())
+    );
+    assert_eq!(naga.label(1), "G1");
+
+    let custom = DeviceDescriptor {
+        name: "g600".to_string(),
+        title: "Macropad".to_string(),
+        layout: vec![],
+        ..g600.clone()
+    };
+    let known = known(&[custom]);
+    assert_eq!(known.len(), devices.len());
+    assert_eq!(known[0].title, "Macropad");
+    assert_eq!(
+        heatmap_rows(&known[0], &[3, 1, 109, 2, 8, 9]),
+        vec![vec![1, 2, 3, 8, 9]]
+    );
+    assert_eq!(
+        candidates(&Selector::named("g700s"), &known),
+        Err("Unknown device \"g700s\"; did you mean \"g700\"?".to_string())
+    );
+}
+
+#[test]
+fn test_scancodes_from_usages() {
+    // G9 on a G600 sends the keyboard page's 1, and a media key the consumer page's Play/Pause
+    assert_eq!(scancode(0x7001e, None), 30);
+    assert_eq!(scancode(0xc00cd, None), 0xc00cd);
+    assert_eq!(scancode(30, None), 30);
+    assert_eq!(scancode(0x9_0001, Some(0xffff)), 1);
+
+    let f13 = keycode_scancode(keycode("KEY_F13").unwrap());
+    assert_eq!(scancode_keycode(f13), Some(183));
+    assert_eq!(scancode_keycode(30), None);
+    assert_eq!(
+        (format_scancode(f13), format_scancode(30)),
+        ("KEY_F13".to_string(), "30".to_string())
+    );
+    assert_eq!(keycode("btn_side"), Some(0x113));
+    assert_eq!(format_scancode(keycode_scancode(0x110)), "BTN_LEFT");
+    assert_eq!(keycode("KEY_F99"), None);
+}
+
+#[test]
+fn test_locate_devices_in_sysfs() {
+    let sysfs = std::env::temp_dir().join(format!("lg600r-sysfs-{}", std::process::id()));
+    let width = 8 * std::mem::size_of::<libc::c_long>();
+    let bitmap = |bits: &[usize]| {
+        let mut words = vec![0u64; bits.iter().max().map_or(1, |max| max / width + 1)];
+        for bit in bits {
+            words[bit / width] |= 1 << (bit % width);
+        }
+        let words = words.iter().rev().map(|word| format!("{:x}", word));
+        words.collect::<Vec<_>>().join(" ")
+    };
+    let (keyboard, consumer) = ((1..120).collect::<Vec<_>>(), vec![113, 114, 115]);
+    let mouse = [BTN_MOUSE, BTN_MOUSE + 1, BTN_MOUSE + 2];
+    let write = |path: PathBuf, contents: &str| {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{}\n", contents)).unwrap();
+    };
+    fs::create_dir_all(sysfs.join("class/input/mice")).unwrap();
+    // A USB device on a port, with event nodes as (number, interface, keys, axes)
+    let plug = |port: &str,
+                ids: (u16, u16),
+                strings: &[(&str, &str)],
+                events: &[(u32, u8, &[usize], &[usize])]| {
+        let usb = sysfs
+            .join("devices/pci0000:00/0000:00:14.0/usb1")
+            .join(port);
+        for (attribute, value) in strings {
+            write(usb.join(attribute), value);
+        }
+        for &(number, interface, keys, axes) in events {
+            let usb_interface = usb.join(format!("{}:1.{}", port, interface));
+            write(
+                usb_interface.join("bInterfaceNumber"),
+                &format!("{:02x}", interface),
+            );
+            let input = usb_interface
+                .join(format!("0003:{:04X}:{:04X}.000{}", ids.0, ids.1, number))
+                .join(format!("input/input{}", number));
+            write(input.join("id/vendor"), &format!("{:04x}", ids.0));
+            write(input.join("id/product"), &format!("{:04x}", ids.1));
+            write(
+                input.join("phys"),
+                &format!("usb-0000:00:14.0-{}/input{}", port, interface),
+            );
+            write(input.join("capabilities/key"), &bitmap(keys));
+            write(input.join("capabilities/rel"), &bitmap(axes));
+            let event = input.join(format!("event{}", number));
+            fs::create_dir_all(&event).unwrap();
+            std::os::unix::fs::symlink(&event, sysfs.join(format!("class/input/event{}", number)))
+                .unwrap();
+        }
+    };
+    let g600 = [
+        ("manufacturer", "Logitech"),
+        ("product", "Gaming Mouse G600"),
+    ];
+    plug(
+        "1-4",
+        (0x1189, 0x8890),
+        &[("manufacturer", "USB Macro"), ("product", "Pad 3x1")],
+        &[(2, 0, &keyboard, &[])],
+    );
+    plug(
+        "1-2",
+        (0x046d, 0xc24a),
+        &[g600[0], g600[1], ("serial", "AAAA")],
+        &[
+            (3, 0, &mouse, &[0, 1, 8]),
+            (4, 1, &keyboard, &[]),
+            (5, 1, &consumer, &[]),
+        ],
+    );
+    plug(
+        "1-3",
+        (0x046d, 0xc24a),
+        &[g600[0], g600[1], ("serial", "BBBB")],
+        &[(6, 0, &mouse, &[0, 1, 8]), (7, 1, &keyboard, &[])],
+    );
+    let razer = |product| [("manufacturer", "Razer"), ("product", product)];
+    plug(
+        "1-5",
+        (0x1532, 0x0228),
+        &razer("Razer BlackWidow"),
+        &[(8, 2, &keyboard, &[])],
+    );
+    plug(
+        "1-6",
+        (0x1532, 0x0040),
+        &razer("Razer Naga"),
+        &[(9, 0, &mouse, &[0, 1, 8]), (10, 2, &keyboard, &[])],
+    );
+
+    let interfaces = scan(&sysfs).unwrap();
+    let node = |number: u32| Path::new(DEV_INPUT).join(format!("event{}", number));
+    let nodes = interfaces
+        .iter()
+        .map(|interface| interface.node.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(nodes, (2..=10).map(node).collect::<Vec<_>>());
+    assert_eq!(interfaces[1].by_id, "usb-Logitech_Gaming_Mouse_G600_AAAA");
+
+    let builtin = builtin();
+    let drive = |selected: &[Selector], known: &[DeviceDescriptor]| {
+        let (found, missing) = locate(selected, known, &interfaces).unwrap();
+        let found = found
+            .into_iter()
+            .map(|found| (found.device.name, found.keyboard, found.pointer))
+            .collect::<Vec<_>>();
+        (found, missing)
+    };
+    let g600 = |keyboard, pointer| ("g600".to_string(), node(keyboard), Some(node(pointer)));
+    assert_eq!(drive(&[], &builtin), (vec![g600(4, 3)], vec![]));
+    let serial = Selector {
+        serial: Some("BBBB".to_string()),
+        ..Selector::default()
+    };
+    assert_eq!(drive(&[serial], &builtin), (vec![g600(7, 6)], vec![]));
+    let phys = Selector {
+        phys: Some("usb-0000:00:14.0-1-3".to_string()),
+        ..Selector::named("g600")
+    };
+    assert_eq!(drive(&[phys], &builtin), (vec![g600(7, 6)], vec![]));
+    // Two alike are told apart without being selected by serial, and what's missing is skipped
+    let selected = [
+        Selector::named("g600"),
+        Selector::named("g600"),
+        Selector::named("g700"),
+    ];
+    assert_eq!(
+        drive(&selected, &builtin),
+        (vec![g600(4, 3), g600(7, 6)], vec![Selector::named("g700")])
+    );
+    // Only the vendor's devices named like the Naga are taken for one
+    let naga = ("naga".to_string(), node(10), Some(node(9)));
+    assert_eq!(
+        drive(&[Selector::named("naga")], &builtin),
+        (vec![naga], vec![])
+    );
+
+    // Without a vendor, a device is recognised by the name udev would give it in by-id
+    let macropad = DeviceDescriptor {
+        name: "macropad".to_string(),
+        title: "Macropad".to_string(),
+        vendor: None,
+        product: None,
+        prefix: "usb-USB_Macro_Pad_3x1".to_string(),
+        keyboard: DEFAULT_KEYBOARD_INTERFACE.to_string(),
+        pointer: None,
+        scancode_mask: None,
+        buttons: BTreeMap::new(),
+        layout: vec![],
+    };
+    assert_eq!(
+        drive(&[Selector::named("macropad")], &known(&[macropad])),
+        (vec![("macropad".to_string(), node(2), None)], vec![])
+    );
+    fs::remove_dir_all(&sysfs).unwrap();
+}
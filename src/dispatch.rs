@@ -0,0 +1,494 @@
+use crate::config::{expand_env, BindingType, ScrollAxis};
+use crate::exec;
+use crate::i3ipc::I3Connection;
+use crate::mpris::MprisControl;
+use crate::volume::VolumeControl;
+use crate::xdo::managed::XdoManaged;
+use crate::xdo::Output;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// An action carried on in the background, so that what it waits for doesn't hold up the keys
+pub struct Running(mpsc::Receiver<Result<(), String>>);
+
+impl Running {
+    /// Runs an action on a thread of its own
+    fn spawn<F>(action: F) -> Running
+    where
+        F: FnOnce() -> Result<(), String> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || sender.send(action()).unwrap_or(()));
+        Running(receiver)
+    }
+
+    /// How the action went, once it's finished
+    pub fn finished(&self) -> Option<Result<(), String>> {
+        match self.0.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Some(Err("Stopped before finishing".to_string()))
+            }
+        }
+    }
+}
+
+/// What's done each time a held binding repeats
+type Repeated = Box<dyn Fn(&mut dyn Output) + Send>;
+
+/// A binding repeated in simulated time, next due once that's come
+struct Scheduled {
+    binding: BindingType,
+    interval: Duration,
+    due: Duration,
+    action: Repeated,
+}
+
+/// i3 commands, with where to tell how each went
+type I3Commands = mpsc::Sender<(String, mpsc::Sender<Result<(), String>>)>;
+
+/// Sends i3 commands in turn over one connection, on a thread of its own
+fn spawn_i3() -> I3Commands {
+    let (sender, commands): (I3Commands, _) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut i3 = I3Connection::default();
+        for (command, finished) in commands {
+            finished.send(i3.run_command(&command)).unwrap_or(());
+        }
+    });
+    sender
+}
+
+/// Performs the actions bindings describe, holding on to whatever connections they need
+pub struct Dispatcher {
+    output: Arc<Mutex<Box<dyn Output>>>,
+    /// Bindings being repeated while held, with the flags that stop them
+    repeating: Vec<(BindingType, Arc<AtomicBool>)>,
+    /// Started on first use, like `volume`
+    i3: Option<I3Commands>,
+    media: MprisControl,
+    /// Started on first use, so configs without volume bindings don't spawn its thread
+    volume: Option<VolumeControl>,
+    /// Whether only the output is reached, as when simulating, leaving commands unrun and other
+    /// programs alone
+    simulated: bool,
+    /// How long a simulation's been going, if time's simulated, in which case repeats only come
+    /// as it's advanced
+    clock: Option<Duration>,
+    /// Repeats in simulated time, in place of `repeating`
+    scheduled: Vec<Scheduled>,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Dispatcher::with_output(Box::new(XdoManaged::default()))
+    }
+}
+
+impl Dispatcher {
+    /// Sends keystrokes and mouse input somewhere other than the X server
+    pub fn with_output(output: Box<dyn Output>) -> Dispatcher {
+        Dispatcher {
+            output: Arc::new(Mutex::new(output)),
+            repeating: Vec::new(),
+            i3: None,
+            media: MprisControl::default(),
+            volume: None,
+            simulated: false,
+            clock: None,
+            scheduled: Vec::new(),
+        }
+    }
+
+    /// Sends keystrokes and mouse input to `output`, and does nothing else bindings describe;
+    /// time is simulated, passing only as `advance` says
+    pub fn simulated(output: Box<dyn Output>) -> Dispatcher {
+        Dispatcher {
+            simulated: true,
+            clock: Some(Duration::from_secs(0)),
+            ..Dispatcher::with_output(output)
+        }
+    }
+
+    /// Lets simulated time pass, carrying out the repeats that come due meanwhile in turn
+    pub fn advance(&mut self, by: Duration) {
+        let until = match self.clock {
+            Some(now) => now + by,
+            None => return,
+        };
+        while let Some(next) = self
+            .scheduled
+            .iter_mut()
+            .filter(|scheduled| scheduled.due <= until)
+            .min_by_key(|scheduled| scheduled.due)
+        {
+            (next.action)(&mut **self.output.lock().unwrap());
+            next.due += next.interval;
+        }
+        self.clock = Some(until);
+    }
+
+    /// Repeats an action every `interval` until its binding is released, on a thread of its own,
+    /// or as simulated time is advanced
+    fn repeat<F>(&mut self, binding: &BindingType, interval: Duration, action: F)
+    where
+        F: Fn(&mut dyn Output) + Send + 'static,
+    {
+        self.stop_repeating(binding);
+        if let Some(now) = self.clock {
+            self.scheduled.push(Scheduled {
+                binding: binding.clone(),
+                interval,
+                due: now + interval,
+                action: Box::new(action),
+            });
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        self.repeating.push((binding.clone(), stop.clone()));
+        let output = self.output.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            // Checked with the output locked, so that nothing is emitted once released
+            let mut output = output.lock().unwrap();
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            action(&mut **output);
+        });
+    }
+
+    fn stop_repeating(&mut self, binding: &BindingType) {
+        self.repeating.retain(|(repeated, stop)| {
+            if repeated == binding {
+                stop.store(true, Ordering::SeqCst);
+            }
+            repeated != binding
+        });
+        self.scheduled
+            .retain(|scheduled| scheduled.binding != *binding);
+    }
+
+    /// The output, locked only while it's used, so repeats aren't held up by slower actions
+    fn output(&self) -> MutexGuard<'_, Box<dyn Output>> {
+        self.output.lock().unwrap()
+    }
+
+    /// Sends a command to i3, leaving its reply to come in the background
+    fn run_i3(&mut self, command: String) -> Result<Running, String> {
+        let (finished, receiver) = mpsc::channel();
+        let sent = self
+            .i3
+            .get_or_insert_with(spawn_i3)
+            .send((command, finished));
+        if sent.is_err() {
+            // Started again on the next command
+            self.i3 = None;
+            return Err("Stopped talking to i3".to_string());
+        }
+        Ok(Running(receiver))
+    }
+
+    /// Performs a binding, leaving commands, programs waited for and i3 commands `Running` in
+    /// the background
+    pub fn perform(
+        &mut self,
+        binding: &BindingType,
+        pressed: bool,
+    ) -> Result<Option<Running>, String> {
+        let external = matches!(
+            binding,
+            BindingType::Command(_)
+                | BindingType::Exec(_)
+                | BindingType::I3(_)
+                | BindingType::Media(_)
+                | BindingType::Volume(_)
+        );
+        if self.simulated && external {
+            return Ok(None);
+        }
+        match (binding, pressed) {
+            (BindingType::Command(cmd), true) => {
+                let line = expand_env(cmd);
+                Ok(Some(Running::spawn(move || exec::run_command(&line))))
+            }
+            (BindingType::Exec(spec), true) if !spec.detach => {
+                let spec = spec.clone();
+                Ok(Some(Running::spawn(move || exec::run(&spec))))
+            }
+            (BindingType::I3(command), true) => self.run_i3(expand_env(command)).map(Some),
+            _ => self.perform_at_once(binding, pressed).map(|()| None),
+        }
+    }
+
+    /// Performs a binding that's over by the time it returns
+    fn perform_at_once(&mut self, binding: &BindingType, pressed: bool) -> Result<(), String> {
+        match (binding, pressed) {
+            // Pressed, these are run in the background by `perform`
+            (BindingType::Command(_), _) | (BindingType::I3(_), _) => Ok(()),
+            (BindingType::EmulateMouse(button), pressed) => {
+                if pressed {
+                    self.output().mouse_down(*button);
+                } else {
+                    self.output().mouse_up(*button);
+                }
+                Ok(())
+            }
+            (BindingType::EmulateKey(combo), pressed) => {
+                if pressed {
+                    self.output().combo_down(combo);
+                } else {
+                    self.output().combo_up(combo);
+                }
+                Ok(())
+            }
+            (BindingType::KeySequence(text), true) => {
+                self.output().key_sequence(text);
+                Ok(())
+            }
+            (BindingType::KeySequence(_), false) => Ok(()),
+            // Only started, as it's detached
+            (BindingType::Exec(spec), true) => exec::run(spec),
+            (BindingType::Exec(_), false) => Ok(()),
+            (
+                BindingType::Scroll {
+                    axis,
+                    amount,
+                    repeat_ms,
+                },
+                true,
+            ) => {
+                let (axis, amount) = (*axis, *amount);
+                let scroll = move |output: &mut dyn Output| match axis {
+                    ScrollAxis::Vertical => output.mouse_scroll_y(amount),
+                    ScrollAxis::Horizontal => output.mouse_scroll_x(amount),
+                };
+                scroll(&mut **self.output());
+                if let Some(ms) = repeat_ms {
+                    self.repeat(binding, Duration::from_millis(u64::from(*ms)), scroll);
+                }
+                Ok(())
+            }
+            (BindingType::Scroll { .. }, false) => {
+                self.stop_repeating(binding);
+                Ok(())
+            }
+            (
+                BindingType::MovePointer {
+                    x,
+                    y,
+                    relative,
+                    screen,
+                },
+                true,
+            ) => {
+                if *relative {
+                    self.output().mouse_move_relative(*x, *y);
+                } else {
+                    self.output().mouse_move_to(*x, *y, *screen);
+                }
+                Ok(())
+            }
+            (BindingType::MovePointer { .. }, false) => Ok(()),
+            (BindingType::Click { button, count }, true) => {
+                let mut output = self.output();
+                for _ in 0..*count {
+                    output.mouse_click(*button);
+                }
+                Ok(())
+            }
+            (BindingType::Click { .. }, false) => Ok(()),
+            (BindingType::Media(action), true) => self.media.perform(action),
+            (BindingType::Media(_), false) => Ok(()),
+            (BindingType::Volume(action), true) => self
+                .volume
+                .get_or_insert_with(VolumeControl::default)
+                .press(action),
+            (BindingType::Volume(_), false) => {
+                if let Some(volume) = &self.volume {
+                    volume.release();
+                }
+                Ok(())
+            }
+            // The keyboard watcher re-emits these keys itself
+            (BindingType::Passthrough, _) => Ok(()),
+            // The controller switches profiles itself
+            (BindingType::Profile(_), _) => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_emulated_input_follows_the_button() {
+    use crate::xdo::recording::{Emitted, Recording};
+    use crate::xdo::{Key, Stroke};
+    let recording = Recording::default();
+    let mut dispatcher = Dispatcher::with_output(Box::new(recording.clone()));
+    let raw = BindingType::EmulateKey(Key::Raw(0x38).into());
+    let mouse = BindingType::EmulateMouse(8);
+    dispatcher.perform(&raw, true).unwrap();
+    dispatcher.perform(&mouse, true).unwrap();
+    dispatcher.perform(&mouse, false).unwrap();
+    dispatcher.perform(&raw, false).unwrap();
+    dispatcher
+        .perform(&BindingType::KeySequence("hi".to_string()), true)
+        .unwrap();
+    assert_eq!(
+        recording.take(),
+        vec![
+            Emitted::KeyDown(Stroke::Keycode(0x38)),
+            Emitted::MouseDown(8),
+            Emitted::MouseUp(8),
+            Emitted::KeyUp(Stroke::Keycode(0x38)),
+            Emitted::Text("hi".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_simulated_bindings_only_reach_the_output() {
+    use crate::xdo::recording::{Emitted, Recording};
+    let recording = Recording::default();
+    let mut dispatcher = Dispatcher::simulated(Box::new(recording.clone()));
+    let command = BindingType::Command("exit 1".to_string());
+    assert!(matches!(dispatcher.perform(&command, true), Ok(None)));
+    dispatcher
+        .perform(
+            &BindingType::Click {
+                button: 2,
+                count: 1,
+            },
+            true,
+        )
+        .unwrap();
+    assert_eq!(recording.take(), vec![Emitted::MouseClick(2)]);
+}
+
+#[test]
+fn test_simulated_repeats_keep_simulated_time() {
+    use crate::xdo::recording::{Emitted, Recording};
+    let recording = Recording::default();
+    let mut dispatcher = Dispatcher::simulated(Box::new(recording.clone()));
+    let scroll = |amount, repeat_ms| BindingType::Scroll {
+        axis: ScrollAxis::Vertical,
+        amount,
+        repeat_ms: Some(repeat_ms),
+    };
+    let (up, down) = (scroll(-1, 20), scroll(1, 30));
+    dispatcher.perform(&up, true).unwrap();
+    dispatcher.advance(Duration::from_millis(10));
+    dispatcher.perform(&down, true).unwrap();
+    assert_eq!(
+        recording.take(),
+        vec![Emitted::MouseClick(4), Emitted::MouseClick(5)]
+    );
+    // Due at 20, 40 and 60ms, and at 40 and 70ms, in that order
+    dispatcher.advance(Duration::from_millis(60));
+    dispatcher.perform(&up, false).unwrap();
+    dispatcher.advance(Duration::from_millis(15));
+    dispatcher.perform(&down, false).unwrap();
+    dispatcher.advance(Duration::from_secs(1));
+    assert_eq!(
+        recording.take(),
+        vec![
+            Emitted::MouseClick(4),
+            Emitted::MouseClick(4),
+            Emitted::MouseClick(5),
+            Emitted::MouseClick(4),
+            Emitted::MouseClick(5),
+        ]
+    );
+}
+
+#[test]
+fn test_commands_run_in_the_background() {
+    use std::time::Instant;
+    let mut dispatcher =
+        Dispatcher::with_output(Box::new(crate::xdo::recording::Recording::default()));
+    let started = Instant::now();
+    let running = dispatcher
+        .perform(&BindingType::Command("sleep 0.3; exit 3".to_string()), true)
+        .unwrap()
+        .expect("Must run in the background");
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert_eq!(running.finished(), None);
+    let result = loop {
+        if let Some(result) = running.finished() {
+            break result;
+        }
+        assert!(started.elapsed() < Duration::from_secs(5), "Never finished");
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(result, Err("\"bash\" exit status: 3".to_string()));
+
+    let detached = crate::exec::ExecSpec {
+        detach: true,
+        ..crate::exec::ExecSpec::shell("sh", "true")
+    };
+    assert!(matches!(
+        dispatcher.perform(&BindingType::Exec(detached), true),
+        Ok(None)
+    ));
+}
+
+#[test]
+fn test_scroll_repeats_while_held() {
+    use crate::xdo::recording::{Emitted, Recording};
+    let recording = Recording::default();
+    let mut dispatcher = Dispatcher::with_output(Box::new(recording.clone()));
+    let scroll = BindingType::Scroll {
+        axis: ScrollAxis::Horizontal,
+        amount: -2,
+        repeat_ms: Some(20),
+    };
+    dispatcher.perform(&scroll, true).unwrap();
+    assert_eq!(
+        recording.take(),
+        vec![Emitted::MouseClick(6), Emitted::MouseClick(6)]
+    );
+    // How many repeats come in a given time is up to the scheduler, so this only waits for the
+    // first; test_simulated_repeats_keep_simulated_time counts them
+    let started = std::time::Instant::now();
+    let mut repeated = Vec::new();
+    while repeated.is_empty() {
+        assert!(started.elapsed() < Duration::from_secs(5), "Never repeated");
+        std::thread::sleep(Duration::from_millis(20));
+        repeated = recording.take();
+    }
+    dispatcher.perform(&scroll, false).unwrap();
+    repeated.extend(recording.take());
+    assert!(repeated.iter().all(|e| *e == Emitted::MouseClick(6)));
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(recording.take(), vec![]);
+
+    dispatcher
+        .perform(
+            &BindingType::MovePointer {
+                x: -1,
+                y: 0,
+                relative: false,
+                screen: 1,
+            },
+            true,
+        )
+        .unwrap();
+    dispatcher
+        .perform(
+            &BindingType::Click {
+                button: 1,
+                count: 2,
+            },
+            true,
+        )
+        .unwrap();
+    assert_eq!(
+        recording.take(),
+        vec![
+            Emitted::MouseMoveTo(-1, 0, 1),
+            Emitted::MouseClick(1),
+            Emitted::MouseClick(1),
+        ]
+    );
+}
@@ -0,0 +1,222 @@
+use crate::config::{expand_env, expand_home};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecTarget {
+    /// A program and its arguments, run directly
+    Argv(Vec<String>),
+    /// A command line, run by the given shell
+    Shell { shell: String, command: String },
+    /// A program and its arguments, passed on by the given shell as `"$0" "$@"`, so that they
+    /// reach it intact but run in the environment the shell sets up
+    ShellArgv { shell: String, argv: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecSpec {
+    pub target: ExecTarget,
+    pub env: BTreeMap<String, String>,
+    /// Where it runs, with variables and a leading `~/` expanded as it starts
+    pub cwd: Option<String>,
+    /// Run in a session of its own without waiting for it, so it outlives lg600r
+    pub detach: bool,
+}
+
+impl ExecSpec {
+    pub fn shell(shell: &str, command: &str) -> ExecSpec {
+        ExecSpec {
+            target: ExecTarget::Shell {
+                shell: shell.to_string(),
+                command: command.to_string(),
+            },
+            env: BTreeMap::new(),
+            cwd: None,
+            detach: false,
+        }
+    }
+
+    fn program(&self) -> &str {
+        match &self.target {
+            ExecTarget::Argv(argv) => argv.first().map(String::as_str).unwrap_or(""),
+            ExecTarget::Shell { shell, .. } | ExecTarget::ShellArgv { shell, .. } => shell,
+        }
+    }
+
+    /// The command to run, with variables expanded in its arguments, command line and
+    /// environment as it starts, so that it sees the environment lg600r has at the time
+    fn command(&self) -> Result<Command, String> {
+        let mut command = match &self.target {
+            ExecTarget::Argv(argv) => {
+                let (program, args) = argv
+                    .split_first()
+                    .ok_or_else(|| "Nothing to execute; argv is empty".to_string())?;
+                let mut command = Command::new(expand_env(program));
+                command.args(args.iter().map(|arg| expand_env(arg)));
+                command
+            }
+            ExecTarget::Shell {
+                shell,
+                command: line,
+            } => {
+                let mut command = Command::new(shell);
+                command.arg("-c").arg(expand_env(line));
+                command
+            }
+            ExecTarget::ShellArgv { shell, argv } => {
+                if argv.is_empty() {
+                    return Err("Nothing to execute; argv is empty".to_string());
+                }
+                let mut command = Command::new(shell);
+                command
+                    .arg("-c")
+                    .arg("\"$0\" \"$@\"")
+                    .args(argv.iter().map(|arg| expand_env(arg)));
+                command
+            }
+        };
+        command
+            .envs(
+                self.env
+                    .iter()
+                    .map(|(name, value)| (name, expand_env(value))),
+            )
+            .stdin(Stdio::null());
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(expand_home(&expand_env(cwd)));
+        }
+        Ok(command)
+    }
+}
+
+/// Runs a command line under bash, sharing lg600r's output and waiting for bash alone, so that
+/// whatever it starts in the background is left running; reports failure if bash does
+pub fn run_command(line: &str) -> Result<(), String> {
+    let status = Command::new("bash")
+        .arg("-c")
+        .arg(line)
+        .status()
+        .map_err(|e| format!("Failed to execute \"bash\": {}", e))?;
+    println!("Subprocess finished.");
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("\"bash\" {}", status))
+    }
+}
+
+/// Logs a program's output line by line as it comes, on a thread of its own
+fn log_lines<R: Read + Send + 'static>(stream: Option<R>, program: &str, errors: bool) {
+    let (stream, program) = match stream {
+        Some(stream) => (stream, program.to_string()),
+        None => return,
+    };
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) if errors => eprintln!("[{}] {}", program, line),
+                Ok(line) => println!("[{}] {}", program, line),
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+/// Runs a command, logging its output and reporting failure if it doesn't exit successfully
+pub fn run(spec: &ExecSpec) -> Result<(), String> {
+    let mut command = spec.command()?;
+    let program = spec.program();
+
+    if spec.detach {
+        use std::os::unix::process::CommandExt;
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    Err(::std::io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("Failed to execute \"{}\": {}", program, e))?;
+        // Reap the child whenever it exits, rather than leaving a zombie behind
+        std::thread::spawn(move || child.wait());
+        println!("Started \"{}\" detached.", program);
+        return Ok(());
+    }
+
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to execute \"{}\": {}", program, e))?;
+    // Only the program is waited for; anything it leaves in the background may keep its output
+    // open long after it exits
+    log_lines(child.stdout.take(), program, false);
+    log_lines(child.stderr.take(), program, true);
+    let status = child
+        .wait()
+        .map_err(|e| format!("Couldn't wait for \"{}\": {}", program, e))?;
+    println!("Subprocess finished.");
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("\"{}\" {}", program, status))
+    }
+}
+
+#[test]
+fn test_run_reports_failure() {
+    let mut spec = ExecSpec::shell("sh", "test \"$GREETING\" = hello && test \"$(pwd)\" = /");
+    spec.env.insert("GREETING".to_string(), "hello".to_string());
+    spec.cwd = Some("/".to_string());
+    assert_eq!(run(&spec), Ok(()));
+
+    spec.env.clear();
+    assert!(run(&spec).unwrap_err().contains("exit status: 1"));
+
+    spec.target = ExecTarget::Argv(vec!["/nonexistent/lg600r-test".to_string()]);
+    assert!(run(&spec).unwrap_err().starts_with("Failed to execute"));
+
+    assert_eq!(
+        run_command("exit 3"),
+        Err("\"bash\" exit status: 3".to_string())
+    );
+}
+
+#[test]
+fn test_variables_are_expanded_as_run() {
+    let home = std::env::var("HOME").unwrap();
+    let argv = ["test", "$HOME", "=", &home];
+    let spec = ExecSpec {
+        target: ExecTarget::Argv(argv.iter().map(|arg| arg.to_string()).collect()),
+        env: BTreeMap::new(),
+        cwd: None,
+        detach: false,
+    };
+    assert_eq!(run(&spec), Ok(()));
+
+    // `$$` is a `$` left for the shell to expand
+    let mut spec = ExecSpec::shell(
+        "sh",
+        "test \"$$GREETING\" = \"${HOME}\" -a \"$$(pwd)\" = \"$HOME\"",
+    );
+    spec.env.insert("GREETING".to_string(), "$HOME".to_string());
+    spec.cwd = Some("$HOME".to_string());
+    assert_eq!(run(&spec), Ok(()));
+}
+
+#[test]
+fn test_background_children_are_not_waited_for() {
+    let started = std::time::Instant::now();
+    assert_eq!(run_command("sleep 3 & true"), Ok(()));
+    assert_eq!(
+        run(&ExecSpec::shell("sh", "sleep 3 & echo started")),
+        Ok(())
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+}
@@ -0,0 +1,223 @@
+use evdev_rs::enums::{EventCode, EV_REL, EV_SYN};
+use evdev_rs::InputEvent;
+use std::fmt;
+
+/// How far, in mouse counts, the pointer must travel for a stroke to register
+pub const DEFAULT_THRESHOLD: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn letter(self) -> char {
+        match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Direction> {
+        match name.to_ascii_lowercase().as_str() {
+            "u" | "up" => Some(Direction::Up),
+            "d" | "down" => Some(Direction::Down),
+            "l" | "left" => Some(Direction::Left),
+            "r" | "right" => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+/// A sequence of strokes, written with their initials joined by dashes, like `L-U`
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Gesture(pub Vec<Direction>);
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let letters = self
+            .0
+            .iter()
+            .map(|direction| direction.letter().to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", letters.join("-"))
+    }
+}
+
+impl ::std::str::FromStr for Gesture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Gesture, String> {
+        let mut strokes = Vec::new();
+        for name in s.split('-') {
+            let direction = Direction::from_name(name.trim()).ok_or_else(|| {
+                format!(
+                    "Unknown stroke \"{}\" in gesture \"{}\"; strokes are U, D, L and R",
+                    name.trim(),
+                    s
+                )
+            })?;
+            // The recogniser merges consecutive strokes in one direction, so these can't be drawn
+            if strokes.last() == Some(&direction) {
+                return Err(format!(
+                    "Gesture \"{}\" repeats a stroke; one long stroke is drawn the same as two",
+                    s
+                ));
+            }
+            strokes.push(direction);
+        }
+        Ok(Gesture(strokes))
+    }
+}
+
+/// Turns relative pointer motion into strokes.
+///
+/// Motion is accumulated a frame at a time, up to each `SYN_REPORT`, so that diagonal movement
+/// is judged as a whole. Once it has travelled `threshold` counts along either axis, the
+/// dominant axis decides the stroke, which extends the previous stroke if it's the same way.
+pub struct Recognizer {
+    threshold: i64,
+    /// Motion within the current frame
+    frame: (i32, i32),
+    /// Motion since the last stroke was registered
+    travelled: (i64, i64),
+    strokes: Vec<Direction>,
+}
+
+impl Recognizer {
+    pub fn new(threshold: u32) -> Recognizer {
+        Recognizer {
+            threshold: i64::from(threshold.max(1)),
+            frame: (0, 0),
+            travelled: (0, 0),
+            strokes: Vec::new(),
+        }
+    }
+
+    pub fn event(&mut self, ev: &InputEvent) {
+        match ev.event_code {
+            EventCode::EV_REL(EV_REL::REL_X) => {
+                self.frame.0 = self.frame.0.saturating_add(ev.value)
+            }
+            EventCode::EV_REL(EV_REL::REL_Y) => {
+                self.frame.1 = self.frame.1.saturating_add(ev.value)
+            }
+            EventCode::EV_SYN(EV_SYN::SYN_REPORT) => {
+                let (dx, dy) = ::std::mem::take(&mut self.frame);
+                self.motion(dx, dy);
+            }
+            _ => (),
+        }
+    }
+
+    fn motion(&mut self, dx: i32, dy: i32) {
+        let (x, y) = (
+            self.travelled.0 + i64::from(dx),
+            self.travelled.1 + i64::from(dy),
+        );
+        self.travelled = (x, y);
+        if x.abs().max(y.abs()) < self.threshold {
+            return;
+        }
+        // Pointer Y grows downwards
+        let direction = match (x.abs() > y.abs(), x > 0, y > 0) {
+            (true, true, _) => Direction::Right,
+            (true, false, _) => Direction::Left,
+            (false, _, true) => Direction::Down,
+            (false, _, false) => Direction::Up,
+        };
+        if self.strokes.last() != Some(&direction) {
+            self.strokes.push(direction);
+        }
+        self.travelled = (0, 0);
+    }
+
+    /// Registers strokes as though the pointer had drawn `gesture`, for simulating input
+    pub fn draw(&mut self, gesture: &Gesture) {
+        let distance = self.threshold.min(i64::from(i32::MAX)) as i32;
+        for direction in &gesture.0 {
+            match direction {
+                Direction::Up => self.motion(0, -distance),
+                Direction::Down => self.motion(0, distance),
+                Direction::Left => self.motion(-distance, 0),
+                Direction::Right => self.motion(distance, 0),
+            }
+        }
+    }
+
+    /// The gesture drawn so far, which is empty if the pointer hardly moved
+    pub fn finish(self) -> Gesture {
+        Gesture(self.strokes)
+    }
+}
+
+#[test]
+fn test_recognize_strokes() {
+    use evdev_rs::TimeVal;
+    let time = TimeVal::new(0, 0);
+    let frame = |dx: i32, dy: i32| {
+        vec![
+            InputEvent::new(&time, &EventCode::EV_REL(EV_REL::REL_X), dx),
+            InputEvent::new(&time, &EventCode::EV_REL(EV_REL::REL_Y), dy),
+            InputEvent::new(&time, &EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0),
+        ]
+    };
+    let draw = |frames: &[(i32, i32)]| {
+        let mut recognizer = Recognizer::new(50);
+        for &(dx, dy) in frames {
+            for ev in frame(dx, dy) {
+                recognizer.event(&ev);
+            }
+        }
+        recognizer.finish().to_string()
+    };
+
+    assert_eq!(draw(&[]), "");
+    // Jitter never adds up to a stroke
+    assert_eq!(draw(&[(10, -5), (-10, 5), (20, 20), (-15, -15)]), "");
+    // A long wobbly stroke is still one stroke
+    assert_eq!(draw(&[(-20, 3); 10]), "L");
+    assert_eq!(draw(&[(-30, 2), (-30, -1), (1, -30), (-2, -30)]), "L-U");
+    assert_eq!(
+        draw(&[(0, 60), (60, 0), (0, -60), (-60, 5), (40, 10), (20, 0)]),
+        "D-R-U-L-R"
+    );
+
+    // Only whole frames count, and other relative axes are ignored
+    let mut recognizer = Recognizer::new(50);
+    let feed = |recognizer: &mut Recognizer, code, value| {
+        recognizer.event(&InputEvent::new(&time, &code, value))
+    };
+    feed(&mut recognizer, EventCode::EV_REL(EV_REL::REL_WHEEL), 80);
+    feed(&mut recognizer, EventCode::EV_REL(EV_REL::REL_X), 80);
+    assert_eq!(recognizer.strokes, vec![]);
+    feed(&mut recognizer, EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0);
+    assert_eq!(recognizer.finish(), Gesture(vec![Direction::Right]));
+
+    let mut recognizer = Recognizer::new(50);
+    feed(&mut recognizer, EventCode::EV_REL(EV_REL::REL_X), -30);
+    feed(&mut recognizer, EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0);
+    recognizer.draw(&Gesture(vec![Direction::Left, Direction::Up]));
+    assert_eq!(recognizer.finish().to_string(), "L-U");
+}
+
+#[test]
+fn test_parse_gestures() {
+    use std::str::FromStr;
+    assert_eq!(
+        Gesture::from_str("L-U"),
+        Ok(Gesture(vec![Direction::Left, Direction::Up]))
+    );
+    assert_eq!(
+        Gesture::from_str("down - right").map(|g| g.to_string()),
+        Ok("D-R".to_string())
+    );
+    assert!(Gesture::from_str("").is_err());
+    assert!(Gesture::from_str("L-X").is_err());
+    assert!(Gesture::from_str("U-U").is_err());
+}
@@ -0,0 +1,198 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct CommandOutcome {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// A persistent connection to i3 or sway's IPC socket, reconnecting as needed
+#[derive(Default)]
+pub struct I3Connection {
+    socket_path: Option<PathBuf>,
+    stream: Option<UnixStream>,
+}
+
+impl I3Connection {
+    pub fn with_socket_path(path: PathBuf) -> I3Connection {
+        I3Connection {
+            socket_path: Some(path),
+            stream: None,
+        }
+    }
+
+    fn socket_path(&self) -> Result<PathBuf, String> {
+        if let Some(path) = &self.socket_path {
+            return Ok(path.clone());
+        }
+        for var in &["I3SOCK", "SWAYSOCK"] {
+            if let Some(path) = ::std::env::var_os(var).filter(|p| !p.is_empty()) {
+                return Ok(PathBuf::from(path));
+            }
+        }
+        // i3 can also tell us, if we've been started outside of its session
+        ::std::process::Command::new("i3")
+            .arg("--get-socketpath")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+            .filter(|path| !path.as_os_str().is_empty())
+            .ok_or_else(|| {
+                "Couldn't find the i3/sway IPC socket; is I3SOCK or SWAYSOCK set?".to_string()
+            })
+    }
+
+    /// Sends a message, connecting first if need be
+    fn send(&mut self, message_type: u32, payload: &[u8]) -> ::std::io::Result<()> {
+        if self.stream.is_none() {
+            let path = self
+                .socket_path()
+                .map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::NotFound, e))?;
+            let stream = UnixStream::connect(&path)?;
+            stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+            self.stream = Some(stream);
+        }
+        let stream = self.stream.as_mut().unwrap();
+
+        let mut message = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(payload);
+        stream.write_all(&message)
+    }
+
+    /// Reads the reply to the message last sent
+    fn receive(&mut self) -> ::std::io::Result<Vec<u8>> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Err(::std::io::ErrorKind::NotConnected.into()),
+        };
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(::std::io::Error::new(
+                ::std::io::ErrorKind::InvalidData,
+                "Reply didn't start with the i3-ipc magic string",
+            ));
+        }
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[6..10]);
+        let mut reply = vec![0u8; u32::from_ne_bytes(length) as usize];
+        stream.read_exact(&mut reply)?;
+        Ok(reply)
+    }
+
+    /// Runs an i3 command, reporting the errors i3 replies with
+    pub fn run_command(&mut self, command: &str) -> Result<(), String> {
+        if self.send(RUN_COMMAND, command.as_bytes()).is_err() {
+            // The window manager may have restarted since we last spoke, so that the command
+            // never reached it; try once more
+            self.stream = None;
+            self.send(RUN_COMMAND, command.as_bytes()).map_err(|e| {
+                self.stream = None;
+                format!("i3 IPC failed: {}", e)
+            })?;
+        }
+        // Once sent, the command may have run, so it's never sent again
+        let reply = self.receive().map_err(|e| {
+            self.stream = None;
+            format!("No reply from i3 to \"{}\": {}", command, e)
+        })?;
+        let outcomes: Vec<CommandOutcome> = serde_json::from_slice(&reply)
+            .map_err(|e| format!("Unexpected reply from i3: {}", e))?;
+        let errors = outcomes
+            .into_iter()
+            .filter(|outcome| !outcome.success)
+            .map(|outcome| outcome.error.unwrap_or_else(|| "unknown error".to_string()))
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "i3 rejected \"{}\": {}",
+                command,
+                errors.join("; ")
+            ))
+        }
+    }
+}
+
+#[test]
+fn test_run_command_against_fake_server() {
+    use std::os::unix::net::UnixListener;
+
+    fn receive(stream: &mut UnixStream) -> String {
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..6], MAGIC);
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[6..10]);
+        let mut payload = vec![0u8; u32::from_ne_bytes(length) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    fn serve(stream: &mut UnixStream, reply: &str) -> String {
+        let payload = receive(stream);
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(reply.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&RUN_COMMAND.to_ne_bytes());
+        message.extend_from_slice(reply.as_bytes());
+        stream.write_all(&message).unwrap();
+        payload
+    }
+
+    let path = ::std::env::temp_dir().join(format!("lg600r-i3ipc-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let (replied, is_replied) = ::std::sync::mpsc::channel();
+    let server = ::std::thread::spawn(move || {
+        let mut received = Vec::new();
+        let (mut first, _) = listener.accept().unwrap();
+        received.push(serve(&mut first, r#"[{"success":true}]"#));
+        received.push(serve(
+            &mut first,
+            r#"[{"success":false,"error":"Unknown command"}]"#,
+        ));
+        // Simulate i3 restarting by dropping the connection
+        drop(first);
+        let (mut second, _) = listener.accept().unwrap();
+        received.push(serve(&mut second, r#"[{"success":true}]"#));
+        // Crash having read a command, without replying to it
+        received.push(receive(&mut second));
+        drop(second);
+        is_replied.recv().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let resent = listener.accept().map(|_| ());
+        assert_eq!(resent.unwrap_err().kind(), ::std::io::ErrorKind::WouldBlock);
+        received
+    });
+
+    let mut i3 = I3Connection::with_socket_path(path.clone());
+    assert_eq!(i3.run_command("focus left"), Ok(()));
+    assert!(i3
+        .run_command("bogus")
+        .unwrap_err()
+        .contains("Unknown command"));
+    assert_eq!(i3.run_command("focus right"), Ok(()));
+    assert!(i3
+        .run_command("kill")
+        .unwrap_err()
+        .starts_with("No reply from i3 to \"kill\""));
+    replied.send(()).unwrap();
+    assert_eq!(
+        server.join().unwrap(),
+        vec!["focus left", "bogus", "focus right", "kill"]
+    );
+    ::std::fs::remove_file(&path).unwrap();
+}
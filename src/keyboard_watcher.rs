@@ -1,98 +1,460 @@
+use crate::access;
+use crate::device;
 use crate::linput;
-use evdev_rs::enums::{BusType, EventCode, EventType, EV_MSC};
-use evdev_rs::{Device, InputEvent, ReadFlag, ReadStatus};
+use crate::systemd::Priority;
+use evdev_rs::enums::{BusType, EventCode, EventType, EV_MSC, EV_SYN};
+use evdev_rs::{Device, InputEvent, ReadFlag, ReadStatus, UInputDevice};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
+use std::io;
 use std::os::unix::prelude::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::{mem, slice};
 
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardSettings {
+    /// Whether keys without a binding are passed through, so that the mouse's own mappings
+    /// still work for them, rather than swallowed
+    pub passthrough: bool,
+    #[serde(skip_serializing_if = "GrabMode::is_always")]
+    pub grab: GrabMode,
+}
+
+/// When the keyboard interface is grabbed, which keeps its keys from anything else reading it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrabMode {
+    /// All along, so that bound keys do only what they're bound to
+    Always,
+    /// Not at all, so that keys also do whatever they did, as when running alongside another
+    /// remapper or only watching what keys send
+    Never,
+    /// Only while the profile of this name is active
+    Profile(String),
+}
+
+// Written out, as deriving a default variant needs a newer compiler than lg600r builds with
+#[allow(clippy::derivable_impls)]
+impl Default for GrabMode {
+    fn default() -> Self {
+        GrabMode::Always
+    }
+}
+
+impl GrabMode {
+    fn is_always(&self) -> bool {
+        *self == GrabMode::Always
+    }
+
+    /// Whether the keyboard interface is to be grabbed while `profile` is active
+    pub fn grabs(&self, profile: Option<&str>) -> bool {
+        match self {
+            GrabMode::Always => true,
+            GrabMode::Never => false,
+            GrabMode::Profile(name) => profile == Some(name.as_str()),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for GrabMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        use serde_value::Value;
+        // Read by hand, as TOML only reads enums with values from inline tables
+        let value = Value::deserialize(deserializer)?;
+        let profile = Value::String("profile".to_string());
+        match &value {
+            Value::String(mode) if mode == "always" => Ok(GrabMode::Always),
+            Value::String(mode) if mode == "never" => Ok(GrabMode::Never),
+            Value::Map(table) if table.len() == 1 => match table.get(&profile) {
+                Some(Value::String(name)) => Ok(GrabMode::Profile(name.clone())),
+                _ => Err(D::Error::custom("Expected { profile = \"<name>\" }")),
+            },
+            _ => Err(D::Error::custom(
+                "Expected \"always\", \"never\" or { profile = \"<name>\" }",
+            )),
+        }
+    }
+}
+
+impl serde::Serialize for GrabMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        match self {
+            GrabMode::Always => serializer.serialize_str("always"),
+            GrabMode::Never => serializer.serialize_str("never"),
+            GrabMode::Profile(name) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("profile", name)?;
+                map.end()
+            }
+        }
+    }
+}
+
+/// Grabs and releases a keyboard interface from any thread, while another one reads it
+pub struct Grab {
+    node: PathBuf,
+    /// A duplicate of the interface's descriptor, sharing the grab with the one being read
+    file: File,
+    grabbed: AtomicBool,
+}
+
+impl Grab {
+    pub fn new(node: &Path, f: &File) -> io::Result<Grab> {
+        Ok(Grab {
+            node: node.to_path_buf(),
+            file: f.try_clone()?,
+            grabbed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed.load(Ordering::SeqCst)
+    }
+
+    /// Grabs or releases the interface, explaining any failure
+    pub fn set(&self, grab: bool) -> Result<(), String> {
+        if self.is_grabbed() == grab {
+            return Ok(());
+        }
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                linput::_EVIOCGRAB as _,
+                grab as libc::c_int,
+            )
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            return Err(if grab && error.raw_os_error() == Some(libc::EBUSY) {
+                format!(
+                    "{}; to run alongside it, set grab = \"never\" under [keyboard]",
+                    access::explain_grab(&self.node, &error)
+                )
+            } else if grab {
+                access::explain_grab(&self.node, &error)
+            } else {
+                format!("Couldn't release {}: {}", self.node.display(), error)
+            });
+        }
+        self.grabbed.store(grab, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Which scancodes are re-emitted on the virtual keyboard
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Passthrough {
+    /// Whether scancodes not in `bound` are passed through
+    pub unbound: bool,
+    /// Scancodes passed through whatever the policy for unbound keys
+    pub always: BTreeSet<u32>,
+    pub bound: BTreeSet<u32>,
+}
+
+impl Passthrough {
+    pub fn passes(&self, scancode: u32) -> bool {
+        self.always.contains(&scancode) || (self.unbound && !self.bound.contains(&scancode))
+    }
+
+    /// Whether anything could be passed through, and so a virtual keyboard is needed
+    pub fn is_used(&self) -> bool {
+        self.unbound || !self.always.is_empty()
+    }
+}
+
 pub struct KeyboardWatcher {
     device: Device,
+    /// The virtual keyboard keys are passed through to, if any are
+    passthrough: Option<UInputDevice>,
+    /// Keys are only passed through while grabbed, as they otherwise get through anyway
+    grab: Arc<Grab>,
+    /// The bits of each scan that make up its scancode, if not those picked out by its usage page
+    scancode_mask: Option<u32>,
+    /// The scan last reported with each key code, for key events that come without one
+    scans_by_key: BTreeMap<u32, u32>,
 }
 
 impl KeyboardWatcher {
-    pub fn create(f: File) -> Result<KeyboardWatcher, String> {
+    pub fn create(
+        f: File,
+        grab: Arc<Grab>,
+        passthrough: bool,
+        scancode_mask: Option<u32>,
+    ) -> Result<KeyboardWatcher, String> {
         let mut d = Device::new().expect("Libevdev must be installed and available");
-        d.set_fd(f).expect("Expected to mount device successfully");
-        d.grab(evdev_rs::GrabMode::Grab)
-            .expect("Failed to EVIOCGRAB device");
-        Ok(KeyboardWatcher { device: d })
-    }
-
-    fn next_event_matching<C: (Fn(&InputEvent) -> bool), B: (Fn(&InputEvent) -> bool)>(
-        device: &mut Device,
-        choose_on: C,
-        bail_on: B,
-    ) -> Result<InputEvent, InputEvent> {
-        let read_flags = evdev_rs::ReadFlag::NORMAL | evdev_rs::ReadFlag::BLOCKING;
-        loop {
-            match device.next_event(read_flags) {
-                Ok((_, ev)) if choose_on(&ev) => return Ok(ev),
-                Ok((_, bail)) if bail_on(&bail) => return Err(bail),
-                Ok((_, _ev)) => (),
-                Err(e) => {
-                    println!("Error encountered: {}", e);
-                }
-            };
+        d.set_fd(f)
+            .map_err(|e| format!("Couldn't mount keyboard device: {}", e))?;
+        // A virtual copy of the keyboard interface, emitting the same key codes it would have
+        let passthrough = if passthrough {
+            UInputDevice::create_from_device(&d)
+                .map_err(|e| {
+                    eprintln!(
+                        "{}Couldn't create a virtual keyboard, so no keys will pass through: {}",
+                        Priority::Warning,
+                        access::explain(Path::new(access::UINPUT), true, &e)
+                    )
+                })
+                .ok()
+        } else {
+            None
+        };
+        Ok(KeyboardWatcher {
+            device: d,
+            passthrough,
+            grab,
+            scancode_mask,
+            scans_by_key: BTreeMap::new(),
+        })
+    }
+
+    /// Re-emits a key event, along with the scan that came before it, if one did
+    fn pass_through(&self, scan: Option<&InputEvent>, key: &InputEvent) {
+        if let Some(uinput) = &self.passthrough {
+            let report = InputEvent::new(&key.time, &EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0);
+            let written = scan
+                .map_or(Ok(()), |scan| uinput.write_event(scan))
+                .and_then(|()| uinput.write_event(key))
+                .and_then(|()| uinput.write_event(&report));
+            if let Err(e) = written {
+                eprintln!("{}Couldn't pass key through: {}", Priority::Warning, e);
+            }
         }
     }
 
-    pub fn watch<F: FnMut(u32, bool) -> ()>(
+    /// Calls `callback` for each key event, with its scancode if it could be told and its key
+    /// code, which returns whether to pass the key through
+    pub fn watch<F: FnMut(Option<u32>, u32, bool) -> bool>(
         &mut self,
         mut callback: F,
         exit: &RefCell<bool>,
     ) -> Result<(), Box<dyn (::std::error::Error)>> {
-        use std::io::prelude::*;
-        use std::io::BufReader;
+        let read_flags = ReadFlag::NORMAL | ReadFlag::BLOCKING;
+        self.watch_events(
+            |device| device.next_event(read_flags).map(|(_, ev)| ev),
+            callback,
+            exit,
+        )
+    }
 
-        let mut bailed_scan = None;
+    /// Watches the events `read` reads from the device, until it fails with anything but
+    /// EAGAIN, as it does with ENODEV once the device is unplugged
+    fn watch_events<R, F>(
+        &mut self,
+        mut read: R,
+        mut callback: F,
+        exit: &RefCell<bool>,
+    ) -> Result<(), Box<dyn (::std::error::Error)>>
+    where
+        R: FnMut(&mut Device) -> io::Result<InputEvent>,
+        F: FnMut(Option<u32>, u32, bool) -> bool,
+    {
+        // The scans and key events since the last report, which belong together
+        let mut frame = Vec::new();
         loop {
-            let choose_scan_ev = |ev: &InputEvent| ev.is_code(&EventCode::EV_MSC(EV_MSC::MSC_SCAN));
-            let choose_key_ev = |ev: &InputEvent| ev.is_type(&EventType::EV_KEY);
-            let scan = match bailed_scan {
-                Some(bailed) => bailed,
-                None => {
-                    let scan_or_bail =
-                        Self::next_event_matching(&mut self.device, choose_scan_ev, choose_key_ev);
-                    match scan_or_bail {
-                        Err(bail) => {
-                            println!("Key event / Scans detected out of order from:\n{:#?}", bail);
-                            println!(" ... Skipping to next scan...");
-                            continue;
-                        }
-                        Ok(scan) => scan,
-                    }
-                }
+            let ev = match read(&mut self.device) {
+                Ok(ev) => ev,
+                Err(ref e) if e.raw_os_error() == Some(libc::EAGAIN) => continue,
+                Err(e) => return Err(format!("Couldn't read key events: {}", e).into()),
             };
-            let scancode = (scan.value & (!0x70000));
-            //println!("Scan Event encountered: {:#?} - scancode: {:#?}", &scan, scancode);
-            let key_or_bail =
-                Self::next_event_matching(&mut self.device, choose_key_ev, choose_scan_ev);
-
-            let key = match key_or_bail {
-                Err(bail) => {
-                    println!("Key event / Scans detected out of order from:\n{:#?}", bail);
-                    println!(" ... Saving as next scan...");
-                    bailed_scan = Some(bail);
-                    continue;
+            if ev.is_code(&EventCode::EV_SYN(EV_SYN::SYN_DROPPED)) {
+                println!("Events were dropped; skipping what's left of them");
+                frame.clear();
+            } else if ev.is_code(&EventCode::EV_MSC(EV_MSC::MSC_SCAN))
+                || ev.is_type(&EventType::EV_KEY)
+            {
+                frame.push(ev);
+            } else if ev.is_code(&EventCode::EV_SYN(EV_SYN::SYN_REPORT)) {
+                self.report(&frame, &mut callback);
+                frame.clear();
+                if *exit.borrow() {
+                    break;
                 }
-                Ok(scan) => {
-                    bailed_scan = None;
-                    scan
-                }
-            };
-
-            let pressed = key.value != 0;
-            callback(scancode as u32, pressed);
-
-            if *exit.borrow() {
-                break;
             }
         }
         Ok(())
     }
+
+    /// Calls `callback` for each key event in a frame
+    fn report<F: FnMut(Option<u32>, u32, bool) -> bool>(
+        &mut self,
+        frame: &[InputEvent],
+        callback: &mut F,
+    ) {
+        let reported = frame
+            .iter()
+            .map(|ev| match ev.event_code {
+                EventCode::EV_MSC(_) => Reported::Scan(ev.value as u32),
+                code => Reported::Key(evdev_rs::util::event_code_to_int(&code).1, ev.value),
+            })
+            .collect::<Vec<_>>();
+        for (key, scan, usage) in pair_frame(&reported, &mut self.scans_by_key) {
+            let key = &frame[key];
+            let code = evdev_rs::util::event_code_to_int(&key.event_code).1;
+            let scancode = usage.map(|usage| device::scancode(usage, self.scancode_mask));
+            if callback(scancode, code, key.value != 0) && self.grab.is_grabbed() {
+                self.pass_through(scan.map(|scan| &frame[scan]), key);
+            }
+        }
+    }
+}
+
+/// What a frame's events tell of its keys, in the order they came
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reported {
+    /// An `MSC_SCAN`, giving the HID usage of the key event after it
+    Scan(u32),
+    /// An `EV_KEY`, by key code, released (0), pressed (1) or repeating (2)
+    Key(u32, i32),
+}
+
+/// Pairs each key event in a frame with a scan before it, in the order they came, or if there's
+/// none left, with the scan last reported for its key code, which `scans_by_key` keeps. Each is
+/// given as its index, its scan's index if it had one, and its usage if it could be told.
+/// Autorepeats are left out, as lg600r's own bindings do whatever repeating they do.
+fn pair_frame(
+    frame: &[Reported],
+    scans_by_key: &mut BTreeMap<u32, u32>,
+) -> Vec<(usize, Option<usize>, Option<u32>)> {
+    let mut scans = std::collections::VecDeque::new();
+    let mut paired = Vec::new();
+    for (index, reported) in frame.iter().enumerate() {
+        match *reported {
+            Reported::Scan(usage) => scans.push_back((index, usage)),
+            Reported::Key(_, 2) => (),
+            Reported::Key(code, _) => match scans.pop_front() {
+                Some((scan, usage)) => {
+                    scans_by_key.insert(code, usage);
+                    paired.push((index, Some(scan), Some(usage)));
+                }
+                None => paired.push((index, None, scans_by_key.get(&code).cloned())),
+            },
+        }
+    }
+    paired
 }
 
 impl Drop for KeyboardWatcher {
     fn drop(&mut self) {}
 }
+
+#[test]
+fn test_passthrough_policy() {
+    let mut passthrough = Passthrough {
+        unbound: false,
+        always: vec![31].into_iter().collect(),
+        bound: vec![30, 32].into_iter().collect(),
+    };
+    assert!(passthrough.is_used());
+    assert_eq!(
+        (passthrough.passes(31), passthrough.passes(33)),
+        (true, false)
+    );
+    passthrough.unbound = true;
+    assert_eq!(
+        (passthrough.passes(30), passthrough.passes(33)),
+        (false, true)
+    );
+    assert!(!Passthrough::default().is_used());
+}
+
+#[test]
+fn test_pair_scans_with_keys() {
+    use Reported::{Key, Scan};
+    let mut scans_by_key = BTreeMap::new();
+    // Two keys pressed at once, each after its own scan
+    let both = [Scan(0x7001e), Key(2, 1), Scan(0x7001f), Key(3, 1)];
+    assert_eq!(
+        pair_frame(&both, &mut scans_by_key),
+        vec![(1, Some(0), Some(0x7001e)), (3, Some(2), Some(0x7001f))]
+    );
+    // Both scans first, then both keys; a repeat, and a release without its scan
+    let bunched = [
+        Scan(0x70020),
+        Scan(0x70021),
+        Key(4, 1),
+        Key(5, 0),
+        Key(2, 2),
+        Key(3, 0),
+    ];
+    assert_eq!(
+        pair_frame(&bunched, &mut scans_by_key),
+        vec![
+            (2, Some(0), Some(0x70020)),
+            (3, Some(1), Some(0x70021)),
+            (5, None, Some(0x7001f)),
+        ]
+    );
+    // A scan with no key event, as for a key already in that state, pairs with nothing
+    assert_eq!(pair_frame(&[Scan(0x70022)], &mut scans_by_key), vec![]);
+    assert_eq!(
+        pair_frame(&[Key(30, 1)], &mut scans_by_key),
+        vec![(0, None, None)]
+    );
+}
+
+#[test]
+fn test_grab_modes() {
+    let parse = |source: &str| toml::from_str::<KeyboardSettings>(source).map(|k| k.grab);
+    assert_eq!(parse("").unwrap(), GrabMode::Always);
+    assert_eq!(parse("grab = \"never\"").unwrap(), GrabMode::Never);
+    let games = parse("grab = { profile = \"games\" }").unwrap();
+    assert_eq!(games, GrabMode::Profile("games".to_string()));
+    assert!(parse("grab = \"sometimes\"").is_err());
+    assert_eq!(
+        (games.grabs(None), games.grabs(Some("games"))),
+        (false, true)
+    );
+    assert!(GrabMode::Always.grabs(Some("games")) && !GrabMode::Never.grabs(None));
+
+    let settings = KeyboardSettings {
+        passthrough: true,
+        grab: games,
+    };
+    let serialized = toml::to_string(&settings).unwrap();
+    assert_eq!(
+        toml::from_str::<KeyboardSettings>(&serialized).unwrap(),
+        settings
+    );
+    assert!(!toml::to_string(&KeyboardSettings::default())
+        .unwrap()
+        .contains("grab"));
+}
+
+#[test]
+fn test_unplugged_device_stops_watching() {
+    let null = File::open("/dev/null").unwrap();
+    let mut watcher = KeyboardWatcher {
+        device: Device::new().unwrap(),
+        passthrough: None,
+        grab: Arc::new(Grab::new(Path::new("/dev/null"), &null).unwrap()),
+        scancode_mask: None,
+        scans_by_key: BTreeMap::new(),
+    };
+    let mut reads = vec![
+        Err(io::Error::from_raw_os_error(libc::EAGAIN)),
+        Err(io::Error::from_raw_os_error(libc::ENODEV)),
+    ]
+    .into_iter();
+    let mut called = false;
+    let result = watcher.watch_events(
+        |_| reads.next().expect("Must stop reading once unplugged"),
+        |_, _, _| {
+            called = true;
+            false
+        },
+        &RefCell::new(false),
+    );
+    assert!(result.unwrap_err().to_string().contains("No such device"));
+    assert!(!called);
+}
@@ -10,130 +10,1271 @@ extern crate xdg;
 extern crate serde_derive;
 extern crate libc;
 
+use crate::config::gkey;
 use crate::config::BindingType;
-use crate::xdo::KeyboardControllable;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use systemd::Priority;
 
+mod access;
+mod chord;
 mod config;
+mod device;
+mod dispatch;
+mod exec;
+mod gesture;
+mod i3ipc;
 mod keyboard_watcher;
+mod keycodes;
 mod linput;
+mod mpris;
+mod notify;
+mod pointer_watcher;
+mod simulate;
+mod stats;
+mod systemd;
+mod volume;
 mod xdo;
 
-fn find_g600() -> io::Result<std::path::PathBuf> {
-    const KPREFIX: &'static str = "usb-Logitech_Gaming_Mouse_G600_";
-    const KSUFFIX: &'static str = "-if01-event-kbd";
+/// What each G-key does and which scancode each sends, derived from a configuration
+struct Keymap {
+    bindings: BTreeMap<u32, BindingType>,
+    gkeys_by_scancode: BTreeMap<u32, u32>,
+    notify: BTreeSet<u32>,
+    gestures: Option<config::GestureSettings>,
+    /// Whether the pointer interface is to be grabbed, which only changes on restarting
+    grab: bool,
+    /// Whether keys without a binding are passed through
+    passthrough_unbound: bool,
+}
+
+impl Keymap {
+    fn new(
+        config::Configuration {
+            bindings: dotcommands,
+            scancodes,
+            notify,
+            gestures,
+            pointer,
+            keyboard,
+            ..
+        }: config::Configuration,
+    ) -> Keymap {
+        let grab = pointer.map(|pointer| pointer.grab) == Some(true);
+        let mut bindings = build_default_commands()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        for (gkey, cmd) in &dotcommands {
+            bindings.insert(*gkey, cmd.clone());
+        }
+        let mut gkeys_by_scancode = scancodes
+            .iter()
+            .map(|(gkey, scancode)| (*scancode, *gkey))
+            .collect::<BTreeMap<_, _>>();
+        // Bound G-keys without a scancode, which `lg600r check` warns about, use their number
+        for gkey in bindings.keys() {
+            let scanned = scancodes.iter().any(|(scanned, _)| scanned == gkey);
+            if scanned || (grab && gkey::is_pointer_gkey(*gkey)) {
+                continue;
+            }
+            gkeys_by_scancode.entry(*gkey).or_insert(*gkey);
+        }
+        Keymap {
+            bindings,
+            gkeys_by_scancode,
+            notify,
+            gestures,
+            grab,
+            passthrough_unbound: keyboard.map(|keyboard| keyboard.passthrough) == Some(true),
+        }
+    }
+
+    /// Whether `gkey` is held to draw gestures
+    fn is_gesture_trigger(&self, gkey: u32) -> bool {
+        self.gestures.as_ref().map(|gestures| gestures.trigger) == Some(gkey)
+    }
+
+    /// Bound G-keys on the pointer interface, which a grabbed pointer doesn't pass through
+    fn pointer_gkeys(&self) -> BTreeSet<u32> {
+        let trigger = self.gestures.as_ref().map(|gestures| gestures.trigger);
+        self.bindings
+            .iter()
+            .filter(|(_, binding)| **binding != BindingType::Passthrough)
+            .map(|(gkey, _)| *gkey)
+            .chain(trigger)
+            .filter(|gkey| gkey::is_pointer_gkey(*gkey))
+            .collect()
+    }
 
-    let kdir = std::path::PathBuf::from("/dev/input/by-id");
-    let inputbyid: fs::ReadDir = fs::read_dir(kdir.as_path())?;
-    for p in inputbyid {
-        let path = p?.path();
-        let fname = String::from(path.file_name().unwrap().to_str().unwrap());
-        if fname.starts_with(KPREFIX) && fname.ends_with(KSUFFIX) {
-            return Ok(path);
+    /// Which scancodes are passed through to the virtual keyboard
+    fn passthrough(&self) -> keyboard_watcher::Passthrough {
+        let mut passthrough = keyboard_watcher::Passthrough {
+            unbound: self.passthrough_unbound,
+            ..Default::default()
+        };
+        for (&scancode, gkey) in &self.gkeys_by_scancode {
+            match self.bindings.get(gkey) {
+                Some(BindingType::Passthrough) => passthrough.always.insert(scancode),
+                Some(_) => passthrough.bound.insert(scancode),
+                None if self.is_gesture_trigger(*gkey) => passthrough.bound.insert(scancode),
+                None => false,
+            };
         }
+        passthrough
+    }
+
+    /// Key codes that G-keys are told apart by, rather than by their scans
+    fn keyed(&self) -> BTreeSet<u32> {
+        self.gkeys_by_scancode
+            .keys()
+            .filter_map(|scancode| device::scancode_keycode(*scancode))
+            .collect()
+    }
+
+    /// The scancode a G-key sends
+    fn scancode(&self, gkey: u32) -> Option<u32> {
+        self.gkeys_by_scancode
+            .iter()
+            .find(|(_, mapped)| **mapped == gkey)
+            .map(|(scancode, _)| *scancode)
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        "Failed to find g600",
-    ))
 }
 
-fn format_gkey(gkey: u32) -> String {
-    match gkey {
-        k if k >= 100 => format!("G^{}", &(k - 100)),
-        k => format!("G{}", &k),
+/// Button presses from the interfaces of the devices driven
+enum Input {
+    /// A keypress on a device's keyboard interface, by the device's index, whose scancode, or the
+    /// key code it stands for, tells which G-key sent it
+    Scancode(usize, u32, bool),
+    /// A button or wheel notch on the first device's grabbed pointer interface
+    Pointer(u32, bool),
+}
+
+/// What the threads watching the devices' interfaces share with the rest of lg600r
+#[derive(Default)]
+struct WatcherState {
+    /// Keyboard scancodes which are passed through, for each device
+    passthrough: Mutex<Vec<keyboard_watcher::Passthrough>>,
+    /// Key codes that G-keys are told apart by instead of scans, for each device
+    keyed: Mutex<Vec<BTreeSet<u32>>>,
+    /// Bound G-keys on the pointer interface
+    bound: Mutex<BTreeSet<u32>>,
+    /// Whether G-shift is held, putting the pointer's buttons in the G-shift layer
+    shifted: AtomicBool,
+    /// The gesture being drawn, while its trigger is held
+    drawing: Mutex<Option<gesture::Recognizer>>,
+}
+
+/// Watches the pointer interface for gestures and, if it's grabbed, for bound buttons
+fn watch_pointer(
+    found: &device::Found,
+    state: Arc<WatcherState>,
+    grab: bool,
+    inputs: mpsc::Sender<Input>,
+) {
+    let title = found.device.title.clone();
+    let pointer = match &found.pointer {
+        Some(pointer) => pointer.clone(),
+        None => return,
+    };
+    let f = match fs::File::open(&pointer) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!(
+                "{}Gestures and pointer bindings won't work on the {}. {}",
+                Priority::Warning,
+                title,
+                access::explain(&pointer, false, &e)
+            );
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        let created = pointer_watcher::PointerWatcher::create(&pointer, f, grab);
+        let result = created.and_then(|mut watcher| {
+            if grab {
+                println!(
+                    "Grabbed the {}'s pointer interface; anything unbound passes through.",
+                    title
+                );
+            }
+            let mut remapper = pointer_watcher::Remapper::default();
+            watcher.watch(|ev| {
+                let drawing = match state.drawing.lock().unwrap().as_mut() {
+                    Some(recognizer) => {
+                        recognizer.event(ev);
+                        true
+                    }
+                    None => false,
+                };
+                if !grab {
+                    return false;
+                }
+                // A grabbed pointer holds still while a gesture is drawn
+                if drawing && pointer_watcher::is_motion(ev) {
+                    return true;
+                }
+                let shifted = state.shifted.load(Ordering::SeqCst);
+                let bound = state.bound.lock().unwrap();
+                match remapper.remap(ev, shifted, |gkey| bound.contains(&gkey)) {
+                    pointer_watcher::Remapped::Forward => false,
+                    pointer_watcher::Remapped::GKeys(presses) => {
+                        for (gkey, pressed) in presses {
+                            inputs.send(Input::Pointer(gkey, pressed)).unwrap_or(());
+                        }
+                        true
+                    }
+                }
+            })
+        });
+        if let Err(e) = result {
+            eprintln!(
+                "{}{}; gestures and pointer bindings won't work until restarted",
+                Priority::Error,
+                e
+            );
+        }
+    });
+}
+
+/// Carries out whatever each input is bound to
+struct Controller {
+    /// The devices driven, the first of which has the shared bindings; they only change on
+    /// restarting
+    devices: Vec<device::DeviceDescriptor>,
+    /// What each device's G-keys do, in the same order as `devices`
+    keymaps: Vec<Keymap>,
+    chords: chord::Tracker<(String, BindingType)>,
+    /// Whether G-shift held on one device shifts the keys of the others
+    shared_layers: bool,
+    /// The device G-shift is held on, if it is
+    shifted_on: Option<usize>,
+    /// Keys pressed in the G-shift layer of another device, so that their releases go the same way
+    shifted_by_sharing: BTreeSet<(usize, u32)>,
+    dispatcher: dispatch::Dispatcher,
+    /// Actions running in the background, which are reported and recorded once they finish
+    running: Vec<(Performed, dispatch::Running)>,
+    notifier: notify::Notifier,
+    usage: stats::Stats,
+    /// Whether usage's been recorded since it was last saved
+    unsaved_usage: bool,
+    state: Arc<WatcherState>,
+    /// Whether a virtual keyboard was set up to pass each device's keys through, which only
+    /// happens on starting
+    passes_keys: Vec<bool>,
+    /// The configuration loaded, which the keymaps are rebuilt from on switching profiles
+    config: config::Configuration,
+    /// The profile switched to, if any
+    active: Option<String>,
+    /// Each device's keyboard interface, grabbed or not as the configuration asks
+    grabs: Vec<Arc<keyboard_watcher::Grab>>,
+    /// Whether the input is scripted, in which case nothing is notified and usage isn't saved
+    simulated: bool,
+}
+
+/// What each device's G-keys do, from a configuration and the profile switched to, which only
+/// the first device's bindings are switched by
+fn keymaps(
+    devices: &[device::DeviceDescriptor],
+    config: &config::Configuration,
+    profile: Option<&str>,
+) -> Vec<Keymap> {
+    devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| {
+            let config = config.for_device(&device.name, index == 0);
+            Keymap::new(if index == 0 {
+                config.with_profile(profile)
+            } else {
+                config
+            })
+        })
+        .collect()
+}
+
+/// A pressed binding, with what's reported and recorded once it's finished
+struct Performed {
+    subject: String,
+    binding: BindingType,
+    /// Whether its use is notified
+    asked: bool,
+    /// The device and profile, as `Controller::counted` gives them, and G-key its action is
+    /// counted against
+    counted: (String, String),
+    gkey: u32,
+    started: Instant,
+}
+
+/// Says how much a configuration maps, once for each time it's loaded rather than for each
+/// keymap built from it
+fn log_loaded(config: &config::Configuration) {
+    println!(
+        "Loaded {} commands and {} scancode mappings from dotfile.",
+        config.bindings.len(),
+        config.scancodes.len(),
+    );
+}
+
+/// A chord's keys, by device index, along with its name and what it's bound to
+type ResolvedChord = (BTreeSet<(usize, u32)>, (String, BindingType));
+
+/// Chords as keys of the devices driven, by index, leaving out any on other devices
+fn resolve_chords(
+    devices: &[device::DeviceDescriptor],
+    chords: &[(chord::Chord, BindingType)],
+) -> Vec<ResolvedChord> {
+    chords
+        .iter()
+        .filter_map(|(chord, binding)| {
+            let keys = chord
+                .0
+                .iter()
+                .map(|(name, gkey)| {
+                    let index = match name {
+                        None => Some(0),
+                        Some(name) => devices.iter().position(|device| device.name == *name),
+                    };
+                    index.map(|index| (index, *gkey))
+                })
+                .collect::<Option<BTreeSet<_>>>()?;
+            Some((keys, (format!("Chord {}", chord), binding.clone())))
+        })
+        .collect()
+}
+
+impl Controller {
+    fn new(
+        devices: Vec<device::DeviceDescriptor>,
+        config: config::Configuration,
+        state: Arc<WatcherState>,
+        grabs: Vec<Arc<keyboard_watcher::Grab>>,
+        dispatcher: dispatch::Dispatcher,
+        usage: stats::Stats,
+    ) -> Controller {
+        let notifier = notify::Notifier::new(config.notifications.clone().unwrap_or_default());
+        log_loaded(&config);
+        let keymaps = keymaps(&devices, &config, None);
+        *state.bound.lock().unwrap() = keymaps[0].pointer_gkeys();
+        let passthrough = keymaps.iter().map(Keymap::passthrough).collect::<Vec<_>>();
+        let passes_keys = passthrough.iter().map(|p| p.is_used()).collect();
+        *state.passthrough.lock().unwrap() = passthrough;
+        *state.keyed.lock().unwrap() = keymaps.iter().map(Keymap::keyed).collect();
+        Controller {
+            chords: chord::Tracker::new(resolve_chords(&devices, &config.chords)),
+            shared_layers: config.layers.as_ref().map(|layers| layers.shared) == Some(true),
+            shifted_on: None,
+            shifted_by_sharing: BTreeSet::new(),
+            devices,
+            keymaps,
+            dispatcher,
+            running: Vec::new(),
+            notifier,
+            usage,
+            unsaved_usage: false,
+            state,
+            passes_keys,
+            config,
+            active: None,
+            grabs,
+            simulated: false,
+        }
+    }
+
+    /// Carries out scripted input, recording what it emits in `output` rather than emitting it
+    fn simulated(
+        devices: Vec<device::DeviceDescriptor>,
+        config: config::Configuration,
+        output: xdo::recording::Recording,
+    ) -> Controller {
+        let mut controller = Controller::new(
+            devices,
+            config,
+            Arc::new(WatcherState::default()),
+            Vec::new(),
+            dispatch::Dispatcher::simulated(Box::new(output)),
+            stats::Stats::default(),
+        );
+        controller.notifier.settings.enabled = false;
+        controller.simulated = true;
+        controller
+    }
+
+    /// Grabs the keyboard interfaces, or releases them, as the grab mode asks of the profile
+    /// switched to
+    fn grab(&self) -> Result<(), String> {
+        let mode = self.config.keyboard.clone().unwrap_or_default().grab;
+        let grabbing = mode.grabs(self.active.as_deref());
+        self.grabs.iter().try_for_each(|grab| grab.set(grabbing))
+    }
+
+    /// Rebuilds what each device's G-keys do, after reloading or switching profiles
+    fn rebuild(&mut self) {
+        let mut keymaps = keymaps(&self.devices, &self.config, self.active.as_deref());
+        if keymaps[0].grab != self.keymaps[0].grab {
+            eprintln!(
+                "{}Grabbing the pointer is only changed by restarting",
+                Priority::Warning
+            );
+            keymaps[0].grab = self.keymaps[0].grab;
+        }
+        let passthrough = keymaps.iter().map(Keymap::passthrough).collect::<Vec<_>>();
+        let unstarted = passthrough
+            .iter()
+            .zip(&self.passes_keys)
+            .any(|(passthrough, passes)| passthrough.is_used() && !passes);
+        if unstarted {
+            eprintln!(
+                "{}Passing keys through only starts on restarting",
+                Priority::Warning
+            );
+        }
+        *self.state.bound.lock().unwrap() = keymaps[0].pointer_gkeys();
+        *self.state.passthrough.lock().unwrap() = passthrough;
+        *self.state.keyed.lock().unwrap() = keymaps.iter().map(Keymap::keyed).collect();
+        self.keymaps = keymaps;
+        if let Err(e) = self.grab() {
+            eprintln!("{}{}", Priority::Error, e);
+        }
+    }
+
+    /// Switches the first device's bindings to a profile's, or without one back to its own
+    fn switch_profile(&mut self, profile: Option<String>) -> Result<(), String> {
+        if let Some(name) = &profile {
+            if !self.config.has_profile(name) {
+                return Err(format!("There's no profile named \"{}\"", name));
+            }
+        }
+        if profile != self.active {
+            let switched = match &profile {
+                Some(name) => format!("Switched to profile \"{}\"", name),
+                None => format!(
+                    "Switched back from profile \"{}\"",
+                    self.active.as_ref().unwrap()
+                ),
+            };
+            println!("{}", switched);
+            self.notifier.state("Profile", &switched);
+            self.active = profile;
+            self.rebuild();
+        }
+        Ok(())
+    }
+
+    fn reload(&mut self, dotfile: &std::path::Path) {
+        match config::load_configuration_from_dotfile(dotfile) {
+            Ok(config) => {
+                println!("Reloaded {}", dotfile.to_string_lossy());
+                log_loaded(&config);
+                self.notifier.settings = config.notifications.clone().unwrap_or_default();
+                if let Some(active) = &self.active {
+                    if !config.has_profile(active) {
+                        eprintln!(
+                            "{}Profile \"{}\" is gone, so switching back from it",
+                            Priority::Warning,
+                            active
+                        );
+                        self.active = None;
+                    }
+                }
+                self.chords
+                    .set_chords(resolve_chords(&self.devices, &config.chords));
+                self.shared_layers =
+                    config.layers.as_ref().map(|layers| layers.shared) == Some(true);
+                self.config = config;
+                self.rebuild();
+            }
+            Err(e) => {
+                eprintln!(
+                    "{}Couldn't reload configuration; keeping the old one: {}",
+                    Priority::Error,
+                    e
+                );
+                self.notifier
+                    .error("Couldn't reload configuration", &e.to_string(), true);
+            }
+        }
+    }
+
+    /// What a device's G-key is called, naming the device if there are several
+    fn label(&self, index: usize, gkey: u32) -> String {
+        let device = &self.devices[index];
+        if self.devices.len() > 1 {
+            format!("{}.{}", device.name, device.label(gkey))
+        } else {
+            device.label(gkey)
+        }
+    }
+
+    /// The device, by name, and the profile a device's presses are counted under; profiles only
+    /// switch the first device's bindings
+    fn counted(&self, index: usize) -> (String, String) {
+        let profile = match &self.active {
+            Some(active) if index == 0 => active.clone(),
+            _ => stats::DEFAULT_PROFILE.to_string(),
+        };
+        (self.devices[index].name.clone(), profile)
+    }
+
+    /// Counts a press against the device and profile `counted` gives
+    fn record_press(&mut self, (device, profile): &(String, String), gkey: u32) {
+        self.usage.record_press(device, profile, gkey);
+    }
+
+    fn handle(&mut self, input: Input) {
+        let (index, gkey, pressed, source, passed) = match input {
+            Input::Scancode(index, scancode, pressed) => (
+                index,
+                self.keymaps[index]
+                    .gkeys_by_scancode
+                    .get(&scancode)
+                    .cloned(),
+                pressed,
+                match device::scancode_keycode(scancode) {
+                    Some(_) => format!("Key {}", device::format_scancode(scancode)),
+                    None => format!("Scancode {:>2}", scancode),
+                },
+                self.passes_keys[index]
+                    && self.state.passthrough.lock().unwrap()[index].passes(scancode),
+            ),
+            Input::Pointer(gkey, pressed) => (0, Some(gkey), pressed, "Pointer".to_string(), false),
+        };
+        let source = if self.devices.len() > 1 {
+            format!("{} {}", self.devices[index].name, source)
+        } else {
+            source
+        };
+        let direction = if pressed { "v" } else { "^" };
+        let passed = if passed { ", passed through" } else { "" };
+        let gkey = match gkey {
+            Some(gkey) => gkey,
+            None => return println!("{}{} (unmapped) is unbound{}", source, direction, passed),
+        };
+        if gkey == gkey::GSHIFT {
+            self.shifted_on = if pressed { Some(index) } else { None };
+            if index == 0 || self.shared_layers {
+                let was = self.state.shifted.swap(pressed, Ordering::SeqCst);
+                if self.shared_layers && was != pressed {
+                    let layer = if pressed {
+                        "Shifted every device's keys"
+                    } else {
+                        "Back to every device's own keys"
+                    };
+                    self.notifier.state("Layer", layer);
+                }
+            }
+        }
+        // With shared layers, G-shift held on one device shifts the other devices' keys
+        let sharing = match self.shifted_on {
+            Some(on) => self.shared_layers && on != index && gkey < gkey::GSHIFT_OFFSET,
+            None => false,
+        };
+        let gkey = if (pressed && sharing)
+            || (!pressed && self.shifted_by_sharing.remove(&(index, gkey)))
+        {
+            if pressed {
+                self.shifted_by_sharing.insert((index, gkey));
+            }
+            gkey + gkey::GSHIFT_OFFSET
+        } else {
+            gkey
+        };
+        let counted = self.counted(index);
+
+        if pressed {
+            if let Some((subject, binding)) = self.chords.press((index, gkey)).cloned() {
+                self.record_press(&counted, gkey);
+                self.perform_tap(counted, gkey, &subject, binding, false);
+                self.unsaved_usage = true;
+                return;
+            }
+        } else if self.chords.release((index, gkey)) {
+            return println!("{}^ completed a chord", self.label(index, gkey));
+        }
+
+        let keymap = &self.keymaps[index];
+        let trigger = index == 0 && keymap.is_gesture_trigger(gkey);
+        match keymap.bindings.get(&gkey).cloned() {
+            _ if trigger && pressed => {
+                println!("{}v: drawing a gesture", self.label(index, gkey));
+                let threshold = keymap.gestures.as_ref().unwrap().threshold;
+                *self.state.drawing.lock().unwrap() = Some(gesture::Recognizer::new(threshold));
+                self.record_press(&counted, gkey);
+            }
+            own if trigger => self.finish_gesture(gkey, own),
+            Some(binding) => {
+                println!(
+                    "{} ({}){} is bound to {:?}",
+                    self.label(index, gkey),
+                    source,
+                    direction,
+                    binding
+                );
+                let performed = Performed {
+                    subject: self.label(index, gkey),
+                    binding,
+                    asked: keymap.notify.contains(&gkey),
+                    counted: counted.clone(),
+                    gkey,
+                    started: Instant::now(),
+                };
+                let result = self.perform(
+                    &performed.subject,
+                    &performed.binding,
+                    pressed,
+                    performed.asked,
+                );
+                if pressed {
+                    self.record_press(&counted, gkey);
+                    self.finish(performed, result);
+                }
+            }
+            None => {
+                println!(
+                    "{}{} ({}) is unbound{}",
+                    source,
+                    direction,
+                    self.label(index, gkey),
+                    passed
+                );
+                if pressed {
+                    self.record_press(&counted, gkey);
+                }
+            }
+        }
+        if pressed || trigger {
+            self.unsaved_usage = true;
+        }
+    }
+
+    /// Saves usage if any's been recorded since it was last saved
+    fn save_usage(&mut self) {
+        if self.simulated || !self.unsaved_usage {
+            return;
+        }
+        self.unsaved_usage = false;
+        if let Err(e) = self.usage.save() {
+            eprintln!("{}Couldn't save usage statistics: {}", Priority::Warning, e);
+        }
     }
+
+    /// Performs whatever the gesture drawn while holding `trigger` is bound to
+    fn finish_gesture(&mut self, trigger: u32, own: Option<BindingType>) {
+        let drawn = self
+            .state
+            .drawing
+            .lock()
+            .unwrap()
+            .take()
+            .map(gesture::Recognizer::finish)
+            .unwrap_or_default();
+        // Tapping the trigger without drawing anything does what it's bound to
+        let (subject, binding) = if drawn.0.is_empty() {
+            (self.label(0, trigger), own)
+        } else {
+            let gestures = self.keymaps[0].gestures.as_ref().unwrap();
+            (
+                format!("Gesture {}", drawn),
+                gestures.binding(&drawn).cloned(),
+            )
+        };
+        match binding {
+            Some(binding) => {
+                let asked = self.keymaps[0].notify.contains(&trigger);
+                self.perform_tap(self.counted(0), trigger, &subject, binding, asked)
+            }
+            None => println!("{} is unbound", subject),
+        }
+    }
+
+    /// Presses and releases a binding at once, counting the action against `gkey`
+    fn perform_tap(
+        &mut self,
+        counted: (String, String),
+        gkey: u32,
+        subject: &str,
+        binding: BindingType,
+        asked: bool,
+    ) {
+        println!("{} is bound to {:?}", subject, binding);
+        let performed = Performed {
+            subject: subject.to_string(),
+            binding,
+            asked,
+            counted,
+            gkey,
+            started: Instant::now(),
+        };
+        let binding = &performed.binding;
+        let result = self
+            .perform(subject, binding, true, asked)
+            .and_then(|running| {
+                self.perform(subject, binding, false, asked)
+                    .map(|_| running)
+            });
+        self.finish(performed, result);
+    }
+
+    /// Performs a binding, reporting any failure and, if asked to, its use, unless it's left
+    /// running in the background, in which case that waits until it's finished
+    fn perform(
+        &mut self,
+        subject: &str,
+        binding: &BindingType,
+        pressed: bool,
+        asked: bool,
+    ) -> Result<Option<dispatch::Running>, String> {
+        let result = match binding {
+            BindingType::Profile(profile) if pressed => {
+                self.switch_profile(profile.clone()).map(|()| None)
+            }
+            binding => self.dispatcher.perform(binding, pressed),
+        };
+        match result {
+            Ok(Some(running)) => Ok(Some(running)),
+            result => {
+                let result = result.map(|_| ());
+                self.report(subject, binding, pressed, asked, &result);
+                result.map(|()| None)
+            }
+        }
+    }
+
+    /// Reports a binding's failure and, if asked to, its use
+    fn report(
+        &mut self,
+        subject: &str,
+        binding: &BindingType,
+        pressed: bool,
+        asked: bool,
+        result: &Result<(), String>,
+    ) {
+        match result {
+            Err(e) => {
+                eprintln!("{}{} failed: {}", Priority::Error, subject, e);
+                self.notifier
+                    .error(&format!("{} failed", subject), e, asked);
+            }
+            Ok(()) if asked && pressed => {
+                self.notifier.action(subject, &binding.to_string());
+            }
+            Ok(()) => (),
+        }
+    }
+
+    /// Records how a pressed binding went, or leaves that until it's finished in the background
+    fn finish(&mut self, performed: Performed, result: Result<Option<dispatch::Running>, String>) {
+        match result {
+            Ok(Some(running)) => self.running.push((performed, running)),
+            result => self.record_action(&performed, result.is_ok()),
+        }
+    }
+
+    /// Reports and records the actions that have finished in the background
+    fn collect_finished(&mut self) {
+        let mut index = 0;
+        while index < self.running.len() {
+            let result = match self.running[index].1.finished() {
+                Some(result) => result,
+                None => {
+                    index += 1;
+                    continue;
+                }
+            };
+            let (performed, _) = self.running.remove(index);
+            let Performed {
+                subject,
+                binding,
+                asked,
+                ..
+            } = &performed;
+            self.report(subject, binding, true, *asked, &result);
+            self.record_action(&performed, result.is_ok());
+            self.unsaved_usage = true;
+        }
+    }
+
+    /// Counts a pressed binding's action, taking as long as it's been since it was pressed
+    fn record_action(&mut self, performed: &Performed, succeeded: bool) {
+        let (device, profile) = &performed.counted;
+        self.usage.record_action(
+            device,
+            profile,
+            performed.gkey,
+            performed.started.elapsed(),
+            succeeded,
+        );
+    }
+
+    /// What a scripted press or release of a key would come as from the device's interfaces
+    fn scripted_input(&self, key: &simulate::Key, pressed: bool) -> Result<Input, String> {
+        let (device, gkey) = (&key.0, key.1);
+        let index = match device {
+            None => 0,
+            Some(name) => self
+                .devices
+                .iter()
+                .position(|device| device.name == *name)
+                .ok_or_else(|| format!("No device named \"{}\" is driven", name))?,
+        };
+        let keymap = &self.keymaps[index];
+        if index == 0 && keymap.grab && gkey::is_pointer_gkey(gkey) {
+            // The pointer's buttons are in the G-shift layer while G-shift is held
+            let shifted = self.state.shifted.load(Ordering::SeqCst) && gkey < gkey::GSHIFT_OFFSET;
+            let gkey = if shifted {
+                gkey + gkey::GSHIFT_OFFSET
+            } else {
+                gkey
+            };
+            return Ok(Input::Pointer(gkey, pressed));
+        }
+        match keymap.scancode(gkey) {
+            Some(scancode) => Ok(Input::Scancode(index, scancode, pressed)),
+            None => Err(format!(
+                "{} has no scancode, so it can't be pressed",
+                self.label(index, gkey)
+            )),
+        }
+    }
+
+    /// Carries out a step of scripted input, calling `handled` after each press and release
+    fn simulate<F: FnMut()>(
+        &mut self,
+        step: &simulate::Step,
+        mut handled: F,
+    ) -> Result<(), String> {
+        let mut press = |controller: &mut Controller, key, pressed| {
+            controller.handle(controller.scripted_input(key, pressed)?);
+            handled();
+            Ok::<(), String>(())
+        };
+        match step {
+            simulate::Step::Down(key) => press(self, key, true)?,
+            simulate::Step::Up(key) => press(self, key, false)?,
+            simulate::Step::Tap(key) => {
+                press(self, key, true)?;
+                press(self, key, false)?;
+            }
+            simulate::Step::Hold(key, held) => {
+                press(self, key, true)?;
+                // Bindings that repeat while held do so as simulated time passes
+                self.dispatcher.advance(*held);
+                press(self, key, false)?;
+            }
+            simulate::Step::Draw(gesture) => match self.state.drawing.lock().unwrap().as_mut() {
+                Some(recognizer) => recognizer.draw(gesture),
+                None => return Err("Nothing's drawn unless the gesture trigger is held".into()),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Set by SIGHUP, asking for the configuration to be reloaded
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Set by SIGTERM or SIGINT, asking for usage to be saved before exiting
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// How often signals and actions finished in the background are looked for while no input
+/// comes, and at most how often systemd's watchdog is fed
+const TICK: Duration = Duration::from_secs(1);
+
+/// How often usage recorded since it was last saved is saved
+const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The devices to drive, with their interfaces: each of those the config selects that's
+/// plugged in, or if it selects none, whichever is found first
+fn find_devices(
+    config: &config::Configuration,
+) -> Result<Vec<device::Found>, Box<dyn (::std::error::Error)>> {
+    let interfaces = device::scan(std::path::Path::new(device::SYSFS))
+        .map_err(|e| format!("Error: Couldn't list input devices in sysfs: {}", e))?;
+    let known = device::known(&config.devices);
+    let (found, missing) = device::locate(&config.device, &known, &interfaces)?;
+    if config.device.is_empty() && found.is_empty() {
+        let titles = known
+            .iter()
+            .map(|device| device.title.as_str())
+            .collect::<Vec<_>>();
+        return Err(format!(
+            "Error: Couldn't find an input device to drive; none of these are plugged in: {}",
+            titles.join(", ")
+        )
+        .into());
+    }
+    for selector in missing {
+        eprintln!(
+            "{}Couldn't find {}, so it won't be driven",
+            Priority::Warning,
+            selector
+        );
+    }
+    if found.is_empty() {
+        return Err("Error: None of the devices to drive could be found".into());
+    }
+    Ok(found)
 }
 
 fn run(
-    commands: std::collections::BTreeMap<u32, (u32, BindingType)>,
-    scancodes_by_gkey: std::collections::BTreeMap<u32, u32>,
+    dotfile: std::path::PathBuf,
+    config: config::Configuration,
 ) -> Result<(), Box<dyn (::std::error::Error)>> {
     println!("Starting G600 Linux controller.\n");
-    let g600path = find_g600().expect("Error: Couldn't find G600 input device.");
-    let f = fs::File::open(g600path.clone()).map_err(|e| {
-        let msg = format!(
-            "Error: Couldn't open \"{}\" for reading; reason: {}",
-            g600path.into_os_string().to_str().unwrap(),
+    let found = find_devices(&config)?;
+    let (mut keyboards, mut grabs) = (Vec::new(), Vec::new());
+    for located in &found {
+        let (device, keyboard_path) = (&located.device, &located.keyboard);
+        println!(
+            "Driving {} at {}",
+            device.title,
+            keyboard_path.to_string_lossy()
+        );
+        let f = fs::File::open(keyboard_path)
+            .map_err(|e| format!("Error: {}", access::explain(keyboard_path, false, &e)))?;
+        let grab = keyboard_watcher::Grab::new(keyboard_path, &f)
+            .map_err(|e| format!("Error: Couldn't duplicate keyboard device: {}", e))?;
+        grabs.push(Arc::new(grab));
+        keyboards.push((f, device.scancode_mask, device.title.clone()));
+    }
+    match config.keyboard.clone().unwrap_or_default().grab {
+        keyboard_watcher::GrabMode::Always => (),
+        keyboard_watcher::GrabMode::Never => {
+            println!("Not grabbing the keyboard interface, so its keys also do what they did.")
+        }
+        keyboard_watcher::GrabMode::Profile(name) => println!(
+            "Only grabbing the keyboard interface while profile \"{}\" is active.",
+            name
+        ),
+    }
+    let devices = found.iter().map(|located| located.device.clone()).collect();
+    let state = Arc::new(WatcherState::default());
+    let usage = stats::Stats::load().unwrap_or_else(|e| {
+        eprintln!(
+            "{}Couldn't load usage statistics, starting afresh: {}",
+            Priority::Warning,
             e
         );
-        Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, msg))
-    })?;
-    let gkeys_by_scancode = scancodes_by_gkey
-        .iter()
-        .map(|(x, y)| (*y, *x))
-        .collect::<BTreeMap<_, _>>();
-    let exit = RefCell::new(false);
-    keyboard_watcher::KeyboardWatcher::create(f)
-        .map_err(|err| {
-            Box::new(std::io::Error::new(
-                ::std::io::ErrorKind::PermissionDenied,
-                err,
-            ))
-            .into()
-        })
-        .and_then(|mut watcher| {
-            println!("G600 controller started successfully.\n");
-            use crate::xdo::managed as xmanaged;
-            let mut xdm = xmanaged::XdoManaged::default();
-            watcher.watch(
-                |scancode, pressed| {
-                    let cmd = commands.get(&scancode);
-                    match cmd {
-                        Some((gkey, binding)) => {
-                            println!(
-                                "{} (Scancode {:>2}){} is bound to {:?}",
-                                format_gkey(*gkey),
-                                &scancode,
-                                (if pressed { "v" } else { "^" }),
-                                binding
-                            );
-                            match (binding, pressed) {
-                                (BindingType::Command(cmd), true) => {
-                                    use std::process::Command;
-                                    let mut output = Command::new("bash")
-                                        .arg("-c")
-                                        .arg(cmd)
-                                        .spawn()
-                                        .expect("Failed to execute subprocess");
-                                    output.wait().expect("Subprocess should exit");
-                                    println!("Subprocess finished.");
-                                }
-                                (BindingType::Command(_), false) => (),
-                                (BindingType::EmulateMouse(button), pressed) => {
-                                    if pressed {
-                                        xdm.mouse_down(*button);
-                                    } else {
-                                        xdm.mouse_up(*button);
-                                    }
-                                }
-                                (BindingType::EmulateKey(key), pressed) => {
-                                    if pressed {
-                                        xdm.key_down(*key);
-                                    } else {
-                                        xdm.key_up(*key);
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {
-                            println!(
-                                "Scancode {:>2}{} ({}) is unbound",
-                                &scancode,
-                                (if pressed { "v" } else { "^" }),
-                                format_gkey(*gkeys_by_scancode.get(&scancode).unwrap())
-                            );
-                        }
-                    }
+        stats::Stats::default()
+    });
+    let mut controller = Controller::new(
+        devices,
+        config,
+        state.clone(),
+        grabs.clone(),
+        dispatch::Dispatcher::default(),
+        usage,
+    );
+    controller.grab().map_err(|e| format!("Error: {}", e))?;
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            request_reload as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+        for signal in &[libc::SIGTERM, libc::SIGINT] {
+            libc::signal(
+                *signal,
+                request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+
+    let (inputs, received) = mpsc::channel();
+    watch_pointer(
+        &found[0],
+        state.clone(),
+        controller.keymaps[0].grab,
+        inputs.clone(),
+    );
+    let (started, is_started) = mpsc::channel();
+    for (index, ((f, scancode_mask, title), grab)) in keyboards.into_iter().zip(grabs).enumerate() {
+        let passes_keys = controller.passes_keys[index];
+        let (state, inputs, started) = (state.clone(), inputs.clone(), started.clone());
+        std::thread::spawn(move || {
+            let created =
+                keyboard_watcher::KeyboardWatcher::create(f, grab, passes_keys, scancode_mask);
+            let mut watcher = match created {
+                Ok(watcher) => watcher,
+                Err(err) => return started.send(Err(err)).unwrap_or(()),
+            };
+            started.send(Ok(())).unwrap_or(());
+            let exit = RefCell::new(false);
+            let result = watcher.watch(
+                |scancode, code, pressed| {
+                    // Keys without a scan, or bound by their key code, go by their key code
+                    let keyed = state.keyed.lock().unwrap()[index].contains(&code);
+                    let scancode = match scancode {
+                        Some(scancode) if !keyed => scancode,
+                        _ => device::keycode_scancode(code),
+                    };
+                    inputs
+                        .send(Input::Scancode(index, scancode, pressed))
+                        .unwrap_or(());
+                    state.passthrough.lock().unwrap()[index].passes(scancode)
                 },
                 &exit,
-            )?;
+            );
+            if let Err(e) = result {
+                eprintln!(
+                    "{}Stopped watching the {}'s keyboard interface: {}",
+                    Priority::Error,
+                    title,
+                    e
+                );
+            }
+        });
+    }
+    drop(inputs);
+    for _ in &controller.devices {
+        is_started
+            .recv()?
+            .map_err(|err| format!("Error: {}", err))?;
+    }
+    println!("G600 controller started successfully.\n");
+    if let Err(e) = systemd::notify("READY=1") {
+        eprintln!(
+            "{}Couldn't tell systemd lg600r started: {}",
+            Priority::Warning,
+            e
+        );
+    }
+
+    let (mut saved, mut fed) = (Instant::now(), Instant::now());
+    loop {
+        match received.recv_timeout(TICK) {
+            Ok(input) => controller.handle(input),
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                controller.save_usage();
+                return Err("Error: Stopped watching every device".into());
+            }
+        }
+        controller.collect_finished();
+        // Fed however much input comes, so that only a loop that's stuck gets lg600r restarted
+        if fed.elapsed() >= TICK {
+            systemd::notify("WATCHDOG=1").unwrap_or(());
+            fed = Instant::now();
+        }
+        if STOP_REQUESTED.load(Ordering::SeqCst) {
+            controller.save_usage();
+            println!("Stopping.");
+            return Ok(());
+        }
+        if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            controller.save_usage();
+            controller.reload(&dotfile);
+        }
+        if saved.elapsed() >= USAGE_SAVE_INTERVAL {
+            controller.save_usage();
+            saved = Instant::now();
+        }
+    }
+}
+
+/// The devices statistics may have been recorded for: those the config would drive, the one
+/// plugged in first, then every other device known
+fn stats_devices() -> Vec<device::DeviceDescriptor> {
+    let config = config::find_dotfile()
+        .and_then(|path| config::load_configuration_from_dotfile(&path).ok())
+        .unwrap_or_default();
+    let mut known = device::known(&config.devices);
+    let interfaces = device::scan(std::path::Path::new(device::SYSFS)).unwrap_or_default();
+    if let Ok((found, _)) = device::locate(&config.device, &known, &interfaces) {
+        if let Some(first) = found.into_iter().next() {
+            known.retain(|device| device.name != first.device.name);
+            known.insert(0, first.device);
+        }
+    }
+    known
+}
+
+fn print_stats(heatmap: bool) -> Result<(), Box<dyn (::std::error::Error)>> {
+    let stats = stats::Stats::load()?;
+    if stats.devices.is_empty() {
+        println!("No usage recorded yet.");
+    } else if heatmap {
+        print!("{}", stats.render_heatmap(&stats_devices()));
+    } else {
+        print!("{}", stats.render_table());
+    }
+    Ok(())
+}
+
+fn check_config(path: Option<&str>) -> Result<(), Box<dyn (::std::error::Error)>> {
+    let path = match path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => config::find_dotfile().ok_or("No configuration found.")?,
+    };
+    let diagnostics = config::check_dotfile(&path)?;
+    for diagnostic in &diagnostics {
+        eprintln!("{}\n", diagnostic.render(&path.to_string_lossy()));
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == config::check::Severity::Error)
+        .count();
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        path.to_string_lossy(),
+        errors,
+        diagnostics.len() - errors
+    );
+    if errors > 0 {
+        Err(format!("{} has errors", path.to_string_lossy()).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn dump_config(options: &[&str]) -> Result<(), Box<dyn (::std::error::Error)>> {
+    let mut resolved = false;
+    let mut format = config::DumpFormat::Toml;
+    let mut path = None;
+    for option in options {
+        match *option {
+            "--resolved" => resolved = true,
+            "--json" => format = config::DumpFormat::Json,
+            "--toml" => format = config::DumpFormat::Toml,
+            other if path.is_none() && !other.starts_with("--") => {
+                path = Some(std::path::PathBuf::from(other))
+            }
+            other => return Err(format!("Unexpected argument \"{}\"", other).into()),
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => config::find_dotfile().ok_or("No configuration found.")?,
+    };
+    println!("{}", config::dump_dotfile(&path, resolved, format)?);
+    Ok(())
+}
+
+/// Runs scripted input through a config's bindings, printing what would be done rather than
+/// doing it
+fn simulate(options: &[&str]) -> Result<(), Box<dyn (::std::error::Error)>> {
+    let mut path = None;
+    let mut script = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "--config" => match options.next() {
+                Some(config) => path = Some(std::path::PathBuf::from(config)),
+                None => return Err("--config needs a path".into()),
+            },
+            other if script.is_none() && !other.starts_with("--") => script = Some(other),
+            other => return Err(format!("Unexpected argument \"{}\"", other).into()),
+        }
+    }
+    let steps = simulate::parse_script(script.ok_or("No input to simulate was given.")?)?;
+    let path = match path {
+        Some(path) => path,
+        None => config::find_dotfile().ok_or("No configuration found.")?,
+    };
+    let config = config::load_configuration_from_dotfile(&path)?;
+    // The first of the devices each selector could be, since none need be plugged in
+    let known = device::known(&config.devices);
+    let mut devices = Vec::new();
+    for selector in &config.device {
+        if let Some(device) = device::candidates(selector, &known)?.into_iter().next() {
+            devices.push(device.clone());
+        }
+    }
+    if devices.is_empty() {
+        devices.push(known[0].clone());
+    }
+    let recording = xdo::recording::Recording::default();
+    let mut controller = Controller::simulated(devices, config, recording.clone());
+    for step in &steps {
+        println!("\n> {}", step);
+        let emit = || {
+            for emitted in recording.take() {
+                println!("Emits {:?}", emitted);
+            }
+        };
+        controller
+            .simulate(step, emit)
+            .map_err(|e| format!("Error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Installs a user unit that runs lg600r with the graphical session, and gives systemd the
+/// session's display to send input to
+fn setup_systemd() -> Result<(), Box<dyn (::std::error::Error)>> {
+    let executable = std::env::current_exe()?;
+    // The service doesn't start where this was run from, so the config's path is made absolute
+    let config = match std::env::var_os("LG600R_CONFIG").filter(|p| !p.is_empty()) {
+        Some(config) => Some(std::env::current_dir()?.join(config)),
+        None => None,
+    };
+    let unit = std::path::Path::new("systemd/user").join(systemd::UNIT_NAME);
+    let path = xdg::BaseDirectories::new()?.place_config_file(unit)?;
+    fs::write(&path, systemd::unit(&executable, config.as_deref()))?;
+    println!("Installed {}", path.to_string_lossy());
+
+    let systemctl = |args: &[&str]| -> Result<(), String> {
+        let status = std::process::Command::new("systemctl")
+            .arg("--user")
+            .args(args)
+            .status()
+            .map_err(|e| format!("Couldn't run systemctl: {}", e))?;
+        if status.success() {
             Ok(())
-        })
+        } else {
+            Err(format!("`systemctl --user {}` failed", args.join(" ")))
+        }
+    };
+    systemctl(&["daemon-reload"])?;
+    let display = ["DISPLAY", "XAUTHORITY"]
+        .iter()
+        .cloned()
+        .filter(|variable| std::env::var_os(variable).is_some())
+        .collect::<Vec<_>>();
+    if display.contains(&"DISPLAY") {
+        systemctl(&[&["import-environment"], display.as_slice()].concat())?;
+    } else {
+        println!("DISPLAY isn't set, so the service can't be given the session's display yet;");
+        println!("run this again from the graphical session, or see the README.");
+    }
+    println!("Start it now and with every session with `systemctl --user enable --now lg600r`");
+    Ok(())
+}
+
+/// Prints udev rules for the devices the config selects, or if it selects none, every known one
+fn setup_udev() -> Result<(), Box<dyn (::std::error::Error)>> {
+    let config = match config::find_dotfile() {
+        Some(path) => config::load_configuration_from_dotfile(&path)?,
+        None => config::Configuration::default(),
+    };
+    let known = device::known(&config.devices);
+    let mut devices = Vec::new();
+    for selector in &config.device {
+        for device in device::candidates(selector, &known)? {
+            if !devices.contains(device) {
+                devices.push(device.clone());
+            }
+        }
+    }
+    if devices.is_empty() {
+        devices = known;
+    }
+    print!(
+        "{}",
+        access::udev_rules(&devices, access::needs_uinput(&config))
+    );
+    Ok(())
 }
 
 fn build_default_commands() -> std::collections::HashMap<u32, BindingType> {
@@ -144,52 +1285,52 @@ fn build_default_commands() -> std::collections::HashMap<u32, BindingType> {
 }
 
 fn run_with_dotfile(path: ::std::path::PathBuf) -> Result<(), Box<dyn (::std::error::Error)>> {
-    assert!(path.exists());
-
-    crate::config::load_configuration_from_dotfile(&path)
-        .map(
-            |config::Configuration {
-                 bindings: dotcommands,
-                 scancodes,
-             }| {
-                let mut commands = build_default_commands();
-                for (sc, cmd) in &dotcommands {
-                    commands.insert(*sc, cmd.clone());
-                }
-                println!(
-                    "Loaded {} commands and {} scancode mappings from dotfile.",
-                    dotcommands.len(),
-                    scancodes.len(),
-                );
-                let scancodes_by_gkey = scancodes.iter().cloned().collect::<BTreeMap<_, _>>();
-                let commands = commands
-                    .iter()
-                    .map(|(gkey, command)| {
-                        if let Some(&scancode) = scancodes_by_gkey.get(&gkey) {
-                            (scancode, (*gkey, command.clone()))
-                        } else {
-                            eprintln!("GKey {} not mapped to scancode; using as scancode", &gkey);
-                            (*gkey, (*gkey, command.clone()))
-                        }
-                    })
-                    .collect::<BTreeMap<u32, (u32, BindingType)>>();
-                (commands, scancodes_by_gkey)
-            },
-        )
-        .and_then(|(commands, scancodes_by_gkey)| run(commands, scancodes_by_gkey))
+    let config = crate::config::load_configuration_from_dotfile(&path)?;
+    run(path, config)
+}
+
+fn report(result: Result<(), Box<dyn (::std::error::Error)>>) {
+    if let Err(e) = result {
+        eprintln!("{}{}", Priority::Error, &e);
+        std::process::exit(1);
+    }
 }
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => (),
+        ["stats"] | ["stats", "--table"] => return report(print_stats(false)),
+        ["stats", "--heatmap"] => return report(print_stats(true)),
+        ["check"] => return report(check_config(None)),
+        ["check", path] => return report(check_config(Some(path))),
+        ["config", "dump", options @ ..] => return report(dump_config(options)),
+        ["simulate", options @ ..] => return report(simulate(options)),
+        ["setup", "udev"] => return report(setup_udev()),
+        ["setup", "systemd"] => return report(setup_systemd()),
+        _ => {
+            eprintln!("Usage: lg600r [stats [--table|--heatmap] | check [CONFIG]");
+            eprintln!("              | config dump [--resolved] [--json] [CONFIG]");
+            eprintln!("              | simulate [--config CONFIG] SCRIPT | setup udev|systemd]");
+            std::process::exit(2);
+        }
+    }
+
     match config::find_dotfile() {
         Some(dot) => {
             println!("Using config file at {}", dot.to_string_lossy());
-            if let Err(e) = run_with_dotfile(dot) {
-                eprintln!("{}", &e);
-            }
+            // Failing with a status of its own, so that systemd can restart it
+            report(run_with_dotfile(dot));
         }
         _ => {
             println!("No configuration found.");
-            println!("Create a config.toml in either ~/.config/lg600r or ~/.lg600r");
+            println!("Create a config.toml in either ~/.config/lg600r or ~/.lg600r,");
+            println!("or point LG600R_CONFIG at one");
         }
     }
 }
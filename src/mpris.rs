@@ -0,0 +1,280 @@
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::time::Duration;
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MediaCommand {
+    PlayPause,
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Previous,
+    /// Seeks by this many milliseconds, backwards if negative
+    Seek(i64),
+    /// Brings the player's window to the front
+    Raise,
+}
+
+impl MediaCommand {
+    /// The names media bindings' `action`s are written with
+    pub const NAMES: &'static [&'static str] = &[
+        "play-pause",
+        "play",
+        "pause",
+        "stop",
+        "next",
+        "previous",
+        "seek",
+        "raise",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaCommand::PlayPause => "play-pause",
+            MediaCommand::Play => "play",
+            MediaCommand::Pause => "pause",
+            MediaCommand::Stop => "stop",
+            MediaCommand::Next => "next",
+            MediaCommand::Previous => "previous",
+            MediaCommand::Seek(_) => "seek",
+            MediaCommand::Raise => "raise",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaAction {
+    pub command: MediaCommand,
+    /// The player to control, by the name it registers on the bus (e.g. "spotify" or "vlc");
+    /// if unset, whichever player is playing or was most recently controlled
+    pub player: Option<String>,
+}
+
+/// Controls MPRIS2 media players over a persistent session bus connection
+#[derive(Default)]
+pub struct MprisControl {
+    address: Option<String>,
+    connection: Option<Connection>,
+    last_active: Option<String>,
+}
+
+impl MprisControl {
+    pub fn with_address(address: &str) -> MprisControl {
+        MprisControl {
+            address: Some(address.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn connect(&self) -> Result<Connection, dbus::Error> {
+        match &self.address {
+            Some(address) => {
+                let mut channel = dbus::channel::Channel::open_private(address)?;
+                channel.register()?;
+                Ok(Connection::from(channel))
+            }
+            None => Connection::new_session(),
+        }
+    }
+
+    pub fn perform(&mut self, action: &MediaAction) -> Result<(), String> {
+        // The session bus may have gone away since we last used it
+        if let Some(false) = self.connection.as_ref().map(|c| c.channel().is_connected()) {
+            self.connection = None;
+        }
+        if self.connection.is_none() {
+            let connection = self
+                .connect()
+                .map_err(|e| format!("Couldn't connect to the session bus: {}", e))?;
+            self.connection = Some(connection);
+        }
+        let connection = self.connection.as_ref().unwrap();
+
+        let players = list_players(connection).map_err(|e| e.to_string())?;
+        let player = match &action.player {
+            Some(name) => {
+                let name = crate::config::expand_env(name);
+                find_player(&players, &name)
+                    .ok_or_else(|| format!("No media player named \"{}\" is running", name))?
+            }
+            None => most_recently_active(connection, &players, self.last_active.as_ref())
+                .ok_or_else(|| "No media player is running".to_string())?,
+        }
+        .clone();
+
+        let result = send(connection, &player, action.command);
+        if !connection.channel().is_connected() {
+            self.connection = None;
+        }
+        self.last_active = Some(player.clone());
+        result.map_err(|e| {
+            format!(
+                "{} failed on {}: {}",
+                action.command.name(),
+                &player[BUS_NAME_PREFIX.len()..],
+                e
+            )
+        })
+    }
+}
+
+fn list_players(connection: &Connection) -> Result<Vec<String>, dbus::Error> {
+    let bus = connection.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        REPLY_TIMEOUT,
+    );
+    let (names,): (Vec<String>,) = bus.method_call("org.freedesktop.DBus", "ListNames", ())?;
+    let mut players = names
+        .into_iter()
+        .filter(|name| name.starts_with(BUS_NAME_PREFIX))
+        .collect::<Vec<_>>();
+    players.sort();
+    Ok(players)
+}
+
+/// Finds a player by name, which also matches any of its instances (e.g. `vlc.instance1234`)
+fn find_player<'a>(players: &'a [String], name: &str) -> Option<&'a String> {
+    let name = name.to_lowercase();
+    players.iter().find(|player| {
+        let player = player[BUS_NAME_PREFIX.len()..].to_lowercase();
+        player == name || player.starts_with(&format!("{}.", name))
+    })
+}
+
+/// Prefers a playing player, then the one we last controlled, then any at all
+fn most_recently_active<'a>(
+    connection: &Connection,
+    players: &'a [String],
+    last_active: Option<&String>,
+) -> Option<&'a String> {
+    let playing = players
+        .iter()
+        .filter(|player| {
+            connection
+                .with_proxy(player.as_str(), OBJECT_PATH, REPLY_TIMEOUT)
+                .get::<String>(PLAYER_INTERFACE, "PlaybackStatus")
+                .map(|status| status == "Playing")
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+    let last_active = players.iter().find(|player| Some(*player) == last_active);
+    match (playing.as_slice(), last_active) {
+        ([], last_active) => last_active.or_else(|| players.first()),
+        (playing, Some(last_active)) if playing.contains(&last_active) => Some(last_active),
+        (playing, _) => Some(playing[0]),
+    }
+}
+
+fn send(connection: &Connection, player: &str, command: MediaCommand) -> Result<(), dbus::Error> {
+    let proxy = connection.with_proxy(player, OBJECT_PATH, REPLY_TIMEOUT);
+    match command {
+        MediaCommand::PlayPause => proxy.method_call(PLAYER_INTERFACE, "PlayPause", ()),
+        MediaCommand::Play => proxy.method_call(PLAYER_INTERFACE, "Play", ()),
+        MediaCommand::Pause => proxy.method_call(PLAYER_INTERFACE, "Pause", ()),
+        MediaCommand::Stop => proxy.method_call(PLAYER_INTERFACE, "Stop", ()),
+        MediaCommand::Next => proxy.method_call(PLAYER_INTERFACE, "Next", ()),
+        MediaCommand::Previous => proxy.method_call(PLAYER_INTERFACE, "Previous", ()),
+        // MPRIS measures offsets in microseconds
+        MediaCommand::Seek(ms) => {
+            proxy.method_call(PLAYER_INTERFACE, "Seek", (ms.saturating_mul(1000),))
+        }
+        MediaCommand::Raise => proxy.method_call(ROOT_INTERFACE, "Raise", ()),
+    }
+}
+
+#[test]
+fn test_control_stub_players() {
+    use dbus::arg::Variant;
+    use std::io::BufRead;
+    use std::sync::mpsc;
+
+    let mut daemon = match ::std::process::Command::new("dbus-daemon")
+        .args(&["--session", "--nofork", "--print-address=1"])
+        .stdout(::std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            eprintln!("Skipping MPRIS test; couldn't start dbus-daemon: {}", e);
+            return;
+        }
+    };
+    let mut address = String::new();
+    ::std::io::BufReader::new(daemon.stdout.take().unwrap())
+        .read_line(&mut address)
+        .unwrap();
+    let address = address.trim().to_string();
+
+    // Each stub player reports its calls, and answers PlaybackStatus with a fixed status
+    let (calls, received) = mpsc::channel();
+    for &(name, status) in &[("vlc.instance42", "Paused"), ("spotify", "Playing")] {
+        let address = address.clone();
+        let calls = calls.clone();
+        let (ready, is_ready) = mpsc::channel();
+        ::std::thread::spawn(move || {
+            let mut channel = dbus::channel::Channel::open_private(&address).unwrap();
+            channel.register().unwrap();
+            let connection = Connection::from(channel);
+            connection
+                .request_name(format!("{}{}", BUS_NAME_PREFIX, name), false, true, true)
+                .unwrap();
+            ready.send(()).unwrap();
+            loop {
+                let message = match connection.channel().blocking_pop_message(REPLY_TIMEOUT) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => continue,
+                    Err(_) => return,
+                };
+                if message.msg_type() != dbus::MessageType::MethodCall {
+                    continue;
+                }
+                let member = message.member().unwrap().to_string();
+                let reply = if member == "Get" {
+                    message.method_return().append1(Variant(status))
+                } else {
+                    let offset = message.get1::<i64>();
+                    calls
+                        .send(format!("{} {} {:?}", name, member, offset))
+                        .unwrap();
+                    message.method_return()
+                };
+                connection.channel().send(reply).unwrap();
+            }
+        });
+        is_ready.recv().unwrap();
+    }
+
+    let mut control = MprisControl::with_address(&address);
+    let mut perform = |command, player: Option<&str>| {
+        control.perform(&MediaAction {
+            command,
+            player: player.map(str::to_string),
+        })
+    };
+    assert_eq!(perform(MediaCommand::PlayPause, None), Ok(()));
+    assert_eq!(perform(MediaCommand::Seek(-5000), Some("VLC")), Ok(()));
+    assert_eq!(perform(MediaCommand::Raise, Some("spotify")), Ok(()));
+    assert_eq!(
+        perform(MediaCommand::Next, Some("mpv")),
+        Err("No media player named \"mpv\" is running".to_string())
+    );
+    assert_eq!(
+        received.iter().take(3).collect::<Vec<_>>(),
+        vec![
+            "spotify PlayPause None",
+            "vlc.instance42 Seek Some(-5000000)",
+            "spotify Raise None",
+        ]
+    );
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
@@ -0,0 +1,255 @@
+use crate::systemd::Priority;
+use dbus::blocking::Connection;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    /// Whether to show any notifications at all
+    pub enabled: bool,
+    /// Whether to notify whenever an action fails, and not only for bindings with `notify`
+    pub errors: bool,
+    /// Whether to notify when the profile or shared layer changes
+    pub states: bool,
+    /// Notifications beyond this many a minute are dropped
+    pub per_minute: u32,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            enabled: true,
+            errors: true,
+            states: true,
+            per_minute: 10,
+        }
+    }
+}
+
+/// Admits at most `limit` notifications within any `RATE_WINDOW`, counting those it turns away
+struct RateLimiter {
+    sent: VecDeque<Instant>,
+    suppressed: u32,
+}
+
+impl RateLimiter {
+    fn new() -> RateLimiter {
+        RateLimiter {
+            sent: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// If admitted, returns how many notifications were dropped since the last one admitted
+    fn admit(&mut self, now: Instant, limit: u32) -> Option<u32> {
+        while let Some(&oldest) = self.sent.front() {
+            if now.duration_since(oldest) < RATE_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= limit as usize {
+            self.suppressed += 1;
+            return None;
+        }
+        self.sent.push_back(now);
+        Some(::std::mem::replace(&mut self.suppressed, 0))
+    }
+}
+
+/// A notification admitted to be shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Notification {
+    summary: String,
+    body: String,
+    critical: bool,
+}
+
+/// Shows freedesktop desktop notifications, reusing one bubble per subject
+pub struct Notifier {
+    pub settings: NotificationSettings,
+    limiter: RateLimiter,
+    /// Hands notifications to a thread of their own, started with the first, so that a slow
+    /// or missing notification daemon doesn't hold up handling keys
+    sender: Option<mpsc::Sender<Notification>>,
+}
+
+impl Notifier {
+    pub fn new(settings: NotificationSettings) -> Notifier {
+        Notifier {
+            settings,
+            limiter: RateLimiter::new(),
+            sender: None,
+        }
+    }
+
+    /// Reports an action having run, for bindings that asked to be notified about
+    pub fn action(&mut self, subject: &str, description: &str) {
+        self.show(subject, description, false);
+    }
+
+    /// Reports a failure; shown even without `notify` on the binding, unless errors are muted
+    pub fn error(&mut self, subject: &str, message: &str, asked: bool) {
+        if asked || self.settings.errors {
+            self.show(subject, message, true);
+        }
+    }
+
+    /// Reports a change of profile or layer, reusing one bubble for each kind of change
+    pub fn state(&mut self, subject: &str, body: &str) {
+        if self.settings.states {
+            self.show(subject, body, false);
+        }
+    }
+
+    fn show(&mut self, summary: &str, body: &str, critical: bool) {
+        if !self.settings.enabled {
+            return;
+        }
+        let suppressed = match self.limiter.admit(Instant::now(), self.settings.per_minute) {
+            Some(suppressed) => suppressed,
+            None => return,
+        };
+        let body = match suppressed {
+            0 => body.to_string(),
+            n => format!("{}\n({} more notification(s) were dropped)", body, n),
+        };
+        let notification = Notification {
+            summary: summary.to_string(),
+            body,
+            critical,
+        };
+        let sender = self.sender.get_or_insert_with(Daemon::spawn);
+        if sender.send(notification).is_err() {
+            // Started afresh with the next notification
+            self.sender = None;
+        }
+    }
+}
+
+/// The connection to the notification daemon, on the thread notifications are sent from
+struct Daemon {
+    connection: Option<Connection>,
+    /// Notification IDs by summary, so that repeats replace their predecessor
+    shown: BTreeMap<String, u32>,
+}
+
+impl Daemon {
+    fn spawn() -> mpsc::Sender<Notification> {
+        let (sender, notifications) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut daemon = Daemon {
+                connection: None,
+                shown: BTreeMap::new(),
+            };
+            for Notification {
+                summary,
+                body,
+                critical,
+            } in notifications
+            {
+                if let Err(e) = daemon.send(&summary, &body, critical) {
+                    // Notifications are a nicety; the terminal still gets everything
+                    eprintln!("{}Couldn't show notification: {}", Priority::Warning, e);
+                    daemon.connection = None;
+                }
+            }
+        });
+        sender
+    }
+
+    fn send(&mut self, summary: &str, body: &str, critical: bool) -> Result<(), dbus::Error> {
+        if self.connection.is_none() {
+            self.connection = Some(Connection::new_session()?);
+        }
+        let proxy = self.connection.as_ref().unwrap().with_proxy(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            REPLY_TIMEOUT,
+        );
+        let mut hints = dbus::arg::PropMap::new();
+        let urgency: u8 = if critical { 2 } else { 1 };
+        hints.insert(
+            "urgency".to_string(),
+            dbus::arg::Variant(Box::new(urgency) as Box<dyn dbus::arg::RefArg>),
+        );
+        let replaces = self.shown.get(summary).cloned().unwrap_or(0);
+        let (id,): (u32,) = proxy.method_call(
+            "org.freedesktop.Notifications",
+            "Notify",
+            (
+                "lg600r",
+                replaces,
+                if critical {
+                    "dialog-error"
+                } else {
+                    "input-mouse"
+                },
+                summary,
+                body,
+                Vec::<String>::new(),
+                hints,
+                -1i32,
+            ),
+        )?;
+        self.shown.insert(summary.to_string(), id);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let start = Instant::now();
+    let mut limiter = RateLimiter::new();
+    assert_eq!(limiter.admit(start, 2), Some(0));
+    assert_eq!(limiter.admit(start + Duration::from_secs(1), 2), Some(0));
+    assert_eq!(limiter.admit(start + Duration::from_secs(2), 2), None);
+    assert_eq!(limiter.admit(start + Duration::from_secs(30), 2), None);
+    // The first notification has left the window, making room for one more
+    assert_eq!(limiter.admit(start + Duration::from_secs(60), 2), Some(2));
+    assert_eq!(limiter.admit(start + Duration::from_secs(60), 2), None);
+    assert_eq!(limiter.admit(start + Duration::from_secs(61), 2), Some(1));
+}
+
+#[test]
+fn test_state_notifications() {
+    // With no room left, what would have been shown is counted as dropped
+    let mut notifier = Notifier::new(NotificationSettings {
+        per_minute: 0,
+        ..Default::default()
+    });
+    notifier.state("Profile", "Switched to profile \"games\"");
+    assert_eq!(notifier.limiter.suppressed, 1);
+    notifier.settings.states = false;
+    notifier.state("Profile", "Switched back from profile \"games\"");
+    assert_eq!(notifier.limiter.suppressed, 1);
+    notifier.settings.states = true;
+    notifier.settings.enabled = false;
+    notifier.state("Layer", "G-shift on every device");
+    assert_eq!(notifier.limiter.suppressed, 1);
+}
+
+#[test]
+fn test_notifications_are_handed_off() {
+    let (sender, notifications) = mpsc::channel();
+    let mut notifier = Notifier::new(NotificationSettings::default());
+    notifier.sender = Some(sender);
+    notifier.error("G9 failed", "exit status 1", false);
+    assert_eq!(
+        notifications.try_recv(),
+        Ok(Notification {
+            summary: "G9 failed".to_string(),
+            body: "exit status 1".to_string(),
+            critical: true,
+        })
+    );
+    // With the thread gone, the notification is dropped, and the next starts another thread
+    drop(notifications);
+    notifier.action("G9", "ls");
+    assert!(notifier.sender.is_none());
+}
@@ -0,0 +1,241 @@
+use crate::access;
+use crate::config::gkey::{GSHIFT_OFFSET, WHEEL_DOWN, WHEEL_LEFT, WHEEL_RIGHT, WHEEL_UP};
+use evdev_rs::enums::{EventCode, EV_KEY, EV_REL};
+use evdev_rs::{Device, GrabMode, InputEvent, UInputDevice};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Buttons on the pointer interface, with the G-keys they're bound as
+const BUTTONS: &[(EV_KEY, u32)] = &[
+    (EV_KEY::BTN_LEFT, 1),
+    (EV_KEY::BTN_RIGHT, 2),
+    (EV_KEY::BTN_MIDDLE, 3),
+];
+
+/// High-resolution wheel axes, as (type, code), which older evdev bindings don't name
+const REL_WHEEL_HI_RES: (u32, u32) = (0x02, 0x0b);
+const REL_HWHEEL_HI_RES: (u32, u32) = (0x02, 0x0c);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PointerSettings {
+    /// Whether to take the pointer interface over, so that its buttons and wheel can be bound;
+    /// whatever isn't bound is passed through a uinput device
+    pub grab: bool,
+}
+
+/// What becomes of an event from a grabbed pointer interface
+#[derive(Debug, PartialEq, Eq)]
+pub enum Remapped {
+    /// Passed through unchanged, as nothing's bound to it
+    Forward,
+    /// Swallowed, having pressed (`true`) or released these G-keys
+    GKeys(Vec<(u32, bool)>),
+}
+
+/// Picks out the pointer events that bound G-keys are made of
+#[derive(Default)]
+pub struct Remapper {
+    /// The G-key pressed by each button still held, so that its release goes the same way
+    held: BTreeMap<u32, u32>,
+}
+
+impl Remapper {
+    pub fn remap<F: Fn(u32) -> bool>(
+        &mut self,
+        ev: &InputEvent,
+        shifted: bool,
+        is_bound: F,
+    ) -> Remapped {
+        let layer = |gkey: u32| {
+            if shifted {
+                gkey + GSHIFT_OFFSET
+            } else {
+                gkey
+            }
+        };
+        match ev.event_code {
+            EventCode::EV_KEY(button) => {
+                let base = match BUTTONS.iter().find(|(b, _)| *b == button) {
+                    Some(&(_, gkey)) => gkey,
+                    None => return Remapped::Forward,
+                };
+                match ev.value {
+                    0 => match self.held.remove(&base) {
+                        Some(gkey) => Remapped::GKeys(vec![(gkey, false)]),
+                        None => Remapped::Forward,
+                    },
+                    1 if is_bound(layer(base)) => {
+                        self.held.insert(base, layer(base));
+                        Remapped::GKeys(vec![(layer(base), true)])
+                    }
+                    // Autorepeat goes wherever the press went
+                    _ if self.held.contains_key(&base) => Remapped::GKeys(vec![]),
+                    _ => Remapped::Forward,
+                }
+            }
+            EventCode::EV_REL(axis @ EV_REL::REL_WHEEL)
+            | EventCode::EV_REL(axis @ EV_REL::REL_HWHEEL) => {
+                let gkey = layer(wheel_gkey(axis == EV_REL::REL_WHEEL, ev.value));
+                if ev.value == 0 || !is_bound(gkey) {
+                    return Remapped::Forward;
+                }
+                // Each notch is a tap of the G-key
+                let notches = ev.value.unsigned_abs() as usize;
+                Remapped::GKeys(
+                    (0..notches)
+                        .flat_map(|_| vec![(gkey, true), (gkey, false)])
+                        .collect(),
+                )
+            }
+            code => {
+                // Applications preferring high-resolution scrolling mustn't see the wheel either
+                let vertical = match evdev_rs::util::event_code_to_int(&code) {
+                    REL_WHEEL_HI_RES => true,
+                    REL_HWHEEL_HI_RES => false,
+                    _ => return Remapped::Forward,
+                };
+                if ev.value != 0 && is_bound(layer(wheel_gkey(vertical, ev.value))) {
+                    Remapped::GKeys(vec![])
+                } else {
+                    Remapped::Forward
+                }
+            }
+        }
+    }
+}
+
+fn wheel_gkey(vertical: bool, value: i32) -> u32 {
+    match (vertical, value > 0) {
+        (true, true) => WHEEL_UP,
+        (true, false) => WHEEL_DOWN,
+        (false, true) => WHEEL_RIGHT,
+        (false, false) => WHEEL_LEFT,
+    }
+}
+
+/// Whether an event moves the pointer
+pub fn is_motion(ev: &InputEvent) -> bool {
+    matches!(
+        ev.event_code,
+        EventCode::EV_REL(EV_REL::REL_X) | EventCode::EV_REL(EV_REL::REL_Y)
+    )
+}
+
+/// Watches the G600's pointer interface, which carries its motion, wheel and click events
+pub struct PointerWatcher {
+    device: Device,
+    /// Where a grabbed pointer's events are passed on to
+    passthrough: Option<UInputDevice>,
+}
+
+impl PointerWatcher {
+    pub fn create(node: &Path, f: File, grab: bool) -> Result<PointerWatcher, String> {
+        let mut d = Device::new().expect("Libevdev must be installed and available");
+        d.set_fd(f)
+            .map_err(|e| format!("Couldn't mount pointer device: {}", e))?;
+        let passthrough = if grab {
+            // Set up the passthrough first, so that the pointer never stops working
+            let uinput = UInputDevice::create_from_device(&d).map_err(|e| {
+                format!(
+                    "Couldn't create a uinput device to pass the pointer through: {}",
+                    access::explain(Path::new(access::UINPUT), true, &e)
+                )
+            })?;
+            d.grab(GrabMode::Grab)
+                .map_err(|e| access::explain_grab(node, &e))?;
+            Some(uinput)
+        } else {
+            None
+        };
+        Ok(PointerWatcher {
+            device: d,
+            passthrough,
+        })
+    }
+
+    /// Passes every event to `callback` until the device goes away.
+    ///
+    /// Unless grabbed, the pointer keeps working as usual while being watched; if grabbed,
+    /// events are passed through as soon as they're read, unless `callback` says it consumed them.
+    pub fn watch<F: FnMut(&InputEvent) -> bool>(&mut self, mut callback: F) -> Result<(), String> {
+        let read_flags = evdev_rs::ReadFlag::NORMAL | evdev_rs::ReadFlag::BLOCKING;
+        loop {
+            match self.device.next_event(read_flags) {
+                Ok((_, ev)) => {
+                    let consumed = callback(&ev);
+                    if let (Some(passthrough), false) = (&self.passthrough, consumed) {
+                        passthrough
+                            .write_event(&ev)
+                            .map_err(|e| format!("Couldn't pass pointer events through: {}", e))?;
+                    }
+                }
+                Err(ref e) if e.raw_os_error() == Some(libc::EAGAIN) => (),
+                Err(e) => return Err(format!("Couldn't read pointer events: {}", e)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_remap_bound_buttons_and_wheel() {
+    use evdev_rs::TimeVal;
+    let time = TimeVal::new(0, 0);
+    let button = |b: EV_KEY, value| InputEvent::new(&time, &EventCode::EV_KEY(b), value);
+    let wheel = |axis: EV_REL, value| InputEvent::new(&time, &EventCode::EV_REL(axis), value);
+    let bound = [3, WHEEL_UP, 104, WHEEL_DOWN + GSHIFT_OFFSET];
+    let is_bound = |gkey: u32| bound.contains(&gkey);
+    let mut remapper = Remapper::default();
+    let mut remap = |ev: &InputEvent, shifted| remapper.remap(ev, shifted, is_bound);
+
+    assert_eq!(
+        remap(&button(EV_KEY::BTN_LEFT, 1), false),
+        Remapped::Forward
+    );
+    assert_eq!(
+        remap(&button(EV_KEY::BTN_LEFT, 0), false),
+        Remapped::Forward
+    );
+    assert_eq!(remap(&wheel(EV_REL::REL_X, 12), false), Remapped::Forward);
+    assert_eq!(
+        remap(&button(EV_KEY::BTN_MIDDLE, 1), false),
+        Remapped::GKeys(vec![(3, true)])
+    );
+    assert_eq!(
+        remap(&button(EV_KEY::BTN_MIDDLE, 2), false),
+        Remapped::GKeys(vec![])
+    );
+    // Shifting mid-press doesn't change which G-key is released
+    assert_eq!(
+        remap(&button(EV_KEY::BTN_MIDDLE, 0), true),
+        Remapped::GKeys(vec![(3, false)])
+    );
+
+    assert_eq!(
+        remap(&wheel(EV_REL::REL_WHEEL, 2), false),
+        Remapped::GKeys(vec![
+            (WHEEL_UP, true),
+            (WHEEL_UP, false),
+            (WHEEL_UP, true),
+            (WHEEL_UP, false)
+        ])
+    );
+    assert_eq!(
+        remap(&wheel(EV_REL::REL_WHEEL, -1), false),
+        Remapped::Forward
+    );
+    assert_eq!(
+        remap(&wheel(EV_REL::REL_WHEEL, -1), true),
+        Remapped::GKeys(vec![(122, true), (122, false)])
+    );
+    assert_eq!(remap(&wheel(EV_REL::REL_WHEEL, 1), true), Remapped::Forward);
+    assert_eq!(
+        remap(&wheel(EV_REL::REL_HWHEEL, -1), true),
+        Remapped::GKeys(vec![(104, true), (104, false)])
+    );
+    assert_eq!(
+        remap(&wheel(EV_REL::REL_HWHEEL, 1), true),
+        Remapped::Forward
+    );
+}
@@ -0,0 +1,143 @@
+use crate::chord::split_device;
+use crate::config::gkey::{format_gkey, parse_gkey};
+use crate::gesture::Gesture;
+use std::fmt;
+use std::time::Duration;
+
+/// A G-key on the named device, or on the first device driven if it names none
+pub type Key = (Option<String>, u32);
+
+/// A step of scripted input, written like `G9 down`, `G9 up`, `G12 tap`, `G^10 hold 600ms` or
+/// `draw L-U`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Down(Key),
+    Up(Key),
+    /// Pressed and released at once
+    Tap(Key),
+    /// Pressed, then released once the time's up
+    Hold(Key, Duration),
+    /// Drawn with the pointer, while the gesture trigger is held
+    Draw(Gesture),
+}
+
+fn format_key((device, gkey): &Key) -> String {
+    match device {
+        Some(device) => format!("{}.{}", device, format_gkey(*gkey)),
+        None => format_gkey(*gkey),
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Down(key) => write!(f, "{} down", format_key(key)),
+            Step::Up(key) => write!(f, "{} up", format_key(key)),
+            Step::Tap(key) => write!(f, "{} tap", format_key(key)),
+            Step::Hold(key, held) => write!(f, "{} hold {}ms", format_key(key), held.as_millis()),
+            Step::Draw(gesture) => write!(f, "draw {}", gesture),
+        }
+    }
+}
+
+/// Parses a duration written in milliseconds or seconds, like `600ms` or `2s`
+fn parse_duration(duration: &str) -> Option<Duration> {
+    if let Some(millis) = duration.strip_suffix("ms") {
+        millis.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = duration.strip_suffix('s') {
+        secs.parse().ok().map(Duration::from_secs)
+    } else {
+        None
+    }
+}
+
+fn parse_step(step: &str) -> Result<Step, String> {
+    let key = |name: &str| {
+        let (device, name) = split_device(name);
+        parse_gkey(name)
+            .map(|gkey| (device, gkey))
+            .map_err(|e| format!("In step \"{}\": {}", step, e))
+    };
+    let words = step.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["draw", gesture @ ..] if !gesture.is_empty() => gesture
+            .join(" ")
+            .parse()
+            .map(Step::Draw)
+            .map_err(|e| format!("In step \"{}\": {}", step, e)),
+        [name, "down"] => Ok(Step::Down(key(name)?)),
+        [name, "up"] => Ok(Step::Up(key(name)?)),
+        [name, "tap"] => Ok(Step::Tap(key(name)?)),
+        [name, "hold", duration] => {
+            let held = parse_duration(duration).ok_or_else(|| {
+                format!(
+                    "In step \"{}\": \"{}\" isn't a duration like 600ms or 2s",
+                    step, duration
+                )
+            })?;
+            Ok(Step::Hold(key(name)?, held))
+        }
+        _ => Err(format!(
+            "Unknown step \"{}\"; steps are like \"G9 down\", \"G9 up\", \"G9 tap\", \
+             \"G9 hold 600ms\" and \"draw L-U\"",
+            step
+        )),
+    }
+}
+
+/// Parses a script of steps separated by commas or lines
+pub fn parse_script(script: &str) -> Result<Vec<Step>, String> {
+    script
+        .split(&[',', '\n'][..])
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(parse_step)
+        .collect()
+}
+
+#[test]
+fn test_parse_script() {
+    use crate::gesture::Direction;
+    let steps = parse_script("G9 down, G9 up, G12 tap, G^10 hold 600ms\npad.G1 hold 2s, draw L-U,")
+        .unwrap();
+    assert_eq!(
+        steps,
+        vec![
+            Step::Down((None, 9)),
+            Step::Up((None, 9)),
+            Step::Tap((None, 12)),
+            Step::Hold((None, 110), Duration::from_millis(600)),
+            Step::Hold((Some("pad".to_string()), 1), Duration::from_secs(2)),
+            Step::Draw(Gesture(vec![Direction::Left, Direction::Up])),
+        ]
+    );
+    assert_eq!(
+        steps.iter().map(Step::to_string).collect::<Vec<_>>(),
+        vec![
+            "G9 down",
+            "G9 up",
+            "G12 tap",
+            "G^10 hold 600ms",
+            "pad.G1 hold 2000ms",
+            "draw L-U",
+        ]
+    );
+    assert_eq!(
+        parse_script("shift.left tap"),
+        Ok(vec![Step::Tap((None, 101))])
+    );
+    assert_eq!(
+        parse_script("G9 hold"),
+        Err(
+            "Unknown step \"G9 hold\"; steps are like \"G9 down\", \"G9 up\", \"G9 tap\", \
+             \"G9 hold 600ms\" and \"draw L-U\""
+                .to_string()
+        )
+    );
+    assert_eq!(
+        parse_script("G9 hold 1m"),
+        Err("In step \"G9 hold 1m\": \"1m\" isn't a duration like 600ms or 2s".to_string())
+    );
+    assert!(parse_script("thumb tap").is_err());
+    assert!(parse_script("draw L-X").is_err());
+}
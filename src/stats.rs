@@ -0,0 +1,249 @@
+use crate::config::gkey::{format_gkey, GSHIFT_OFFSET};
+use crate::device::{self, DeviceDescriptor};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const STATS_NAME: &str = "stats.json";
+
+/// Profile name used for presses while no profile is switched to at runtime, as is always the
+/// case for devices other than the first driven.
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyStats {
+    pub presses: u64,
+    /// Presses which performed an action, and thus contribute to latency
+    pub actions: u64,
+    pub failures: u64,
+    pub total_latency_us: u64,
+    pub max_latency_us: u64,
+}
+
+impl KeyStats {
+    pub fn average_latency_us(&self) -> u64 {
+        if self.actions == 0 {
+            0
+        } else {
+            self.total_latency_us / self.actions
+        }
+    }
+}
+
+/// Press counts per device and profile, per G-key; the layer is implied by the G-key.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// By device name, then profile
+    pub devices: BTreeMap<String, BTreeMap<String, BTreeMap<u32, KeyStats>>>,
+}
+
+pub fn layer_name(gkey: u32) -> &'static str {
+    if gkey >= GSHIFT_OFFSET {
+        "G-shift"
+    } else {
+        "base"
+    }
+}
+
+fn stats_path() -> ::std::io::Result<::std::path::PathBuf> {
+    xdg::BaseDirectories::with_prefix("lg600r")
+        .map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::NotFound, e))
+        .and_then(|basedirs| basedirs.place_data_file(STATS_NAME))
+}
+
+impl Stats {
+    /// Loads persisted statistics, starting afresh if none exist yet.
+    pub fn load() -> Result<Stats, Box<dyn (::std::error::Error)>> {
+        let path = stats_path()?;
+        if !path.exists() {
+            return Ok(Stats::default());
+        }
+        let contents = ::std::fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn (::std::error::Error)>> {
+        let path = stats_path()?;
+        // Write-then-rename, so a crash mid-write can't truncate the history
+        let staging = path.with_extension("json.tmp");
+        ::std::fs::write(&staging, serde_json::to_string_pretty(self)?)?;
+        ::std::fs::rename(&staging, &path)?;
+        Ok(())
+    }
+
+    fn entry(&mut self, device: &str, profile: &str, gkey: u32) -> &mut KeyStats {
+        self.devices
+            .entry(device.to_string())
+            .or_default()
+            .entry(profile.to_string())
+            .or_default()
+            .entry(gkey)
+            .or_default()
+    }
+
+    /// Counts a press of a G-key on a device, by name, while a profile is switched to
+    pub fn record_press(&mut self, device: &str, profile: &str, gkey: u32) {
+        self.entry(device, profile, gkey).presses += 1;
+    }
+
+    pub fn record_action(
+        &mut self,
+        device: &str,
+        profile: &str,
+        gkey: u32,
+        latency: Duration,
+        succeeded: bool,
+    ) {
+        let latency_us = latency.as_micros() as u64;
+        let entry = self.entry(device, profile, gkey);
+        entry.actions += 1;
+        entry.total_latency_us += latency_us;
+        entry.max_latency_us = entry.max_latency_us.max(latency_us);
+        if !succeeded {
+            entry.failures += 1;
+        }
+    }
+
+    pub fn render_table(&self) -> String {
+        let mut out = format!(
+            "{:<10} {:<10} {:<6} {:<8} {:>8} {:>8} {:>12} {:>12}\n",
+            "Device",
+            "Profile",
+            "Key",
+            "Layer",
+            "Presses",
+            "Failures",
+            "Avg latency",
+            "Max latency"
+        );
+        let profiles = self.devices.iter().flat_map(|(device, profiles)| {
+            profiles
+                .iter()
+                .map(move |(profile, keys)| (device, profile, keys))
+        });
+        for (device, profile, keys) in profiles {
+            let mut rows = keys.iter().collect::<Vec<_>>();
+            rows.sort_by(|(ka, a), (kb, b)| b.presses.cmp(&a.presses).then(ka.cmp(kb)));
+            for (gkey, stats) in rows {
+                out.push_str(&format!(
+                    "{:<10} {:<10} {:<6} {:<8} {:>8} {:>8} {:>10}ms {:>10}ms\n",
+                    device,
+                    profile,
+                    format_gkey(*gkey),
+                    layer_name(*gkey),
+                    stats.presses,
+                    stats.failures,
+                    stats.average_latency_us() / 1000,
+                    stats.max_latency_us / 1000,
+                ));
+            }
+        }
+        out
+    }
+
+    /// Lays each profile out over the device it was used on, or over the first device if that
+    /// isn't described anymore
+    pub fn render_heatmap(&self, devices: &[DeviceDescriptor]) -> String {
+        let mut out = String::new();
+        for (name, profiles) in &self.devices {
+            let device = devices
+                .iter()
+                .find(|device| device.name == *name)
+                .unwrap_or(&devices[0]);
+            for (profile, keys) in profiles {
+                out.push_str(&format!("{}, profile \"{}\"\n", name, profile));
+                out.push_str(&render_heatmap(keys, device));
+            }
+        }
+        out
+    }
+}
+
+const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+fn shade(presses: u64, max: u64) -> char {
+    if presses == 0 || max == 0 {
+        SHADES[0]
+    } else {
+        // Any use at all earns at least the lightest shade
+        let level = (presses * (SHADES.len() as u64 - 1) + max - 1) / max;
+        SHADES[level as usize]
+    }
+}
+
+fn render_heatmap(keys: &BTreeMap<u32, KeyStats>, device: &DeviceDescriptor) -> String {
+    let max = keys.values().map(|k| k.presses).max().unwrap_or(0);
+    let presses = |gkey: u32| keys.get(&gkey).map(|k| k.presses).unwrap_or(0);
+    let rows = device::heatmap_rows(device, &keys.keys().cloned().collect::<Vec<_>>());
+    // Labels are padded to line up, with at least the room a G-number takes
+    let width = rows
+        .iter()
+        .flatten()
+        .map(|g| device.label(g + GSHIFT_OFFSET).chars().count() + 1)
+        .fold(5, usize::max);
+    let mut out = String::new();
+    for &(offset, layer) in &[(0u32, "Base layer"), (GSHIFT_OFFSET, "G-shift layer")] {
+        out.push_str(&format!("  {}\n", layer));
+        for row in &rows {
+            let cells = row
+                .iter()
+                .map(|g| {
+                    let gkey = g + offset;
+                    let s = shade(presses(gkey), max);
+                    let label = device.label(gkey);
+                    format!(
+                        "{:<width$}{}{}{:>6}",
+                        label,
+                        s,
+                        s,
+                        presses(gkey),
+                        width = width
+                    )
+                })
+                .collect::<Vec<_>>();
+            out.push_str(&format!("    {}\n", cells.join("  ")));
+        }
+    }
+    out
+}
+
+#[test]
+fn test_heatmap_shading() {
+    let mut stats = Stats::default();
+    for _ in 0..8 {
+        stats.record_press("g600", DEFAULT_PROFILE, 9);
+    }
+    stats.record_press("g600", DEFAULT_PROFILE, 112);
+    stats.record_action("g600", DEFAULT_PROFILE, 9, Duration::from_millis(4), false);
+
+    let keys = &stats.devices["g600"][DEFAULT_PROFILE];
+    assert_eq!(keys[&9].failures, 1);
+    assert_eq!(keys[&9].average_latency_us(), 4000);
+
+    let heatmap = render_heatmap(keys, &device::builtin()[0]);
+    assert!(heatmap.contains("G9   ██     8"));
+    assert!(heatmap.contains("G^12 ░░     1"));
+    assert!(heatmap.contains("G10  ··     0"));
+
+    // Each device's profiles are counted apart, even if another device has one of the name
+    stats.record_press("naga", DEFAULT_PROFILE, 9);
+    stats.record_press("naga", "naga", 10);
+    assert_eq!(stats.devices["g600"][DEFAULT_PROFILE][&9].presses, 8);
+    let heatmap = stats.render_heatmap(&device::builtin());
+    let sections = heatmap
+        .lines()
+        .filter(|line| line.contains("profile"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sections,
+        vec![
+            "g600, profile \"default\"",
+            "naga, profile \"default\"",
+            "naga, profile \"naga\"",
+        ]
+    );
+    // Laid out over the Naga's grid, where G10 is its button 2
+    assert!(heatmap.contains("2        ██     1"));
+    assert!(stats
+        .render_table()
+        .contains("\nnaga       naga       G10    base"));
+}
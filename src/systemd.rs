@@ -0,0 +1,199 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// The user unit `lg600r setup systemd` installs, under the user's systemd config directory
+pub const UNIT_NAME: &str = "lg600r.service";
+
+/// Tells systemd how the service is doing, like `READY=1` once started, if it's waiting to hear
+pub fn notify(state: &str) -> io::Result<()> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => notify_to(&socket, state),
+        None => Ok(()),
+    }
+}
+
+fn notify_to(socket: &OsStr, state: &str) -> io::Result<()> {
+    let (address, length) = socket_address(socket)?;
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sent = unsafe {
+        libc::sendto(
+            fd,
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            libc::MSG_NOSIGNAL,
+            &address as *const libc::sockaddr_un as *const libc::sockaddr,
+            length,
+        )
+    };
+    let result = if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// The address of a Unix socket, which is in the abstract namespace if written with a leading @
+fn socket_address(socket: &OsStr) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path = socket.as_bytes();
+    if path.is_empty() || path.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("\"{}\" isn't a socket address", socket.to_string_lossy()),
+        ));
+    }
+    for (slot, byte) in address.sun_path.iter_mut().zip(path) {
+        *slot = *byte as libc::c_char;
+    }
+    // The kernel marks the abstract namespace by a leading NUL in place of the @
+    if path[0] == b'@' {
+        address.sun_path[0] = 0;
+    }
+    let length = std::mem::size_of::<libc::sa_family_t>() + path.len();
+    Ok((address, length as libc::socklen_t))
+}
+
+/// Whether stderr goes to the journal, which names the stream it connected in `JOURNAL_STREAM`
+fn logs_to_journal() -> bool {
+    let stream = match std::env::var("JOURNAL_STREAM") {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(libc::STDERR_FILENO, &mut stat) } != 0 {
+        return false;
+    }
+    stream == format!("{}:{}", stat.st_dev, stat.st_ino)
+}
+
+/// How serious a line logged to stderr is.
+///
+/// Displayed at the start of a line, it's the `<N>` prefix journald reads the line's priority
+/// from, or nothing when stderr goes elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Error = 3,
+    Warning = 4,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if logs_to_journal() {
+            write!(f, "<{}>", *self as u8)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Quotes a word of a setting in a unit, if it needs to be, escaping specifiers like `%h`
+fn quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%");
+    if word.is_empty() || word.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c)) {
+        let escaped = escaped.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// A user unit running lg600r in the graphical session, with the config it's to use if that
+/// isn't found by default
+pub fn unit(executable: &Path, config: Option<&Path>) -> String {
+    let mut unit = vec![
+        "# Generated by `lg600r setup systemd`".to_string(),
+        "[Unit]".to_string(),
+        "Description=lg600r, carrying out bindings for the buttons of multi-button mice"
+            .to_string(),
+        "PartOf=graphical-session.target".to_string(),
+        "After=graphical-session.target".to_string(),
+        String::new(),
+        "[Service]".to_string(),
+        "Type=notify".to_string(),
+        // Commands expand variables, unlike the environment
+        format!(
+            "ExecStart={}",
+            quote(&executable.to_string_lossy().replace('$', "$$"))
+        ),
+        "ExecReload=/bin/kill -HUP $MAINPID".to_string(),
+        "Restart=on-failure".to_string(),
+        "RestartSec=5".to_string(),
+        // Fed every second from the event loop, which actions don't hold up
+        "WatchdogSec=30".to_string(),
+    ];
+    if let Some(config) = config {
+        let variable = format!("LG600R_CONFIG={}", config.to_string_lossy());
+        unit.push(format!("Environment={}", quote(&variable)));
+    }
+    unit.push(String::new());
+    unit.push("[Install]".to_string());
+    unit.push("WantedBy=graphical-session.target".to_string());
+    unit.join("\n") + "\n"
+}
+
+#[test]
+fn test_unit() {
+    assert_eq!(
+        unit(Path::new("/usr/local/bin/lg600r"), None),
+        "# Generated by `lg600r setup systemd`
+[Unit]
+Description=lg600r, carrying out bindings for the buttons of multi-button mice
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart=/usr/local/bin/lg600r
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=5
+WatchdogSec=30
+
+[Install]
+WantedBy=graphical-session.target
+"
+    );
+    let unit = unit(
+        Path::new("/home/me/my bin/$lg600r"),
+        Some(Path::new("/home/me/50% off.toml")),
+    );
+    assert!(unit.contains("\nExecStart=\"/home/me/my bin/$$lg600r\"\n"));
+    assert!(unit.contains("\nEnvironment=\"LG600R_CONFIG=/home/me/50%% off.toml\"\n"));
+}
+
+#[test]
+fn test_notify() {
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixDatagram;
+    let path = std::env::temp_dir().join(format!("lg600r-notify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixDatagram::bind(&path).unwrap();
+    notify_to(path.as_os_str(), "READY=1").unwrap();
+    let mut received = [0; 16];
+    let length = listener.recv(&mut received).unwrap();
+    assert_eq!(&received[..length], b"READY=1");
+    std::fs::remove_file(&path).unwrap();
+
+    let name = format!("@lg600r-notify-{}", std::process::id());
+    let (address, length) = socket_address(OsStr::new(&name)).unwrap();
+    let listener = unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        let address = &address as *const libc::sockaddr_un as *const libc::sockaddr;
+        assert_eq!(libc::bind(fd, address, length), 0);
+        UnixDatagram::from_raw_fd(fd)
+    };
+    notify_to(OsStr::new(&name), "STATUS=ok").unwrap();
+    let length = listener.recv(&mut received).unwrap();
+    assert_eq!(&received[..length], b"STATUS=ok");
+    assert!(notify_to(OsStr::new("/nonexistent/notify"), "READY=1").is_err());
+    assert!(notify_to(OsStr::new(""), "READY=1").is_err());
+}
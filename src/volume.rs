@@ -0,0 +1,620 @@
+#![allow(non_camel_case_types)]
+use crate::systemd::Priority;
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const PA_VOLUME_NORM: u32 = 0x10000;
+const PA_CHANNELS_MAX: usize = 32;
+
+const PA_CONTEXT_READY: c_int = 4;
+const PA_CONTEXT_FAILED: c_int = 5;
+const PA_CONTEXT_TERMINATED: c_int = 6;
+const PA_OPERATION_RUNNING: c_int = 0;
+
+type pa_threaded_mainloop = c_void;
+type pa_mainloop_api = c_void;
+type pa_context = c_void;
+type pa_operation = c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct pa_sample_spec {
+    format: c_int,
+    rate: u32,
+    channels: u8,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct pa_channel_map {
+    channels: u8,
+    map: [c_int; PA_CHANNELS_MAX],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct pa_cvolume {
+    channels: u8,
+    values: [u32; PA_CHANNELS_MAX],
+}
+
+/// The leading fields shared by `pa_sink_info` and `pa_source_info`; only ever read through
+/// pointers handed to us by libpulse, so the remainder of either struct needn't be declared.
+#[repr(C)]
+struct pa_device_info_prefix {
+    name: *const c_char,
+    index: u32,
+    description: *const c_char,
+    sample_spec: pa_sample_spec,
+    channel_map: pa_channel_map,
+    owner_module: u32,
+    volume: pa_cvolume,
+    mute: c_int,
+}
+
+type pa_context_notify_cb_t = extern "C" fn(*mut pa_context, *mut c_void);
+type pa_context_success_cb_t = extern "C" fn(*mut pa_context, c_int, *mut c_void);
+type pa_device_info_cb_t =
+    extern "C" fn(*mut pa_context, *const pa_device_info_prefix, c_int, *mut c_void);
+
+/// The library the functions below are looked up in, on first connecting to the sound server,
+/// so that lg600r runs without it for those who don't change the volume or fall back instead
+const LIBPULSE: &str = "libpulse.so.0";
+
+/// Declares the libpulse functions used: a `Pulse` table of them, filled in by looking each up
+/// in the library, and a function of each name calling through the table once it's loaded
+macro_rules! libpulse {
+    ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        struct Pulse {
+            $($name: unsafe extern "C" fn($($ty),*) $(-> $ret)?,)*
+        }
+
+        impl Pulse {
+            unsafe fn load(library: *mut c_void) -> Result<Pulse, String> {
+                Ok(Pulse {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let symbol = libc::dlsym(library, name.as_ptr() as *const c_char);
+                        if symbol.is_null() {
+                            return Err(format!(
+                                "{} has no {}",
+                                LIBPULSE,
+                                stringify!($name)
+                            ));
+                        }
+                        std::mem::transmute::<
+                            *mut c_void,
+                            unsafe extern "C" fn($($ty),*) $(-> $ret)?,
+                        >(symbol)
+                    },)*
+                })
+            }
+        }
+
+        $(unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+            (loaded().$name)($($arg),*)
+        })*
+    };
+}
+
+libpulse! {
+    fn pa_threaded_mainloop_new() -> *mut pa_threaded_mainloop;
+    fn pa_threaded_mainloop_free(m: *mut pa_threaded_mainloop);
+    fn pa_threaded_mainloop_start(m: *mut pa_threaded_mainloop) -> c_int;
+    fn pa_threaded_mainloop_stop(m: *mut pa_threaded_mainloop);
+    fn pa_threaded_mainloop_lock(m: *mut pa_threaded_mainloop);
+    fn pa_threaded_mainloop_unlock(m: *mut pa_threaded_mainloop);
+    fn pa_threaded_mainloop_wait(m: *mut pa_threaded_mainloop);
+    fn pa_threaded_mainloop_signal(m: *mut pa_threaded_mainloop, wait_for_accept: c_int);
+    fn pa_threaded_mainloop_get_api(m: *mut pa_threaded_mainloop) -> *mut pa_mainloop_api;
+
+    fn pa_context_new(api: *mut pa_mainloop_api, name: *const c_char) -> *mut pa_context;
+    fn pa_context_unref(c: *mut pa_context);
+    fn pa_context_connect(
+        c: *mut pa_context,
+        server: *const c_char,
+        flags: c_int,
+        api: *const c_void,
+    ) -> c_int;
+    fn pa_context_disconnect(c: *mut pa_context);
+    fn pa_context_get_state(c: *mut pa_context) -> c_int;
+    fn pa_context_errno(c: *mut pa_context) -> c_int;
+    fn pa_context_set_state_callback(
+        c: *mut pa_context,
+        cb: pa_context_notify_cb_t,
+        userdata: *mut c_void,
+    );
+
+    fn pa_context_get_sink_info_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        cb: pa_device_info_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    fn pa_context_get_source_info_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        cb: pa_device_info_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    fn pa_context_set_sink_volume_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        volume: *const pa_cvolume,
+        cb: pa_context_success_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    fn pa_context_set_source_volume_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        volume: *const pa_cvolume,
+        cb: pa_context_success_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    fn pa_context_set_sink_mute_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        mute: c_int,
+        cb: pa_context_success_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    fn pa_context_set_source_mute_by_name(
+        c: *mut pa_context,
+        name: *const c_char,
+        mute: c_int,
+        cb: pa_context_success_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+
+    fn pa_operation_get_state(o: *mut pa_operation) -> c_int;
+    fn pa_operation_unref(o: *mut pa_operation);
+    fn pa_strerror(error: c_int) -> *const c_char;
+}
+
+static PULSE: AtomicPtr<Pulse> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Loads libpulse, if it isn't already
+fn load_pulse() -> Result<(), String> {
+    if !PULSE.load(Ordering::Acquire).is_null() {
+        return Ok(());
+    }
+    let pulse = unsafe {
+        let name = CString::new(LIBPULSE).unwrap();
+        let library = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if library.is_null() {
+            let error = CStr::from_ptr(libc::dlerror())
+                .to_string_lossy()
+                .into_owned();
+            return Err(format!("Couldn't load PulseAudio's library: {}", error));
+        }
+        // The library stays loaded for good, as the table points into it
+        Pulse::load(library)?
+    };
+    let pulse = Box::into_raw(Box::new(pulse));
+    let swapped = PULSE.compare_exchange(
+        std::ptr::null_mut(),
+        pulse,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    if swapped.is_err() {
+        // Loaded by another thread meanwhile
+        drop(unsafe { Box::from_raw(pulse) });
+    }
+    Ok(())
+}
+
+/// The functions of libpulse, which must have been loaded by `load_pulse`
+fn loaded() -> &'static Pulse {
+    let pulse = PULSE.load(Ordering::Acquire);
+    assert!(!pulse.is_null(), "libpulse is used before it's loaded");
+    unsafe { &*pulse }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VolumeOperation {
+    /// Changes the volume by this many percent
+    Step(i32),
+    /// Sets the volume to this many percent
+    Set(u32),
+    /// Mutes or unmutes, or toggles muting if `None`
+    Mute(Option<bool>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VolumeDevice {
+    Sink(String),
+    Source(String),
+}
+
+impl Default for VolumeDevice {
+    fn default() -> Self {
+        VolumeDevice::Sink("@DEFAULT_SINK@".to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeAction {
+    pub operation: VolumeOperation,
+    pub device: VolumeDevice,
+    /// While held, repeat the operation at this interval
+    pub repeat_ms: Option<u32>,
+    /// Shell command to run instead, should the sound server be unreachable
+    pub fallback: Option<String>,
+}
+
+/// Applies a step to every channel, keeping within silence and 100%
+fn stepped(volume: &pa_cvolume, percent: i32) -> pa_cvolume {
+    let mut stepped = *volume;
+    let delta = i64::from(percent) * i64::from(PA_VOLUME_NORM) / 100;
+    for value in stepped.values.iter_mut().take(volume.channels as usize) {
+        let adjusted = (i64::from(*value) + delta)
+            .max(0)
+            .min(i64::from(PA_VOLUME_NORM));
+        *value = adjusted as u32;
+    }
+    stepped
+}
+
+fn set_to(volume: &pa_cvolume, percent: u32) -> pa_cvolume {
+    let mut set = *volume;
+    let level = (u64::from(percent.min(100)) * u64::from(PA_VOLUME_NORM) / 100) as u32;
+    for value in set.values.iter_mut().take(volume.channels as usize) {
+        *value = level;
+    }
+    set
+}
+
+struct Pending<T> {
+    mainloop: *mut pa_threaded_mainloop,
+    result: Option<T>,
+}
+
+extern "C" fn on_state_change(_context: *mut pa_context, mainloop: *mut c_void) {
+    unsafe { pa_threaded_mainloop_signal(mainloop, 0) }
+}
+
+extern "C" fn on_device_info(
+    _context: *mut pa_context,
+    info: *const pa_device_info_prefix,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    unsafe {
+        let pending = &mut *(userdata as *mut Pending<(pa_cvolume, bool)>);
+        if eol == 0 && !info.is_null() {
+            pending.result = Some(((*info).volume, (*info).mute != 0));
+        }
+        pa_threaded_mainloop_signal(pending.mainloop, 0);
+    }
+}
+
+extern "C" fn on_success(_context: *mut pa_context, success: c_int, userdata: *mut c_void) {
+    unsafe {
+        let pending = &mut *(userdata as *mut Pending<bool>);
+        pending.result = Some(success != 0);
+        pa_threaded_mainloop_signal(pending.mainloop, 0);
+    }
+}
+
+/// A connection to PulseAudio, or to PipeWire through its PulseAudio server
+struct PulseConnection {
+    mainloop: *mut pa_threaded_mainloop,
+    context: *mut pa_context,
+}
+
+impl PulseConnection {
+    fn connect() -> Result<PulseConnection, String> {
+        load_pulse()?;
+        unsafe {
+            let mainloop = pa_threaded_mainloop_new();
+            if mainloop.is_null() {
+                return Err("Couldn't create a PulseAudio main loop".to_string());
+            }
+            let name = CString::new("lg600r").unwrap();
+            let context = pa_context_new(pa_threaded_mainloop_get_api(mainloop), name.as_ptr());
+            if context.is_null() {
+                pa_threaded_mainloop_free(mainloop);
+                return Err("Couldn't create a PulseAudio context".to_string());
+            }
+            // From here on, dropping the connection cleans up after us
+            let connection = PulseConnection { mainloop, context };
+            pa_context_set_state_callback(context, on_state_change, mainloop);
+            if pa_context_connect(context, std::ptr::null(), 0, std::ptr::null()) < 0 {
+                return Err(connection.last_error());
+            }
+            pa_threaded_mainloop_lock(mainloop);
+            if pa_threaded_mainloop_start(mainloop) < 0 {
+                pa_threaded_mainloop_unlock(mainloop);
+                return Err("Couldn't start the PulseAudio main loop".to_string());
+            }
+            let state = loop {
+                match pa_context_get_state(context) {
+                    state @ PA_CONTEXT_READY
+                    | state @ PA_CONTEXT_FAILED
+                    | state @ PA_CONTEXT_TERMINATED => break state,
+                    _ => pa_threaded_mainloop_wait(mainloop),
+                }
+            };
+            pa_threaded_mainloop_unlock(mainloop);
+            if state == PA_CONTEXT_READY {
+                Ok(connection)
+            } else {
+                Err(connection.last_error())
+            }
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        unsafe {
+            pa_threaded_mainloop_lock(self.mainloop);
+            let state = pa_context_get_state(self.context);
+            pa_threaded_mainloop_unlock(self.mainloop);
+            state == PA_CONTEXT_READY
+        }
+    }
+
+    fn last_error(&self) -> String {
+        unsafe {
+            let message = CStr::from_ptr(pa_strerror(pa_context_errno(self.context)));
+            format!("PulseAudio: {}", message.to_string_lossy())
+        }
+    }
+
+    /// Runs an operation to completion, with the main loop locked throughout
+    unsafe fn complete<T, F>(&self, start: F) -> Result<T, String>
+    where
+        F: FnOnce(*mut c_void) -> *mut pa_operation,
+    {
+        let mut pending = Pending::<T> {
+            mainloop: self.mainloop,
+            result: None,
+        };
+        pa_threaded_mainloop_lock(self.mainloop);
+        let operation = start(&mut pending as *mut Pending<T> as *mut c_void);
+        if operation.is_null() {
+            pa_threaded_mainloop_unlock(self.mainloop);
+            return Err(self.last_error());
+        }
+        while pa_operation_get_state(operation) == PA_OPERATION_RUNNING {
+            pa_threaded_mainloop_wait(self.mainloop);
+        }
+        pa_operation_unref(operation);
+        pa_threaded_mainloop_unlock(self.mainloop);
+        pending.result.ok_or_else(|| self.last_error())
+    }
+
+    fn apply(&self, operation: VolumeOperation, device: &VolumeDevice) -> Result<(), String> {
+        let (name, is_sink) = match device {
+            VolumeDevice::Sink(name) => (name, true),
+            VolumeDevice::Source(name) => (name, false),
+        };
+        let name = CString::new(crate::config::expand_env(name)).map_err(|e| e.to_string())?;
+        let context = self.context;
+        unsafe {
+            let (volume, muted): (pa_cvolume, bool) = self.complete(|userdata| {
+                if is_sink {
+                    pa_context_get_sink_info_by_name(
+                        context,
+                        name.as_ptr(),
+                        on_device_info,
+                        userdata,
+                    )
+                } else {
+                    pa_context_get_source_info_by_name(
+                        context,
+                        name.as_ptr(),
+                        on_device_info,
+                        userdata,
+                    )
+                }
+            })?;
+            let succeeded: bool = match operation {
+                VolumeOperation::Mute(mute) => {
+                    let mute = mute.unwrap_or(!muted) as c_int;
+                    self.complete(|userdata| {
+                        if is_sink {
+                            pa_context_set_sink_mute_by_name(
+                                context,
+                                name.as_ptr(),
+                                mute,
+                                on_success,
+                                userdata,
+                            )
+                        } else {
+                            pa_context_set_source_mute_by_name(
+                                context,
+                                name.as_ptr(),
+                                mute,
+                                on_success,
+                                userdata,
+                            )
+                        }
+                    })?
+                }
+                VolumeOperation::Step(_) | VolumeOperation::Set(_) => {
+                    let volume = match operation {
+                        VolumeOperation::Step(percent) => stepped(&volume, percent),
+                        VolumeOperation::Set(percent) => set_to(&volume, percent),
+                        VolumeOperation::Mute(_) => unreachable!(),
+                    };
+                    self.complete(|userdata| {
+                        if is_sink {
+                            pa_context_set_sink_volume_by_name(
+                                context,
+                                name.as_ptr(),
+                                &volume,
+                                on_success,
+                                userdata,
+                            )
+                        } else {
+                            pa_context_set_source_volume_by_name(
+                                context,
+                                name.as_ptr(),
+                                &volume,
+                                on_success,
+                                userdata,
+                            )
+                        }
+                    })?
+                }
+            };
+            if succeeded {
+                Ok(())
+            } else {
+                Err(self.last_error())
+            }
+        }
+    }
+}
+
+impl Drop for PulseConnection {
+    fn drop(&mut self) {
+        unsafe {
+            // The main loop's thread may be dispatching the context's callbacks until it's locked
+            pa_threaded_mainloop_lock(self.mainloop);
+            pa_context_disconnect(self.context);
+            pa_context_unref(self.context);
+            pa_threaded_mainloop_unlock(self.mainloop);
+            pa_threaded_mainloop_stop(self.mainloop);
+            pa_threaded_mainloop_free(self.mainloop);
+        }
+    }
+}
+
+enum Request {
+    Press(VolumeAction, mpsc::Sender<Result<(), String>>),
+    Release,
+}
+
+/// Applies volume actions on a thread of its own, so that held buttons can repeat them
+pub struct VolumeControl {
+    requests: mpsc::Sender<Request>,
+}
+
+impl Default for VolumeControl {
+    fn default() -> Self {
+        let (requests, incoming) = mpsc::channel();
+        std::thread::spawn(move || VolumeWorker { connection: None }.serve(incoming));
+        VolumeControl { requests }
+    }
+}
+
+impl VolumeControl {
+    /// Applies an action, then keeps repeating it in the background until released
+    pub fn press(&self, action: &VolumeAction) -> Result<(), String> {
+        let (reply, result) = mpsc::channel();
+        self.requests
+            .send(Request::Press(action.clone(), reply))
+            .map_err(|_| "Volume control has stopped".to_string())?;
+        result
+            .recv()
+            .unwrap_or_else(|_| Err("Volume control has stopped".to_string()))
+    }
+
+    pub fn release(&self) {
+        let _ = self.requests.send(Request::Release);
+    }
+}
+
+struct VolumeWorker {
+    connection: Option<PulseConnection>,
+}
+
+impl VolumeWorker {
+    fn serve(mut self, incoming: mpsc::Receiver<Request>) {
+        let mut next = None;
+        loop {
+            let request = match next.take() {
+                Some(request) => request,
+                None => match incoming.recv() {
+                    Ok(request) => request,
+                    Err(_) => return,
+                },
+            };
+            let action = match request {
+                Request::Press(action, reply) => {
+                    let _ = reply.send(self.apply(&action));
+                    action
+                }
+                Request::Release => continue,
+            };
+            // Only relative steps make sense to repeat
+            let interval = match (action.operation, action.repeat_ms) {
+                (VolumeOperation::Step(_), Some(ms)) => Duration::from_millis(u64::from(ms)),
+                _ => continue,
+            };
+            loop {
+                match incoming.recv_timeout(interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if let Err(e) = self.apply(&action) {
+                            eprintln!("{}Repeated volume change failed: {}", Priority::Error, e);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    Ok(Request::Release) => break,
+                    Ok(press) => {
+                        next = Some(press);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, action: &VolumeAction) -> Result<(), String> {
+        let result = self.apply_natively(action);
+        match (&result, &action.fallback) {
+            (Err(e), Some(fallback)) => {
+                eprintln!(
+                    "{}{}; falling back to \"{}\"",
+                    Priority::Warning,
+                    e,
+                    fallback
+                );
+                crate::exec::run(&crate::exec::ExecSpec::shell("sh", fallback))
+            }
+            _ => result,
+        }
+    }
+
+    fn apply_natively(&mut self, action: &VolumeAction) -> Result<(), String> {
+        if !self
+            .connection
+            .as_ref()
+            .map_or(false, PulseConnection::is_ready)
+        {
+            // Reconnect, in case the sound server has restarted since we last used it
+            self.connection = None;
+            self.connection = Some(PulseConnection::connect()?);
+        }
+        self.connection
+            .as_ref()
+            .unwrap()
+            .apply(action.operation, &action.device)
+    }
+}
+
+#[test]
+fn test_volume_arithmetic() {
+    let mut volume = pa_cvolume {
+        channels: 2,
+        values: [0; PA_CHANNELS_MAX],
+    };
+    volume.values[0] = PA_VOLUME_NORM / 2;
+    volume.values[1] = PA_VOLUME_NORM - 1;
+
+    let louder = stepped(&volume, 5);
+    assert_eq!(louder.values[0], PA_VOLUME_NORM / 2 + PA_VOLUME_NORM / 20);
+    assert_eq!(louder.values[1], PA_VOLUME_NORM);
+    assert_eq!(louder.values[2], 0);
+
+    let silent = stepped(&volume, -100);
+    assert_eq!(&silent.values[..2], &[0, 0]);
+
+    let set = set_to(&volume, 30);
+    assert_eq!(&set.values[..3], &[19660, 19660, 0]);
+}
@@ -23,15 +23,16 @@
 //SOFTWARE.
 use libc;
 
-use super::{Key, KeyboardControllable};
+use super::{Key, KeyCombo, KeyboardControllable, MouseControllable, Stroke};
 
-use libc::{c_char, c_int, useconds_t};
-use std::{borrow::Cow, ffi::CString, ptr};
+use libc::{c_char, c_int, c_uint, c_ulong, useconds_t};
+use std::{ffi::CString, ptr};
 
 const CURRENT_WINDOW: c_int = 0;
 const DEFAULT_DELAY: u64 = 12000;
 type Window = c_int;
 type Xdo = *const libc::c_void;
+type Display = libc::c_void;
 
 #[link(name = "xdo")]
 extern "C" {
@@ -43,6 +44,12 @@ extern "C" {
     fn xdo_mouse_up(xdo: Xdo, window: Window, button: c_int) -> c_int;
     fn xdo_move_mouse(xdo: Xdo, x: c_int, y: c_int, screen: c_int) -> c_int;
     fn xdo_move_mouse_relative(xdo: Xdo, x: c_int, y: c_int) -> c_int;
+    fn xdo_get_viewport_dimensions(
+        xdo: Xdo,
+        width: *mut c_uint,
+        height: *mut c_uint,
+        screen: c_int,
+    ) -> c_int;
 
     fn xdo_enter_text_window(
         xdo: Xdo,
@@ -70,19 +77,29 @@ extern "C" {
     ) -> c_int;
 }
 
-const MOUSEBUTTON_LEFT: c_int = 1;
-const MOUSEBUTTON_MIDDLE: c_int = 2;
-const MOUSEBUTTON_RIGHT: c_int = 3;
-const MOUSEBUTTON_SCROLL_UP: c_int = 4;
-const MOUSEBUTTON_SCROLL_DOWN: c_int = 5;
-const MOUSEBUTTON_SCROLL_LEFT: c_int = 6;
-const MOUSEBUTTON_SCROLL_RIGHT: c_int = 7;
-const MOUSEBUTTON_4: c_int = 8;
-const MOUSEBUTTON_5: c_int = 9;
+// xdo only deals in keysyms, so raw keycodes go through XTest directly
+#[link(name = "X11")]
+extern "C" {
+    fn XOpenDisplay(name: *const c_char) -> *mut Display;
+    fn XCloseDisplay(display: *mut Display) -> c_int;
+    fn XFlush(display: *mut Display) -> c_int;
+}
+
+#[link(name = "Xtst")]
+extern "C" {
+    fn XTestFakeKeyEvent(
+        display: *mut Display,
+        keycode: c_uint,
+        is_press: c_int,
+        delay: c_ulong,
+    ) -> c_int;
+}
 
 pub struct XdoManaged {
     xdo: Xdo,
     delay: u64,
+    /// Opened the first time a raw keycode is sent
+    display: *mut Display,
 }
 // This is safe, we have a unique pointer.
 // TODO: use Unique<c_char> once stable.
@@ -94,6 +111,7 @@ impl Default for XdoManaged {
         Self {
             xdo: unsafe { xdo_new(ptr::null()) },
             delay: DEFAULT_DELAY,
+            display: ptr::null_mut(),
         }
     }
 }
@@ -110,83 +128,96 @@ impl XdoManaged {
         self.delay = delay;
     }
 
-    pub fn mouse_move_to(&mut self, x: i32, y: i32) {
+    fn keycode(&mut self, keycode: u8, is_press: bool) {
         unsafe {
-            xdo_move_mouse(self.xdo, x as c_int, y as c_int, 0);
+            if self.display.is_null() {
+                self.display = XOpenDisplay(ptr::null());
+                if self.display.is_null() {
+                    eprintln!("Couldn't open the X display to send keycode {}", keycode);
+                    return;
+                }
+            }
+            XTestFakeKeyEvent(self.display, c_uint::from(keycode), is_press as c_int, 0);
+            XFlush(self.display);
         }
     }
-    pub fn mouse_move_relative(&mut self, x: i32, y: i32) {
+
+    fn stroke(&mut self, stroke: &Stroke, is_press: bool) {
+        match stroke {
+            Stroke::Keycode(keycode) => self.keycode(*keycode, is_press),
+            Stroke::Keysym(name) => {
+                let string = CString::new(name.as_str()).unwrap();
+                unsafe {
+                    let send = if is_press {
+                        xdo_send_keysequence_window_down
+                    } else {
+                        xdo_send_keysequence_window_up
+                    };
+                    send(
+                        self.xdo,
+                        CURRENT_WINDOW,
+                        string.as_ptr() as *const c_char,
+                        self.delay as useconds_t,
+                    );
+                }
+            }
+        }
+    }
+}
+impl MouseControllable for XdoManaged {
+    fn mouse_move_to(&mut self, x: i32, y: i32, screen: u32) {
+        unsafe {
+            let (mut width, mut height) = (0, 0);
+            if x < 0 || y < 0 {
+                xdo_get_viewport_dimensions(self.xdo, &mut width, &mut height, screen as c_int);
+            }
+            let from_far_edge = |position: i32, size: c_uint| {
+                if position < 0 {
+                    size as i32 + position
+                } else {
+                    position
+                }
+            };
+            xdo_move_mouse(
+                self.xdo,
+                from_far_edge(x, width) as c_int,
+                from_far_edge(y, height) as c_int,
+                screen as c_int,
+            );
+        }
+    }
+    fn mouse_move_relative(&mut self, x: i32, y: i32) {
         unsafe {
             xdo_move_mouse_relative(self.xdo, x as c_int, y as c_int);
         }
     }
-    pub fn mouse_down(&mut self, button: u8) {
+    fn mouse_down(&mut self, button: u8) {
         unsafe {
             xdo_mouse_down(self.xdo, CURRENT_WINDOW, c_int::from(button));
         }
     }
-    pub fn mouse_up(&mut self, button: u8) {
+    fn mouse_up(&mut self, button: u8) {
         unsafe {
             xdo_mouse_up(self.xdo, CURRENT_WINDOW, c_int::from(button));
         }
     }
-    pub fn mouse_click(&mut self, button: u8) {
+    fn mouse_click(&mut self, button: u8) {
         unsafe {
             xdo_click_window(self.xdo, CURRENT_WINDOW, c_int::from(button));
         }
     }
-    pub fn mouse_scroll_x(&mut self, length: i32) {
-        let button: c_int;
-        let mut length = length;
-
-        if length < 0 {
-            button = MOUSEBUTTON_SCROLL_LEFT;
-        } else {
-            button = MOUSEBUTTON_SCROLL_RIGHT;
-        }
-
-        if length < 0 {
-            length = -length;
-        }
-
-        for _ in 0..length {
-            self.mouse_click(button as u8);
-        }
-    }
-    pub fn mouse_scroll_y(&mut self, length: i32) {
-        let button: c_int;
-        let mut length = length;
-
-        if length < 0 {
-            button = MOUSEBUTTON_SCROLL_UP;
-        } else {
-            button = MOUSEBUTTON_SCROLL_DOWN;
-        }
-
-        if length < 0 {
-            length = -length;
-        }
-
-        for _ in 0..length {
-            self.mouse_click(button as u8);
-        }
-    }
 }
 impl Drop for XdoManaged {
     fn drop(&mut self) {
         unsafe {
             xdo_free(self.xdo);
+            if !self.display.is_null() {
+                XCloseDisplay(self.display);
+            }
         }
     }
 }
 
-fn keysequence<'a>(key: Key) -> Cow<'a, String> {
-    if let Key::Layout(c) = key {
-        return Cow::Owned(format!("U{:X}", c as u32));
-    }
-
-    Cow::Owned(key.to_string())
-}
 impl KeyboardControllable for XdoManaged {
     fn key_sequence(&mut self, sequence: &str) {
         let string = CString::new(sequence).unwrap();
@@ -200,36 +231,38 @@ impl KeyboardControllable for XdoManaged {
         }
     }
     fn key_down(&mut self, key: Key) {
-        let string = CString::new(&*keysequence(key).as_str()).unwrap();
-        unsafe {
-            xdo_send_keysequence_window_down(
-                self.xdo,
-                CURRENT_WINDOW,
-                string.as_ptr() as *const c_char,
-                self.delay as useconds_t,
-            );
-        }
+        self.stroke(&key.stroke(), true);
     }
     fn key_up(&mut self, key: Key) {
-        let string = CString::new(&*keysequence(key).as_str()).unwrap();
-        unsafe {
-            xdo_send_keysequence_window_up(
-                self.xdo,
-                CURRENT_WINDOW,
-                string.as_ptr() as *const c_char,
-                self.delay as useconds_t,
-            );
-        }
+        self.stroke(&key.stroke(), false);
     }
     fn key_click(&mut self, key: Key) {
-        let string = CString::new(&*keysequence(key).as_str()).unwrap();
-        unsafe {
-            xdo_send_keysequence_window(
-                self.xdo,
-                CURRENT_WINDOW,
-                string.as_ptr() as *const c_char,
-                self.delay as useconds_t,
-            );
+        match key.stroke() {
+            Stroke::Keysym(name) => {
+                let string = CString::new(name).unwrap();
+                unsafe {
+                    xdo_send_keysequence_window(
+                        self.xdo,
+                        CURRENT_WINDOW,
+                        string.as_ptr() as *const c_char,
+                        self.delay as useconds_t,
+                    );
+                }
+            }
+            stroke => {
+                self.stroke(&stroke, true);
+                self.stroke(&stroke, false);
+            }
+        }
+    }
+    fn combo_down(&mut self, combo: &KeyCombo) {
+        for stroke in combo.strokes() {
+            self.stroke(&stroke, true);
+        }
+    }
+    fn combo_up(&mut self, combo: &KeyCombo) {
+        for stroke in combo.strokes().iter().rev() {
+            self.stroke(stroke, false);
         }
     }
 }
@@ -21,7 +21,9 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
+pub mod keysyms;
 pub mod managed;
+pub mod recording;
 
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Key {
@@ -123,8 +125,12 @@ pub enum Key {
     UpArrow,
     /// keyboard layout dependent key
     Layout(char),
-    /// raw keycode eg 0x38
+    /// raw X keycode eg 0x38, as reported by xev; written `keycode:56`
     Raw(u16),
+    /// Linux evdev keycode eg 29 (KEY_LEFTCTRL), which X numbers 8 higher; written `evdev:29`
+    Evdev(u16),
+    /// any other X keysym, eg 0x1008ff13 (XF86AudioRaiseVolume)
+    Sym(u32),
     /// number row
     #[serde(rename = "1")]
     Number1,
@@ -148,8 +154,363 @@ pub enum Key {
     Number0,
 }
 
+impl Key {
+    /// Every name a `Key` can be parsed from, including aliases
+    pub const NAMES: &'static [&'static str] = &[
+        "Alt",
+        "Backspace",
+        "CapsLock",
+        "Control",
+        "Delete",
+        "DownArrow",
+        "End",
+        "Escape",
+        "F1",
+        "F2",
+        "F3",
+        "F4",
+        "F5",
+        "F6",
+        "F7",
+        "F8",
+        "F9",
+        "F10",
+        "F11",
+        "F12",
+        "F13",
+        "F14",
+        "F15",
+        "F16",
+        "F17",
+        "F18",
+        "F19",
+        "F20",
+        "F21",
+        "F22",
+        "F23",
+        "F24",
+        "Home",
+        "LeftArrow",
+        "Meta",
+        "Super_L",
+        "SuperL",
+        "Super",
+        "Super_R",
+        "SuperR",
+        "Hyper_L",
+        "HyperL",
+        "Hyper",
+        "Hyper_R",
+        "HyperR",
+        "Option",
+        "PageDown",
+        "PageUp",
+        "Return",
+        "RightArrow",
+        "Shift",
+        "Space",
+        "Tab",
+        "UpArrow",
+        "1",
+        "2",
+        "3",
+        "4",
+        "5",
+        "6",
+        "7",
+        "8",
+        "9",
+        "0",
+    ];
+}
+
+/// Friendlier names for keysyms, matched case-insensitively
+const KEYSYM_ALIASES: &[(&str, &str)] = &[
+    ("Ctrl", "Control_L"),
+    ("Win", "Super_L"),
+    ("Esc", "Escape"),
+    ("Enter", "Return"),
+    ("Ins", "Insert"),
+    ("Del", "Delete"),
+    ("PrintScreen", "Print"),
+    ("PrtSc", "Print"),
+    ("NumLock", "Num_Lock"),
+    ("ScrollLock", "Scroll_Lock"),
+    ("VolumeUp", "XF86AudioRaiseVolume"),
+    ("VolumeDown", "XF86AudioLowerVolume"),
+    ("Mute", "XF86AudioMute"),
+    ("MicMute", "XF86AudioMicMute"),
+    ("PlayPause", "XF86AudioPlay"),
+    ("MediaNext", "XF86AudioNext"),
+    ("MediaPrevious", "XF86AudioPrev"),
+    ("MediaStop", "XF86AudioStop"),
+    ("BrightnessUp", "XF86MonBrightnessUp"),
+    ("BrightnessDown", "XF86MonBrightnessDown"),
+];
+
+/// Modifier names usable in combos such as `ctrl+alt+Left`, matched case-insensitively
+const MODIFIERS: &[(&str, Key)] = &[
+    ("ctrl", Key::Control),
+    ("control", Key::Control),
+    ("alt", Key::Alt),
+    ("shift", Key::Shift),
+    ("super", Key::SuperL),
+    ("win", Key::SuperL),
+    ("logo", Key::SuperL),
+    ("meta", Key::Meta),
+    ("hyper", Key::HyperL),
+];
+
+fn keysym_by_name(name: &str) -> Option<u32> {
+    let exact = |name: &str| {
+        keysyms::KEYSYMS
+            .iter()
+            .find(|(sym, _)| *sym == name)
+            .map(|(_, value)| *value)
+    };
+    let alias = || {
+        KEYSYM_ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .and_then(|(_, sym)| exact(sym))
+    };
+    let insensitive = |name: &str| {
+        keysyms::KEYSYMS
+            .iter()
+            .find(|(sym, _)| sym.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    };
+    let hex = || {
+        if name.starts_with("0x") {
+            u32::from_str_radix(&name[2..], 16).ok()
+        } else {
+            None
+        }
+    };
+    // XF86 keys may be named without their prefix, eg AudioMute
+    let xf86 = format!("XF86{}", name);
+    exact(name)
+        .or_else(alias)
+        .or_else(|| exact(&xf86))
+        .or_else(|| insensitive(name))
+        .or_else(|| insensitive(&xf86))
+        .or_else(hex)
+}
+
+impl Key {
+    /// The X keysym name xdo is given for this key
+    pub fn keysym_name(&self) -> Option<std::borrow::Cow<'static, str>> {
+        use std::borrow::Cow;
+        let name = match self {
+            Key::Alt | Key::Option => "Alt_L",
+            Key::Backspace => "BackSpace",
+            Key::CapsLock => "Caps_Lock",
+            Key::Control => "Control_L",
+            Key::Delete => "Delete",
+            Key::DownArrow => "Down",
+            Key::End => "End",
+            Key::Escape => "Escape",
+            Key::F1 => "F1",
+            Key::F2 => "F2",
+            Key::F3 => "F3",
+            Key::F4 => "F4",
+            Key::F5 => "F5",
+            Key::F6 => "F6",
+            Key::F7 => "F7",
+            Key::F8 => "F8",
+            Key::F9 => "F9",
+            Key::F10 => "F10",
+            Key::F11 => "F11",
+            Key::F12 => "F12",
+            Key::F13 => "F13",
+            Key::F14 => "F14",
+            Key::F15 => "F15",
+            Key::F16 => "F16",
+            Key::F17 => "F17",
+            Key::F18 => "F18",
+            Key::F19 => "F19",
+            Key::F20 => "F20",
+            Key::F21 => "F21",
+            Key::F22 => "F22",
+            Key::F23 => "F23",
+            Key::F24 => "F24",
+            Key::Home => "Home",
+            Key::LeftArrow => "Left",
+            Key::Meta => "Meta_L",
+            Key::SuperL => "Super_L",
+            Key::SuperR => "Super_R",
+            Key::HyperL => "Hyper_L",
+            Key::HyperR => "Hyper_R",
+            Key::PageDown => "Next",
+            Key::PageUp => "Prior",
+            Key::Return => "Return",
+            Key::RightArrow => "Right",
+            Key::Shift => "Shift_L",
+            Key::Space => "space",
+            Key::Tab => "Tab",
+            Key::UpArrow => "Up",
+            Key::Number1 => "1",
+            Key::Number2 => "2",
+            Key::Number3 => "3",
+            Key::Number4 => "4",
+            Key::Number5 => "5",
+            Key::Number6 => "6",
+            Key::Number7 => "7",
+            Key::Number8 => "8",
+            Key::Number9 => "9",
+            Key::Number0 => "0",
+            Key::Sym(value) => {
+                return Some(
+                    keysyms::KEYSYMS
+                        .iter()
+                        .find(|(_, sym)| sym == value)
+                        .map(|(name, _)| Cow::Borrowed(*name))
+                        // XStringToKeysym understands hexadecimal keysyms as well
+                        .unwrap_or_else(|| Cow::Owned(format!("0x{:x}", value))),
+                );
+            }
+            Key::Layout(_) | Key::Raw(_) | Key::Evdev(_) => return None,
+        };
+        Some(Cow::Borrowed(name))
+    }
+
+    /// The key for a keysym, preferring the named variants to `Sym`
+    pub fn from_keysym(value: u32) -> Key {
+        use std::str::FromStr;
+        let sym = Key::Sym(value).keysym_name();
+        Key::NAMES
+            .iter()
+            .filter_map(|name| Key::from_str(name).ok())
+            .find(|key| key.keysym_name() == sym)
+            .unwrap_or(Key::Sym(value))
+    }
+
+    /// Every name a key might be written as, for suggesting corrections to typos
+    pub fn all_names() -> impl Iterator<Item = &'static str> {
+        Key::NAMES
+            .iter()
+            .cloned()
+            .chain(KEYSYM_ALIASES.iter().map(|(alias, _)| *alias))
+            .chain(keysyms::KEYSYMS.iter().map(|(name, _)| *name))
+    }
+}
+
+/// X numbers keycodes from 8, and no higher than 255
+const X_KEYCODES: ::std::ops::RangeInclusive<u16> = 8..=255;
+/// X keycodes are evdev keycodes offset by this much
+const EVDEV_TO_X_KEYCODE: u16 = 8;
+
+/// The lowest level at which a key is emitted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stroke {
+    /// A keysym name, which xdo maps to a keycode through the current keyboard layout
+    Keysym(String),
+    /// An X keycode, sent as is
+    Keycode(u8),
+}
+
+impl Key {
+    pub fn stroke(&self) -> Stroke {
+        match self {
+            Key::Layout(c) => Stroke::Keysym(format!("U{:X}", *c as u32)),
+            Key::Raw(code) => Stroke::Keycode(*code as u8),
+            Key::Evdev(code) => Stroke::Keycode((*code + EVDEV_TO_X_KEYCODE) as u8),
+            key => Stroke::Keysym(key.keysym_name().unwrap().into_owned()),
+        }
+    }
+
+    /// Parses the `keycode:56` and `evdev:29` forms of raw and evdev keycodes
+    fn parse_keycode(s: &str) -> Option<Key> {
+        let number = |n: &str| {
+            if n.starts_with("0x") {
+                u16::from_str_radix(&n[2..], 16).ok()
+            } else {
+                n.parse::<u16>().ok()
+            }
+        };
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next().and_then(number)) {
+            (Some("keycode"), Some(code)) if X_KEYCODES.contains(&code) => Some(Key::Raw(code)),
+            (Some("evdev"), Some(code)) => code
+                .checked_add(EVDEV_TO_X_KEYCODE)
+                .filter(|code| X_KEYCODES.contains(code))
+                .map(|_| Key::Evdev(code)),
+            _ => None,
+        }
+    }
+}
+
+/// A key, along with any modifiers held while it's pressed
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyCombo {
+    pub modifiers: Vec<Key>,
+    pub key: Key,
+}
+
+impl KeyCombo {
+    /// The strokes to press, in order; they're released in reverse
+    pub fn strokes(&self) -> Vec<Stroke> {
+        self.modifiers
+            .iter()
+            .chain(::std::iter::once(&self.key))
+            .map(Key::stroke)
+            .collect()
+    }
+}
+
+impl From<Key> for KeyCombo {
+    fn from(key: Key) -> KeyCombo {
+        KeyCombo {
+            modifiers: Vec::new(),
+            key,
+        }
+    }
+}
+
+#[test]
+fn test_key_names_parse() {
+    use std::str::FromStr;
+    for name in Key::NAMES {
+        assert!(Key::from_str(name).is_ok(), "{} should parse", name);
+    }
+}
+
+#[test]
+fn test_parse_keysyms_and_combos() {
+    use std::str::FromStr;
+    let parse = |s: &str| KeyCombo::from_str(s).map(|combo| combo.to_string());
+    assert_eq!(parse("Print"), Ok("Print".to_string()));
+    assert_eq!(parse("volumeup"), Ok("XF86AudioRaiseVolume".to_string()));
+    assert_eq!(parse("AudioMute"), Ok("XF86AudioMute".to_string()));
+    assert_eq!(parse("KP_1"), Ok("KP_1".to_string()));
+    assert_eq!(parse("Page_Down"), Ok("PageDown".to_string()));
+    assert_eq!(parse("0xff63"), Ok("Insert".to_string()));
+    assert_eq!(parse("Ctrl+Alt+Left"), Ok("ctrl+alt+LeftArrow".to_string()));
+    assert_eq!(
+        parse("super+shift+Return"),
+        Ok("super+shift+Return".to_string())
+    );
+    assert_eq!(parse("ctrl++"), Ok("ctrl+plus".to_string()));
+    assert_eq!(parse("ctrl+Bogus"), Err("Bogus".to_string()));
+    assert_eq!(parse("ctrl+"), Err("".to_string()));
+
+    assert_eq!(
+        Key::from_str("Ins").unwrap().keysym_name().unwrap(),
+        "Insert"
+    );
+    assert_eq!(Key::DownArrow.keysym_name().unwrap(), "Down");
+    assert_eq!(Key::Sym(0x1234567).keysym_name().unwrap(), "0x1234567");
+
+    assert_eq!(parse("keycode:0x38"), Ok("keycode:56".to_string()));
+    assert_eq!(parse("ctrl+evdev:30"), Ok("ctrl+evdev:30".to_string()));
+    assert_eq!(parse("é"), Ok("é".to_string()));
+    assert_eq!(parse("keycode:7"), Err("keycode:7".to_string()));
+    assert_eq!(parse("evdev:248"), Err("evdev:248".to_string()));
+    assert_eq!(parse("evdev:65535"), Err("evdev:65535".to_string()));
+}
+
 mod formatting_impls {
-    use super::Key;
+    use super::{keysym_by_name, Key, KeyCombo, MODIFIERS};
     use std::fmt::{Debug, Display, Error, Formatter};
 
     impl Display for Key {
@@ -160,19 +521,92 @@ mod formatting_impls {
 
     impl Debug for Key {
         fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            match self {
+                Key::Sym(_) => return f.write_str(&self.keysym_name().unwrap()),
+                Key::Raw(code) => return write!(f, "keycode:{}", code),
+                Key::Evdev(code) => return write!(f, "evdev:{}", code),
+                Key::Layout(c) => return write!(f, "{}", c),
+                _ => (),
+            }
             serde_json::to_value(self)
                 .map_err(|_| std::fmt::Error)
                 .and_then(|s| f.write_str(&s.as_str().unwrap()))
         }
     }
 
+    impl Display for KeyCombo {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            Debug::fmt(self, f)
+        }
+    }
+
+    impl Debug for KeyCombo {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            for modifier in &self.modifiers {
+                match MODIFIERS.iter().find(|(_, key)| key == modifier) {
+                    Some((name, _)) => write!(f, "{}+", name)?,
+                    None => write!(f, "{}+", modifier)?,
+                }
+            }
+            Display::fmt(&self.key, f)
+        }
+    }
+
     use std::str::FromStr;
 
     impl FromStr for Key {
         type Err = Box<dyn std::error::Error>;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
-            serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|e| e.into())
+            serde_json::from_value(serde_json::Value::String(s.to_string())).or_else(|e| {
+                let mut chars = s.chars();
+                let single = match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(Key::Layout(c)),
+                    _ => None,
+                };
+                Key::parse_keycode(s)
+                    .or_else(|| keysym_by_name(s).map(Key::from_keysym))
+                    // Any other single character is typed through the keyboard layout
+                    .or(single)
+                    .ok_or_else(|| e.into())
+            })
+        }
+    }
+
+    impl FromStr for KeyCombo {
+        /// The part of the combo that isn't a known key
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            // `+` separates keys, so a trailing `++` stands for the plus key itself
+            let (s, plus) = if s == "+" {
+                ("", true)
+            } else if s.ends_with("++") {
+                (&s[..s.len() - 2], true)
+            } else {
+                (s, false)
+            };
+            let mut keys = s
+                .split('+')
+                .filter(|_| !s.is_empty())
+                .map(|part| {
+                    MODIFIERS
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(part.trim()))
+                        .map(|(_, key)| Ok(*key))
+                        .unwrap_or_else(|| Key::from_str(part.trim()).map_err(|_| part.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if plus {
+                keys.push(Key::from_keysym(0x2b));
+            }
+            match keys.pop() {
+                Some(key) => Ok(KeyCombo {
+                    modifiers: keys,
+                    key,
+                }),
+                None => Err(String::new()),
+            }
         }
     }
 }
@@ -206,4 +640,63 @@ pub trait KeyboardControllable {
     /// [key_up](trait.KeyboardControllable.html#tymethod.key_up)
     /// function they're just invoked consecutively
     fn key_click(&mut self, key: Key);
+
+    /// presses the combo's modifiers, then its key
+    fn combo_down(&mut self, combo: &KeyCombo);
+
+    /// releases the combo's key, then its modifiers
+    fn combo_up(&mut self, combo: &KeyCombo);
+}
+
+pub trait MouseControllable {
+    /// Moves the pointer to an absolute position on an X screen;
+    /// negative coordinates count back from its right and bottom edges
+    fn mouse_move_to(&mut self, x: i32, y: i32, screen: u32);
+
+    /// Moves the pointer relative to where it is
+    fn mouse_move_relative(&mut self, x: i32, y: i32);
+
+    /// presses a mouse button down
+    fn mouse_down(&mut self, button: u8);
+
+    /// releases a mouse button formerly pressed down by
+    /// [mouse_down](trait.MouseControllable.html#tymethod.mouse_down)
+    fn mouse_up(&mut self, button: u8);
+
+    /// presses and releases a mouse button
+    fn mouse_click(&mut self, button: u8);
+
+    /// Scrolls horizontally, to the left if negative, by clicking the scroll buttons
+    fn mouse_scroll_x(&mut self, length: i32) {
+        let button = if length < 0 {
+            MOUSEBUTTON_SCROLL_LEFT
+        } else {
+            MOUSEBUTTON_SCROLL_RIGHT
+        };
+        for _ in 0..length.abs() {
+            self.mouse_click(button);
+        }
+    }
+
+    /// Scrolls vertically, upwards if negative, by clicking the scroll buttons
+    fn mouse_scroll_y(&mut self, length: i32) {
+        let button = if length < 0 {
+            MOUSEBUTTON_SCROLL_UP
+        } else {
+            MOUSEBUTTON_SCROLL_DOWN
+        };
+        for _ in 0..length.abs() {
+            self.mouse_click(button);
+        }
+    }
 }
+
+pub const MOUSEBUTTON_SCROLL_UP: u8 = 4;
+pub const MOUSEBUTTON_SCROLL_DOWN: u8 = 5;
+pub const MOUSEBUTTON_SCROLL_LEFT: u8 = 6;
+pub const MOUSEBUTTON_SCROLL_RIGHT: u8 = 7;
+
+/// Somewhere keystrokes and mouse input can be sent
+pub trait Output: KeyboardControllable + MouseControllable + Send {}
+
+impl<T: KeyboardControllable + MouseControllable + Send> Output for T {}
@@ -0,0 +1,112 @@
+use super::{Key, KeyCombo, KeyboardControllable, MouseControllable, Stroke};
+use std::sync::{Arc, Mutex};
+
+/// Something an output backend was asked to emit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Emitted {
+    KeyDown(Stroke),
+    KeyUp(Stroke),
+    Text(String),
+    MouseDown(u8),
+    MouseUp(u8),
+    MouseClick(u8),
+    MouseMoveTo(i32, i32, u32),
+    MouseMoveRelative(i32, i32),
+}
+
+/// An output backend that records what it would emit, rather than emitting it.
+///
+/// Clones share the same record, so one can be handed to a `Dispatcher` and another inspected.
+#[derive(Clone, Default)]
+pub struct Recording {
+    emitted: Arc<Mutex<Vec<Emitted>>>,
+}
+
+impl Recording {
+    /// Everything recorded since last taken
+    pub fn take(&self) -> Vec<Emitted> {
+        self.emitted.lock().unwrap().drain(..).collect()
+    }
+
+    fn record(&self, emitted: Emitted) {
+        self.emitted.lock().unwrap().push(emitted);
+    }
+}
+
+impl KeyboardControllable for Recording {
+    fn key_sequence(&mut self, sequence: &str) {
+        self.record(Emitted::Text(sequence.to_string()));
+    }
+    fn key_down(&mut self, key: Key) {
+        self.record(Emitted::KeyDown(key.stroke()));
+    }
+    fn key_up(&mut self, key: Key) {
+        self.record(Emitted::KeyUp(key.stroke()));
+    }
+    fn key_click(&mut self, key: Key) {
+        self.key_down(key);
+        self.key_up(key);
+    }
+    fn combo_down(&mut self, combo: &KeyCombo) {
+        for stroke in combo.strokes() {
+            self.record(Emitted::KeyDown(stroke));
+        }
+    }
+    fn combo_up(&mut self, combo: &KeyCombo) {
+        for stroke in combo.strokes().into_iter().rev() {
+            self.record(Emitted::KeyUp(stroke));
+        }
+    }
+}
+
+impl MouseControllable for Recording {
+    fn mouse_move_to(&mut self, x: i32, y: i32, screen: u32) {
+        self.record(Emitted::MouseMoveTo(x, y, screen));
+    }
+    fn mouse_move_relative(&mut self, x: i32, y: i32) {
+        self.record(Emitted::MouseMoveRelative(x, y));
+    }
+    fn mouse_down(&mut self, button: u8) {
+        self.record(Emitted::MouseDown(button));
+    }
+    fn mouse_up(&mut self, button: u8) {
+        self.record(Emitted::MouseUp(button));
+    }
+    fn mouse_click(&mut self, button: u8) {
+        self.record(Emitted::MouseClick(button));
+    }
+}
+
+#[test]
+fn test_keycodes_and_keysyms() {
+    use std::str::FromStr;
+    let keysym = |name: &str| Stroke::Keysym(name.to_string());
+    let mut recording = Recording::default();
+    for combo in &["ctrl+keycode:0x38", "evdev:30", "super+AudioMute", "é"] {
+        let combo = KeyCombo::from_str(combo).unwrap();
+        recording.combo_down(&combo);
+        recording.combo_up(&combo);
+    }
+    recording.key_click(Key::Raw(0x38));
+    assert_eq!(
+        recording.take(),
+        vec![
+            Emitted::KeyDown(keysym("Control_L")),
+            Emitted::KeyDown(Stroke::Keycode(0x38)),
+            Emitted::KeyUp(Stroke::Keycode(0x38)),
+            Emitted::KeyUp(keysym("Control_L")),
+            // KEY_A is X keycode 38
+            Emitted::KeyDown(Stroke::Keycode(38)),
+            Emitted::KeyUp(Stroke::Keycode(38)),
+            Emitted::KeyDown(keysym("Super_L")),
+            Emitted::KeyDown(keysym("XF86AudioMute")),
+            Emitted::KeyUp(keysym("XF86AudioMute")),
+            Emitted::KeyUp(keysym("Super_L")),
+            Emitted::KeyDown(keysym("UE9")),
+            Emitted::KeyUp(keysym("UE9")),
+            Emitted::KeyDown(Stroke::Keycode(0x38)),
+            Emitted::KeyUp(Stroke::Keycode(0x38)),
+        ]
+    );
+    assert_eq!(recording.take(), vec![]);
+}